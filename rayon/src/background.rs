@@ -0,0 +1,113 @@
+use std::ops::ControlFlow;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::render::{FlockView, MAX_STEPS_PER_FRAME};
+
+// A change to the flocks from the window, run on the simulation thread before its next step
+pub type FlockCommand = Box<dyn FnOnce(&mut [FlockView]) + Send>;
+
+// Newest copy of the flocks the thread has published and the window not yet taken,
+// with when it was published
+type Published = Option<(Vec<FlockView>, Instant)>;
+
+// Flocks stepped on their own thread at a fixed rate, so a slow step doesn't hold up
+// drawing and a slow frame doesn't hold up the simulation. After each step the thread
+// publishes a copy of the flocks, which the window takes whole; the lock around it is
+// only held to swap a copy in or out, never across a step or a frame
+pub struct BackgroundSimulation {
+    published: Arc<Mutex<Published>>,
+    commands: Option<Sender<FlockCommand>>, // dropped to stop the thread
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundSimulation {
+    // Step `flocks` with `step` at `rate` steps per second, or as fast as they go at 0,
+    // until it breaks or this is dropped. A thread that falls more than
+    // MAX_STEPS_PER_FRAME steps behind gives up catching up, as the window does
+    pub fn spawn<F>(mut flocks: Vec<FlockView>, rate: f64, mut step: F) -> Self
+    where
+        F: FnMut(&mut [FlockView]) -> ControlFlow<()> + Send + 'static,
+    {
+        let published = Arc::new(Mutex::new(None));
+        let (commands, received): (Sender<FlockCommand>, Receiver<FlockCommand>) = mpsc::channel();
+
+        let thread = {
+            let published = Arc::clone(&published);
+            std::thread::spawn(move || {
+                let period = (rate > 0.0).then(|| Duration::from_secs_f64(1.0 / rate));
+                let mut next_step = Instant::now();
+                loop {
+                    // Everything the window sent since the last step, in the order it was sent
+                    loop {
+                        match received.try_recv() {
+                            Ok(command) => command(&mut flocks),
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => return,
+                        }
+                    }
+
+                    let flow = step(&mut flocks);
+                    let copy = flocks.iter().map(FlockView::snapshot).collect();
+                    *published.lock().unwrap() = Some((copy, Instant::now()));
+                    if flow.is_break() {
+                        return;
+                    }
+
+                    if let Some(period) = period {
+                        next_step += period;
+                        let now = Instant::now();
+                        if now > next_step && now - next_step > period * MAX_STEPS_PER_FRAME as u32 {
+                            next_step = now;
+                        }
+                        // Wait on the channel rather than sleeping, running commands as they
+                        // arrive and stopping as soon as the window goes, however slow the rate
+                        loop {
+                            let now = Instant::now();
+                            if now >= next_step {
+                                break;
+                            }
+                            match received.recv_timeout(next_step - now) {
+                                Ok(command) => command(&mut flocks),
+                                Err(RecvTimeoutError::Timeout) => break,
+                                Err(RecvTimeoutError::Disconnected) => return,
+                            }
+                        }
+                    }
+                }
+            })
+        };
+
+        BackgroundSimulation { published, commands: Some(commands), thread: Some(thread) }
+    }
+
+    // The newest copy of the flocks and when it was published, if there is one the
+    // window hasn't taken yet
+    pub fn take_published(&self) -> Published {
+        self.published.lock().unwrap().take()
+    }
+
+    // Whether the thread has stopped, having published its last step
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    pub fn send(&self, command: FlockCommand) {
+        if let Some(commands) = &self.commands {
+            // A thread that has finished no longer listens, and there is nothing left to change
+            let _ = commands.send(command);
+        }
+    }
+}
+
+impl Drop for BackgroundSimulation {
+    // Stop the thread after the step it is on, so it never outlives the window
+    fn drop(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
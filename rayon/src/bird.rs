@@ -0,0 +1,216 @@
+use nalgebra::{Rotation3, Unit, Vector3};
+use rand::Rng;
+
+use crate::params::{Distribution, SpawnConfig, TagRule, SPAWN_POSITIONS, SPAWN_VELOCITIES};
+use crate::real::Real;
+
+#[derive(Clone)]
+pub struct Bird {
+    pub position: Vector3<Real>,
+    pub velocity: Vector3<Real>,
+    pub acceleration: Vector3<Real>,
+    pub remembered_centre: Vector3<Real>, // moving average of the neighbor centre
+    pub startle: Real,                   // panic level, 0 = calm, 1 = fully startled
+    pub anchored: bool,                  // held in place, still seen by its neighbors
+    pub influence: Real,                 // weight in its neighbors' alignment and cohesion, 1 = ordinary
+    pub layer: u32,                      // horizontal layer when flocking in layers, bottom = 0
+    pub alone_steps: u32,                // steps since it last had a neighbor
+    pub age: u32,                        // steps since it joined the flock
+    pub tags: u32,                       // bitset the tag rules match against, see TagRule
+}
+
+impl Bird {
+    // Create a new bird with random position and velocity
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        Bird::sampled(&SPAWN_POSITIONS, &SPAWN_VELOCITIES, rng)
+    }
+
+    // Create a new bird as `new` does, in a world `world_scale` times the size
+    pub fn new_scaled<R: Rng>(world_scale: Real, rng: &mut R) -> Self {
+        Bird::sampled(&SPAWN_POSITIONS.scaled(world_scale), &SPAWN_VELOCITIES.scaled(world_scale), rng)
+    }
+
+    // Create a new bird at a given position with its velocity drawn from `velocities`
+    pub fn at_position<R: Rng>(position: Vector3<Real>, velocities: &Distribution, rng: &mut R) -> Self {
+        Bird::moving(position, velocities.sample(rng))
+    }
+
+    // Create a new bird with its position and velocity drawn from the given distributions
+    pub fn sampled<R: Rng>(positions: &Distribution, velocities: &Distribution, rng: &mut R) -> Self {
+        let position = positions.sample(rng);
+        Bird::moving(position, velocities.sample(rng))
+    }
+
+    fn moving(position: Vector3<Real>, velocity: Vector3<Real>) -> Self {
+        Bird {
+            position,
+            velocity,
+            acceleration: Vector3::zeros(),
+            remembered_centre: position,
+            startle: 0.0,
+            anchored: false,
+            influence: 1.0,
+            layer: 0,
+            alone_steps: 0,
+            age: 0,
+            tags: 0,
+        }
+    }
+
+    // Create a new bird somewhere within the spawn radius, flying directly away from its centre
+    pub fn spawned_from<R: Rng>(spawn: &SpawnConfig, rng: &mut R) -> Self {
+        let direction = random_unit_vector(rng);
+        let distance = spawn.radius * rng.random::<Real>().cbrt(); // uniform over the ball
+        let position = Vector3::from(spawn.position) + direction * distance;
+        Bird::moving(position, direction * spawn.speed)
+    }
+}
+
+impl Distribution {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Vector3<Real> {
+        match *self {
+            Distribution::Uniform { extent: 0.0 } => Vector3::zeros(), // an empty range would panic
+            Distribution::Uniform { extent } => Vector3::new(
+                rng.random_range(-extent..extent),
+                rng.random_range(-extent..extent),
+                rng.random_range(-extent..extent)
+            ),
+            Distribution::Normal { mean, stddev } => {
+                Vector3::from(mean) + Vector3::new(standard_normal(rng), standard_normal(rng), standard_normal(rng)) * stddev
+            }
+            Distribution::Shell { radius } => random_unit_vector(rng) * radius,
+        }
+    }
+}
+
+// Box-Muller, using one of the pair
+fn standard_normal<R: Rng>(rng: &mut R) -> Real {
+    let u: Real = 1.0 - rng.random::<Real>(); // (0, 1], so the log is finite
+    let v: Real = rng.random();
+    (-2.0 * u.ln()).sqrt() * (crate::real::consts::TAU * v).cos()
+}
+
+// Blend each bird's velocity towards a shared random heading, keeping its speed,
+// so `coherence` 0 leaves the velocities random and 1 starts every bird aligned
+pub fn bias_headings<R: Rng>(birds: &mut [Bird], coherence: Real, rng: &mut R) {
+    if coherence <= 0.0 {
+        return;
+    }
+    let heading = random_unit_vector(rng);
+    for bird in birds {
+        let speed = bird.velocity.norm();
+        bird.velocity = bird.velocity.lerp(&(heading * speed), coherence.min(1.0));
+    }
+}
+
+// Make a random `leader_fraction` of the birds leaders with `leader_influence`,
+// leaving the rest as ordinary followers
+pub fn assign_influence<R: Rng>(birds: &mut [Bird], leader_fraction: Real, leader_influence: Real, rng: &mut R) {
+    if leader_fraction <= 0.0 {
+        return;
+    }
+    for bird in birds {
+        if rng.random::<Real>() < leader_fraction {
+            bird.influence = leader_influence;
+        }
+    }
+}
+
+// Give each rule's tag to a random `fraction` of the birds, drawing nothing without rules
+pub fn assign_tags<R: Rng>(birds: &mut [Bird], rules: &[TagRule], rng: &mut R) {
+    for rule in rules.iter().filter(|rule| rule.fraction > 0.0) {
+        for bird in birds.iter_mut() {
+            if rng.random::<Real>() < rule.fraction {
+                bird.tags |= 1 << rule.tag;
+            }
+        }
+    }
+}
+
+pub fn limit_vec(v: Vector3<Real>, max: Real) -> Vector3<Real> {
+    if v.norm() > max {
+        v.normalize() * max
+    } else {
+        v
+    }
+}
+
+// `velocity` after `acceleration` for `dt`, turned by the part of the acceleration across
+// the heading and sped up or slowed by the part along it, up to `max_speed`. From a
+// standstill there is no heading to turn, so the acceleration is simply added
+pub fn turn_then_speed(velocity: Vector3<Real>, acceleration: Vector3<Real>, dt: Real, max_speed: Real) -> Vector3<Real> {
+    let speed = velocity.norm();
+    if speed == 0.0 {
+        return limit_vec(acceleration * dt, max_speed);
+    }
+    let heading = velocity / speed;
+    let along = acceleration.dot(&heading);
+    let across = acceleration - heading * along;
+    let turned = (velocity + across * dt).normalize();
+    turned * (speed + along * dt).clamp(0.0, max_speed)
+}
+
+// `to` rotated back towards `from` until the two are at most `max_angle` apart, keeping
+// its length, so a bird's heading turns through a smooth arc rather than kinking.
+// A reversal turns about an arbitrary axis perpendicular to `from`
+pub fn limit_turn(from: Vector3<Real>, to: Vector3<Real>, max_angle: Real) -> Vector3<Real> {
+    let (from_speed, speed) = (from.norm(), to.norm());
+    if from_speed == 0.0 || speed == 0.0 {
+        return to;
+    }
+    let angle = from.angle(&to);
+    if angle <= max_angle {
+        return to;
+    }
+    let axis = from.cross(&to);
+    let axis = if axis.norm() > Real::EPSILON * from_speed * speed {
+        axis
+    } else {
+        let other = if from.x.abs() < from.y.abs() { Vector3::x() } else { Vector3::y() };
+        from.cross(&other)
+    };
+    let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(axis), max_angle);
+    rotation * from / from_speed * speed
+}
+
+// Uniformly distributed direction, by rejection sampling the unit ball
+pub fn random_unit_vector<R: Rng>(rng: &mut R) -> Vector3<Real> {
+    loop {
+        let v: Vector3<Real> = Vector3::new(
+            rng.random_range(-1.0..1.0),
+            rng.random_range(-1.0..1.0),
+            rng.random_range(-1.0..1.0)
+        );
+        let norm = v.norm();
+        if norm > 0.0 && norm <= 1.0 {
+            return v / norm;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::golden::{same_bits, GOLDEN_BIRDS, GOLDEN_SEED};
+
+    #[test]
+    fn spawning_is_reproducible_from_the_seed() {
+        // Each one is drawn from for both the positions and the velocities
+        let distributions = [
+            Distribution::Uniform { extent: 5.0 },
+            Distribution::Normal { mean: [1.0, -2.0, 0.5], stddev: 2.0 },
+            Distribution::Shell { radius: 3.0 },
+        ];
+        for distribution in &distributions {
+            let spawn = || {
+                let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+                (0..GOLDEN_BIRDS).map(|_| Bird::sampled(distribution, distribution, &mut rng)).collect::<Vec<_>>()
+            };
+            let differing = spawn().iter().zip(&spawn()).filter(|(a, b)| !same_bits(a, b)).count();
+            assert_eq!(differing, 0, "{} birds spawned from {:?} differ between runs with the same seed", differing, distribution);
+        }
+    }
+}
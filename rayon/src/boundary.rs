@@ -0,0 +1,119 @@
+use nalgebra::Vector3;
+
+use crate::bird::Bird;
+use crate::flocking::steer;
+use crate::params::{Boundary, BoundaryMode, SimulationParams};
+use crate::real::Real;
+
+// Steering back towards the origin once a bird is within the margin of a soft sphere.
+// The bird's distance is projected along its outward speed over the lookahead time,
+// so one flying fast at the boundary turns earlier and harder than a slow one
+pub fn boundary_force(bird: &Bird, params: &SimulationParams) -> Vector3<Real> {
+    if params.boundary != Boundary::SoftSphere {
+        return Vector3::zeros();
+    }
+    let distance = bird.position.norm();
+    if distance == 0.0 {
+        return Vector3::zeros();
+    }
+    let outward_speed = bird.velocity.dot(&(bird.position / distance)).max(0.0);
+    let projected = distance + outward_speed * params.boundary_lookahead;
+    let start = params.boundary_radius - params.boundary_margin;
+    if projected <= start {
+        return Vector3::zeros();
+    }
+    // Stronger the further out the bird is or is heading, at least 1 once past the radius
+    let depth = (projected - start) / params.boundary_margin.max(Real::EPSILON);
+    steer(-bird.position, bird.velocity, params.max_speed, params.max_force) * depth
+}
+
+// Keep a bird inside the world after its position update: hold it to the box, or
+// mirror it back inside a hard sphere with the outward part of its velocity reversed.
+// A soft sphere only steers, so the box is kept as a backstop for strays
+pub fn contain(bird: &mut Bird, params: &SimulationParams) {
+    if params.boundary == Boundary::HardSphere {
+        let distance = bird.position.norm();
+        if distance > params.boundary_radius {
+            let normal = bird.position / distance;
+            bird.position = normal * (2.0 * params.boundary_radius - distance).max(0.0);
+            let outward = bird.velocity.dot(&normal);
+            if outward > 0.0 {
+                bird.velocity -= 2.0 * outward * normal;
+            }
+        }
+    }
+    contain_box(bird, &params.boundary_modes, params.extent);
+}
+
+// Wrap, bounce or clamp the bird at the faces of the box spanning +/- `extent`, each
+// axis by its own mode. A coordinate that is no longer finite, e.g. after a blowup,
+// can't be wrapped or mirrored and is put back at the centre of the box
+pub fn contain_box(bird: &mut Bird, modes: &[BoundaryMode; 3], extent: Real) {
+    let (space_min, space_max) = (-extent, extent);
+    for (i, mode) in modes.iter().enumerate() {
+        let p = bird.position[i];
+        if (space_min..=space_max).contains(&p) {
+            continue;
+        }
+        if !p.is_finite() {
+            bird.position[i] = 0.0;
+            continue;
+        }
+        let past_max = p > space_max;
+        match mode {
+            BoundaryMode::Wrap => bird.position[i] = wrap(p, space_min, space_max),
+            BoundaryMode::Bounce => {
+                let mirrored = if past_max { 2.0 * space_max - p } else { 2.0 * space_min - p };
+                bird.position[i] = mirrored.clamp(space_min, space_max);
+                bird.velocity[i] = if past_max { -bird.velocity[i].abs() } else { bird.velocity[i].abs() };
+            }
+            BoundaryMode::Clamp => {
+                bird.position[i] = p.clamp(space_min, space_max);
+                bird.velocity[i] = if past_max { bird.velocity[i].min(0.0) } else { bird.velocity[i].max(0.0) };
+            }
+        }
+    }
+}
+
+// A finite `p` outside [min, max] brought back in through the opposite face, in one pass
+// however many box widths out it is: the float remainder is exact, so the distance past
+// the face reduces to less than one width, and only the final addition rounds. The
+// clamp keeps that rounding from landing a hair outside
+fn wrap(p: Real, min: Real, max: Real) -> Real {
+    let size = max - min;
+    let wrapped = if p > max { min + (p - max) % size } else { max - (min - p) % size };
+    wrapped.clamp(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::golden::GOLDEN_SEED;
+
+    #[test]
+    fn wrapping_brings_far_out_coordinates_back_inside() {
+        // Coordinates up to this many box widths either side of the space, and infinite and
+        // NaN ones, must all be wrapped back inside by a single pass
+        const WIDTHS: Real = 10000.0;
+        let extent = SimulationParams::default().extent;
+        let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+        let reach = WIDTHS * 2.0 * extent;
+        let mut values: Vec<Real> = (0..100000).map(|_| rng.random_range(-reach..reach)).collect();
+        values.extend([reach, -reach, Real::MAX, Real::MIN, Real::INFINITY, Real::NEG_INFINITY, Real::NAN]);
+        // Whole multiples of the width past each face, where rounding lands closest to a face
+        values.extend((1..=WIDTHS as i32).step_by(97).flat_map(|k| {
+            let offset = k as Real * 2.0 * extent;
+            [extent + offset, -extent - offset, extent + offset + Real::EPSILON, -extent - offset - Real::EPSILON]
+        }));
+
+        for value in values {
+            let mut bird = Bird::new(&mut rng);
+            bird.position = Vector3::repeat(value);
+            contain_box(&mut bird, &[BoundaryMode::Wrap; 3], extent);
+            assert!(bird.position.iter().all(|p| (-extent..=extent).contains(p)), "{} wrapped to {:?}", value, bird.position);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::metrics::FlockMetrics;
+use crate::params::SimulationParams;
+use crate::real::Real;
+
+// Changes a controller asks for before the next step, added to the current values
+#[derive(Clone, Copy, Default)]
+pub struct ParameterDeltas {
+    pub separation_weight: Real,
+    pub alignment_weight: Real,
+    pub cohesion_weight: Real,
+    pub perception_radius: Real,
+    pub max_speed: Real,
+    pub max_force: Real,
+}
+
+impl ParameterDeltas {
+    // `params` with the deltas added, weights kept from going negative
+    pub fn applied_to(&self, params: &SimulationParams) -> SimulationParams {
+        let mut changed = params.clone();
+        changed.separation_weight = (params.separation_weight + self.separation_weight).max(0.0);
+        changed.alignment_weight = (params.alignment_weight + self.alignment_weight).max(0.0);
+        changed.cohesion_weight = (params.cohesion_weight + self.cohesion_weight).max(0.0);
+        changed.perception_radius += self.perception_radius;
+        changed.max_speed += self.max_speed;
+        changed.max_force += self.max_force;
+        changed
+    }
+}
+
+// Closed-loop control: sees the flock's metrics and parameters before each step and
+// returns the changes to make, e.g. to hold the flock at a target order
+pub type Controller = Box<dyn FnMut(&FlockMetrics, &SimulationParams) -> ParameterDeltas + Send>;
+
+pub const ORDER_CONTROLLER_GAIN:         Real = 0.05; // cohesion weight change per step per unit of order error
+pub const ORDER_CONTROLLER_MAX_COHESION: Real = 8.0; // past this the flock is a tight, disordered ball
+
+// Proportional controller holding the order parameter near `target` through the
+// cohesion weight. Pulling birds together lowers order steadily, where alignment
+// stops helping past about twice its default and then breaks the flock into
+// clumps, so cohesion is the rule to steer. The weight is kept within
+// 0..=ORDER_CONTROLLER_MAX_COHESION so an unreachable target can't wind it up
+pub fn order_controller(target: Real, gain: Real) -> impl FnMut(&FlockMetrics, &SimulationParams) -> ParameterDeltas + Send {
+    move |metrics, params| {
+        let error = target - metrics.order_parameter;
+        let cohesion = (params.cohesion_weight - gain * error).clamp(0.0, ORDER_CONTROLLER_MAX_COHESION);
+        ParameterDeltas {
+            cohesion_weight: cohesion - params.cohesion_weight,
+            ..ParameterDeltas::default()
+        }
+    }
+}
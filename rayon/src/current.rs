@@ -0,0 +1,23 @@
+use nalgebra::Vector3;
+
+use crate::params::{Current, SimulationParams};
+use crate::real::Real;
+
+// Velocity of the ambient air at `position`, added to a bird's own velocity when it
+// moves. Unlike a gust this is not a force, so it carries birds without turning them
+pub fn current_at(position: Vector3<Real>, params: &SimulationParams) -> Vector3<Real> {
+    let strength = params.current_strength;
+    match params.current {
+        Current::Off => Vector3::zeros(),
+        Current::Drift => Vector3::from(params.current_direction).normalize() * strength,
+        // Rotation about the vertical axis, at `strength` on the faces of the space
+        Current::Vortex => Vector3::new(-position.z, 0.0, position.x) * (strength / params.extent),
+        // Arnold-Beltrami-Childress flow, divergence free so it swirls birds without
+        // bunching them, with cells `current_scale` across
+        Current::Curl => {
+            let k = crate::real::consts::TAU / params.current_scale;
+            let (x, y, z) = (position.x * k, position.y * k, position.z * k);
+            Vector3::new(z.sin() + y.cos(), x.sin() + z.cos(), y.sin() + x.cos()) * (strength / 2.0)
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use nalgebra::Vector3;
+
+use crate::real::Real;
+use crate::scene::Scene;
+
+// The sequence --demo and the D key run when the scene doesn't script its own
+pub const BUILT_IN_DEMO: &str = include_str!("../scenes/demo.scene");
+
+// Something a demo stage does as it begins
+#[derive(Clone, Debug)]
+pub enum DemoAction {
+    Weights(Real, Real, Real),        // separation, alignment, cohesion
+    Set(&'static str, String),        // a scene parameter, e.g. `centre_attraction`
+    Shockwave(Option<Vector3<Real>>), // at the flock's centroid unless given
+    Flash(Option<Vector3<Real>>),     // likewise
+    Gust,
+    Predators(usize),                 // add or remove predators to leave this many
+    Birds(usize),                     // add this many at the arrival point
+}
+
+#[derive(Clone)]
+pub struct DemoStage {
+    pub name: String,
+    pub steps: usize, // how long the stage runs before the next begins, at least 1
+    pub actions: Vec<DemoAction>,
+}
+
+// Stages run one after another, starting over after the last, so a demo can be left
+// running. It only changes parameters and sets off events, the flock does the rest
+#[derive(Clone)]
+pub struct Demo {
+    stages: Vec<DemoStage>,
+    stage: usize,
+    steps_left: usize, // in the current stage, 0 before the first has begun
+}
+
+impl Demo {
+    pub fn new(stages: Vec<DemoStage>) -> Self {
+        Demo { stages, stage: 0, steps_left: 0 }
+    }
+
+    pub fn built_in() -> Self {
+        Scene::parse(BUILT_IN_DEMO).ok().and_then(|scene| scene.demo).expect("the built-in demo is a valid scene")
+    }
+
+    pub fn stages(&self) -> &[DemoStage] {
+        &self.stages
+    }
+
+    // Count off one step, returning the stage that begins with it, if any
+    pub fn advance(&mut self) -> Option<&DemoStage> {
+        if self.stages.is_empty() {
+            return None;
+        }
+        if self.steps_left > 0 {
+            self.steps_left -= 1;
+            if self.steps_left > 0 {
+                return None;
+            }
+            self.stage = (self.stage + 1) % self.stages.len();
+        }
+        let stage = &self.stages[self.stage];
+        self.steps_left = stage.steps;
+        Some(stage)
+    }
+}
@@ -0,0 +1,107 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use crate::bird::Bird;
+use crate::params::SimulationParams;
+use crate::real::Real;
+use crate::simulation::Simulation;
+
+const MIRROR_TILE: usize = 64; // birds per side of the blocks the upper triangle is mirrored in
+
+// Every pairwise distance in a flock, computed once per step with each pair measured
+// once. Memory grows with the square of the flock, so this is for small flocks, where
+// the grid's bookkeeping costs more than the distances it saves
+pub struct DistanceMatrix {
+    count: usize,
+    distances: Vec<Real>, // row by row, mirrored across the diagonal
+}
+
+impl DistanceMatrix {
+    pub fn new(birds: &[Bird]) -> Self {
+        let count = birds.len();
+        let mut distances = vec![0.0; count * count];
+        distances.par_chunks_mut(count.max(1)).enumerate().for_each(|(i, row)| {
+            for j in (i + 1)..count {
+                row[j] = (birds[i].position - birds[j].position).norm();
+            }
+        });
+
+        // Mirror the upper triangle so each bird's row reads contiguously, a tile
+        // at a time to keep the column reads in cache
+        for tile_i in (0..count).step_by(MIRROR_TILE) {
+            for tile_j in (tile_i..count).step_by(MIRROR_TILE) {
+                for i in tile_i..(tile_i + MIRROR_TILE).min(count) {
+                    for j in tile_j.max(i + 1)..(tile_j + MIRROR_TILE).min(count) {
+                        distances[j * count + i] = distances[i * count + j];
+                    }
+                }
+            }
+        }
+        DistanceMatrix { count, distances }
+    }
+
+    pub fn distance(&self, i: usize, j: usize) -> Real {
+        self.distances[i * self.count + j]
+    }
+
+    // Distances from bird `i` to every bird in index order, itself included at 0
+    pub fn row(&self, i: usize) -> &[Real] {
+        &self.distances[i * self.count..(i + 1) * self.count]
+    }
+}
+
+// Step time of each neighbor search over a range of flock sizes, to find where the
+// distance matrix stops paying off against brute force and the grid. Each search is
+// timed from a fresh flock several times over after a few untimed steps, and the
+// quickest repeat kept, so a stray slow step or a cold cache doesn't move the crossover
+pub const NEIGHBOR_BENCHMARK_BIRDS:   [usize; 6] = [100, 250, 500, 1000, 2000, 4000];
+pub const NEIGHBOR_BENCHMARK_STEPS:   usize = 20; // timed, per repeat
+pub const NEIGHBOR_BENCHMARK_WARMUP:  usize = 5;  // untimed steps before each repeat
+pub const NEIGHBOR_BENCHMARK_REPEATS: usize = 3;
+
+pub struct NeighborBenchmark {
+    pub birds: usize,
+    pub brute_force: f64, // seconds per step
+    pub pairwise: f64,    // brute force over each pair once
+    pub grid: f64,
+    pub octree: f64,
+    pub matrix: f64,
+    pub matrix_matches: bool, // bit-identical to brute force
+}
+
+pub fn neighbor_benchmark() -> Vec<NeighborBenchmark> {
+    NEIGHBOR_BENCHMARK_BIRDS
+        .iter()
+        .map(|&count| {
+            let mut rng = StdRng::seed_from_u64(crate::golden::GOLDEN_SEED);
+            let birds: Vec<Bird> = (0..count).map(|_| Bird::new(&mut rng)).collect();
+            let run = |params: SimulationParams| {
+                let mut quickest = f64::INFINITY;
+                let mut finished = Vec::new();
+                for _ in 0..NEIGHBOR_BENCHMARK_REPEATS {
+                    let mut simulation = Simulation::new(params.clone(), birds.clone(), Vec::new());
+                    for _ in 0..NEIGHBOR_BENCHMARK_WARMUP {
+                        let _ = simulation.step();
+                    }
+                    let mut time = 0.0;
+                    for _ in 0..NEIGHBOR_BENCHMARK_STEPS {
+                        let _ = simulation.step();
+                        time += simulation.last_calc_time;
+                    }
+                    quickest = quickest.min(time / NEIGHBOR_BENCHMARK_STEPS as f64);
+                    finished = simulation.birds;
+                }
+                (quickest, finished)
+            };
+            let (brute_force, brute_birds) = run(SimulationParams { use_grid: false, ..SimulationParams::default() });
+            let (pairwise, _) = run(SimulationParams { use_grid: false, pairwise_forces: true, ..SimulationParams::default() });
+            let (grid, _) = run(SimulationParams { use_grid: true, ..SimulationParams::default() });
+            let (octree, _) = run(SimulationParams { use_octree: true, ..SimulationParams::default() });
+            let (matrix, matrix_birds) = run(SimulationParams { distance_matrix_birds: count, ..SimulationParams::default() });
+            let bits = |bird: &Bird| (bird.position.map(Real::to_bits), bird.velocity.map(Real::to_bits));
+            let matrix_matches = brute_birds.iter().zip(&matrix_birds).all(|(a, b)| bits(a) == bits(b));
+            NeighborBenchmark { birds: count, brute_force, pairwise, grid, octree, matrix, matrix_matches }
+        })
+        .collect()
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+// Everything that can go wrong setting up a simulation or its window, returned
+// rather than panicking so an embedding application can report it and carry on
+pub enum BirdflockError {
+    GlInit(String),                             // event loop, window, context or buffer creation
+    GlDraw(String),                             // a draw call the driver refused mid-frame
+    ShaderCompile { name: String, log: String }, // the driver's log for both GLSL versions tried
+    InvalidParams(String),
+    Io { path: String, error: std::io::Error },
+    Parse(String),                              // a scene, mesh, image or CSV that can't be understood
+    ThreadPool { threads: usize, error: rayon::ThreadPoolBuildError },
+}
+
+impl BirdflockError {
+    pub fn io(path: &str, error: std::io::Error) -> Self {
+        BirdflockError::Io { path: path.to_string(), error }
+    }
+
+    pub fn thread_pool(threads: usize, error: rayon::ThreadPoolBuildError) -> Self {
+        BirdflockError::ThreadPool { threads, error }
+    }
+}
+
+impl fmt::Display for BirdflockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BirdflockError::GlInit(message) => write!(f, "cannot initialise OpenGL: {}", message),
+            BirdflockError::GlDraw(message) => write!(f, "cannot draw: {}", message),
+            BirdflockError::ShaderCompile { name, log } => write!(f, "cannot compile the {} shaders\n{}", name, log),
+            BirdflockError::InvalidParams(message) => write!(f, "invalid parameters: {}", message),
+            BirdflockError::Io { path, error } => write!(f, "cannot access '{}': {}", path, error),
+            BirdflockError::Parse(message) => write!(f, "{}", message),
+            BirdflockError::ThreadPool { threads, error } => write!(f, "cannot run on {} threads: {}", threads, error),
+        }
+    }
+}
+
+// The message rather than the variant, so `main` returning an error prints something readable
+impl fmt::Debug for BirdflockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for BirdflockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BirdflockError::Io { error, .. } => Some(error),
+            BirdflockError::ThreadPool { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use crate::real::Real;
+
+// Something worth telling the user that happened during a step, recorded by the
+// simulation for the application to report as it sees fit
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepEvent {
+    Arrived { count: usize, birds: usize }, // birds is the flock size afterwards
+    ControllerRejected(String),              // why the controller's parameters were invalid
+    Dusk,
+    Dawn,
+    Layers(usize),
+    DemoStage { name: String, steps: usize, order_parameter: Real, birds: usize, predators: usize },
+    DemoSetSkipped { name: &'static str, value: String, reason: String },
+    Culled { caught: usize, stragglers: usize, left: usize },
+    Stalled { steps: usize, order_parameter: Real, mean_speed: Real },
+    Reseeded(usize),
+}
+
+impl fmt::Display for StepEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepEvent::Arrived { count, birds } => write!(f, "{} birds arrived, {} in the flock", count, birds),
+            StepEvent::ControllerRejected(reason) => write!(f, "ignoring the controller's changes, {}", reason),
+            StepEvent::Dusk => write!(f, "dusk, the flock heads to the roost"),
+            StepEvent::Dawn => write!(f, "dawn, the flock leaves the roost"),
+            StepEvent::Layers(count) => write!(f, "flocking in {} layers", count),
+            StepEvent::DemoStage { name, steps, order_parameter, birds, predators } => write!(
+                f,
+                "demo stage '{}' for {} steps (order parameter {:.4}, {} birds, {} predators)",
+                name, steps, order_parameter, birds, predators
+            ),
+            StepEvent::DemoSetSkipped { name, value, reason } => write!(f, "skipping the demo's `set {} {}`, {}", name, value, reason),
+            StepEvent::Culled { caught, stragglers, left } => write!(
+                f,
+                "removed {} birds ({} caught, {} stragglers), {} left",
+                caught + stragglers,
+                caught,
+                stragglers,
+                left
+            ),
+            StepEvent::Stalled { steps, order_parameter, mean_speed } => write!(
+                f,
+                "flock stalled for {} steps (order parameter {:.4}, mean speed {:.4})",
+                steps, order_parameter, mean_speed
+            ),
+            StepEvent::Reseeded(count) => write!(f, "reseeded {} birds", count),
+        }
+    }
+}
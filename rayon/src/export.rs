@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use birdflock::{Bird, BirdflockError, Real, Scene, Simulation};
+use glium::Surface;
+
+pub const EXPORT_ARROWS: bool = false;  // add a velocity line per bird
+pub const ARROW_LENGTH: Real = 4.0;     // arrow length per unit of velocity
+
+// Write the flock as an OBJ point cloud, one vertex per bird, to a timestamped file
+pub fn export_obj(birds: &[Bird]) -> Result<String, String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis()).unwrap_or(0);
+    let path = format!("flock_{}.obj", timestamp);
+    let write_error = |err: std::io::Error| format!("cannot write '{}': {}", path, err);
+
+    let mut file = BufWriter::new(File::create(&path).map_err(write_error)?);
+    writeln!(file, "# {} birds", birds.len()).map_err(write_error)?;
+    for bird in birds {
+        let p = bird.position;
+        writeln!(file, "v {} {} {}", p.x, p.y, p.z).map_err(write_error)?;
+    }
+
+    if EXPORT_ARROWS {
+        // Arrow tips follow the bird vertices, so bird i is vertex i + 1 and its tip i + 1 + n
+        for bird in birds {
+            let tip = bird.position + bird.velocity * ARROW_LENGTH;
+            writeln!(file, "v {} {} {}", tip.x, tip.y, tip.z).map_err(write_error)?;
+        }
+        for i in 1..=birds.len() {
+            writeln!(file, "l {} {}", i, i + birds.len()).map_err(write_error)?;
+        }
+    } else {
+        writeln!(file, "p {}", (1..=birds.len()).map(|i| i.to_string()).collect::<Vec<_>>().join(" "))
+            .map_err(write_error)?;
+    }
+
+    file.flush().map_err(write_error)?;
+    Ok(path)
+}
+
+// Save the flocks' current settings as a timestamped scene file that --scene can load
+pub fn export_scene(simulations: &[&Simulation]) -> Result<String, String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis()).unwrap_or(0);
+    let path = format!("scene_{}.scene", timestamp);
+    let text = format!("# Saved at step {}\n{}", simulations[0].step_index, Scene::capture(simulations).to_text());
+
+    // A scene that reads back differently would silently lose some of the tuning
+    let reloaded = Scene::parse(&text).map_err(|err| format!("saved scene does not load: {}", err))?;
+    if !text.ends_with(&reloaded.to_text()) {
+        return Err("saved scene does not read back unchanged".to_string());
+    }
+
+    std::fs::write(&path, text).map_err(|err| format!("cannot write '{}': {}", path, err))?;
+    Ok(path)
+}
+
+// Raw RGBA frames for ffmpeg, copied from the window into an offscreen texture of
+// the video size, e.g. `ffmpeg -f rawvideo -pix_fmt rgba -s 1280x720 -r 60 -i out.raw out.mp4`.
+// The path may be a named pipe to encode while running.
+pub struct VideoRecorder {
+    texture: glium::texture::Texture2d,
+    file: BufWriter<File>,
+    path: String,
+    pub frames: usize,
+}
+
+impl VideoRecorder {
+    pub fn create<F: glium::backend::Facade>(facade: &F, path: &str, (width, height): (u32, u32)) -> Result<Self, BirdflockError> {
+        let file = File::create(path).map_err(|error| BirdflockError::io(path, error))?;
+        let texture = glium::texture::Texture2d::empty(facade, width, height).map_err(|err| {
+            BirdflockError::GlInit(format!("cannot create a {}x{} video texture: {:?}", width, height, err))
+        })?;
+        Ok(VideoRecorder { texture, file: BufWriter::new(file), path: path.to_string(), frames: 0 })
+    }
+
+    // Append the contents of `source`, scaled to the video size if the window was resized
+    pub fn capture<S: Surface>(&mut self, source: &S) -> Result<(), String> {
+        source.fill(&self.texture.as_surface(), glium::uniforms::MagnifySamplerFilter::Linear);
+        let image: glium::texture::RawImage2d<u8> = self.texture.read();
+        let path = &self.path;
+        let write_error = |err: std::io::Error| format!("cannot write '{}': {}", path, err);
+
+        // GL rows run bottom to top, video rows top to bottom
+        let row_bytes = image.width as usize * 4;
+        for row in image.data.chunks(row_bytes).rev() {
+            self.file.write_all(row).map_err(write_error)?;
+        }
+        self.file.flush().map_err(write_error)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::bird::random_unit_vector;
+use crate::real::Real;
+
+// Expanding spherical wavefront that turns birds as it passes, like the waves that ripple
+// across a murmuration. Every bird on the front is steered round the same axis, so
+// neighbors turn together and the turn spreads outwards; the nudge is across each
+// bird's velocity, changing its direction rather than its speed
+#[derive(Clone)]
+pub struct Flash {
+    pub centre: Vector3<Real>,
+    pub axis: Vector3<Real>, // unit vector birds turn round
+    pub speed: Real,         // growth of the radius per unit time
+    pub strength: Real,      // turning acceleration on the front
+    pub width: Real,         // thickness of the front
+    pub lifetime: Real,
+    pub age: Real,
+}
+
+impl Flash {
+    // Flash from `centre` turning round a random axis
+    pub fn random<R: Rng>(rng: &mut R, centre: Vector3<Real>, speed: Real, strength: Real, width: Real, lifetime: Real) -> Self {
+        Flash { centre, axis: random_unit_vector(rng), speed, strength, width, lifetime, age: 0.0 }
+    }
+
+    pub fn radius(&self) -> Real {
+        self.speed * self.age
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.age >= self.lifetime
+    }
+
+    // Steering the front gives a bird at `position` flying at `velocity`, strongest at its middle
+    pub fn force_at(&self, position: Vector3<Real>, velocity: Vector3<Real>) -> Vector3<Real> {
+        let from_front = ((position - self.centre).norm() - self.radius()).abs();
+        if from_front > self.width {
+            return Vector3::zeros();
+        }
+        let turn = self.axis.cross(&velocity).try_normalize(0.0).unwrap_or_else(Vector3::zeros);
+        turn * self.strength * (1.0 - from_front / self.width)
+    }
+}
@@ -0,0 +1,394 @@
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::bird::{limit_vec, Bird};
+use crate::boundary::contain_box;
+use crate::params::{BoundaryMode, LayerParams, PredatorParams, SeparationShape, SimulationParams, DENSITY_CELL_SIZE, DIMENSIONS};
+use crate::real::Real;
+
+// Steering force turning `velocity` towards `desired`, zero if there is no desired direction
+pub fn steer(desired: Vector3<Real>, velocity: Vector3<Real>, max_speed: Real, max_force: Real) -> Vector3<Real> {
+    if desired.norm() > 0.0 {
+        limit_vec(desired.normalize() * max_speed - velocity, max_force)
+    } else {
+        Vector3::zeros()
+    }
+}
+
+// Chunks of rows `pairwise_neighbor_sums` splits the pairs into, fixed rather than
+// following the thread count so the order partial sums are merged in never changes
+pub const PAIRWISE_CHUNKS: usize = 16;
+
+// Sums over the neighbors of a bird, before averaging and steering
+#[derive(Clone)]
+pub struct NeighborSums {
+    pub separation: Vector3<Real>,
+    pub alignment: Vector3<Real>, // velocities weighted by influence
+    pub position: Vector3<Real>, // positions weighted by influence
+    pub influence: Real,         // total influence of the neighbors
+    pub count: usize,
+    pub nearest: Real, // distance to the closest neighbor, infinite without any
+    pub startle: Real, // highest startle among the neighbors
+}
+
+// Accumulate the neighbors of `bird` among `others` within `radius`
+pub fn neighbor_sums<'a>(bird: &Bird, others: impl IntoIterator<Item = &'a Bird>, radius: Real) -> NeighborSums {
+    shaped_neighbor_sums(bird, others, radius, None, None)
+}
+
+// As `neighbor_sums`, with each neighbor's separation scaled by `shape` for where it
+// is relative to the bird's heading. An isotropic shape is skipped, since steering only
+// follows the direction of the summed separation and a uniform scale leaves that alone.
+// With `layers`, neighbors in other layers count for less in all three rules
+pub fn shaped_neighbor_sums<'a>(
+    bird: &Bird,
+    others: impl IntoIterator<Item = &'a Bird>,
+    radius: Real,
+    shape: Option<&SeparationShape>,
+    layers: Option<&LayerParams>,
+) -> NeighborSums {
+    let position = bird.position;
+    let measured = others.into_iter().map(|other| (other, (position - other.position).norm()));
+    measured_neighbor_sums(bird, measured, radius, shape, layers)
+}
+
+// As `shaped_neighbor_sums`, for neighbors paired with their already known distance from `bird`
+pub fn measured_neighbor_sums<'a>(
+    bird: &Bird,
+    others: impl IntoIterator<Item = (&'a Bird, Real)>,
+    radius: Real,
+    shape: Option<&SeparationShape>,
+    layers: Option<&LayerParams>,
+) -> NeighborSums {
+    let shape = shape.filter(|shape| !shape.is_isotropic());
+    let layers = layers.filter(|layers| layers.count > 1);
+    let speed = bird.velocity.norm();
+    let heading = if speed > 0.0 { bird.velocity / speed } else { Vector3::zeros() };
+    let mut sums = NeighborSums::empty();
+
+    for (other, distance) in others {
+        if distance > 0.0 && distance < radius {
+
+            let coupling = match layers {
+                Some(layers) => layers.coupling_between(bird.layer, other.layer),
+                None => 1.0,
+            };
+            let away = (bird.position - other.position) / distance;
+            let away = match shape {
+                Some(shape) => away * shape.weight(-away.dot(&heading)),
+                None => away,
+            };
+            sums.add(other, away, distance, coupling);
+        }
+    }
+    sums
+}
+
+// Brute-force neighbor sums for every bird, measuring each pair once and giving both
+// birds of a pair its equal and opposite separation, by Newton's third law. Each chunk
+// of rows sums into its own partial sums, merged bird by bird in chunk order, so the
+// result is the same for any thread count. For an isotropic separation shape only, as
+// a shaped one weighs the two sides of a pair by their own headings
+pub fn pairwise_neighbor_sums(birds: &[Bird], radius: Real, layers: Option<&LayerParams>) -> Vec<NeighborSums> {
+    let layers = layers.filter(|layers| layers.count > 1);
+    let count = birds.len();
+    let chunks: Vec<(usize, usize)> = pair_chunks(count).windows(2).map(|rows| (rows[0], rows[1])).collect();
+
+    // Rows from `first` pair only with later birds, so a chunk's partials start there
+    let partials: Vec<(usize, Vec<NeighborSums>)> = chunks
+        .into_par_iter()
+        .map(|(first, end)| {
+            let mut sums = vec![NeighborSums::empty(); count - first];
+            for i in first..end {
+                for j in i + 1..count {
+                    let offset = birds[i].position - birds[j].position;
+                    let distance = offset.norm();
+                    if distance > 0.0 && distance < radius {
+                        let coupling = match layers {
+                            Some(layers) => layers.coupling_between(birds[i].layer, birds[j].layer),
+                            None => 1.0,
+                        };
+                        let away = offset / distance;
+                        sums[i - first].add(&birds[j], away, distance, coupling);
+                        sums[j - first].add(&birds[i], -away, distance, coupling);
+                    }
+                }
+            }
+            (first, sums)
+        })
+        .collect();
+
+    (0..count)
+        .into_par_iter()
+        .map(|i| {
+            let mut sums = NeighborSums::empty();
+            for (first, partial) in partials.iter().filter(|(first, _)| *first <= i) {
+                sums.merge(&partial[i - first]);
+            }
+            sums
+        })
+        .collect()
+}
+
+// Row boundaries splitting the pairs of `count` birds into PAIRWISE_CHUNKS runs of
+// rows with about as many pairs each, the early rows being the long ones
+fn pair_chunks(count: usize) -> Vec<usize> {
+    let total = count * count.saturating_sub(1) / 2;
+    let mut bounds = vec![0];
+    let mut pairs = 0;
+    for row in 0..count {
+        pairs += count - 1 - row;
+        if bounds.len() < PAIRWISE_CHUNKS && pairs * PAIRWISE_CHUNKS >= total * bounds.len() {
+            bounds.push(row + 1);
+        }
+    }
+    if bounds.last() != Some(&count) {
+        bounds.push(count);
+    }
+    bounds
+}
+
+// Debug check: largest difference between pairwise and per-bird brute-force sums,
+// relative to the size of the sums, or infinite if any neighbor counts differ
+pub fn check_pairwise_sums(birds: &[Bird], pairwise: &[NeighborSums], radius: Real, layers: Option<&LayerParams>) -> Real {
+    let mut worst: Real = 0.0;
+    for (bird, pair) in birds.iter().zip(pairwise) {
+        let single = shaped_neighbor_sums(bird, birds, radius, None, layers);
+        if single.count != pair.count {
+            return Real::INFINITY;
+        }
+        for (a, b) in [(single.separation, pair.separation), (single.alignment, pair.alignment), (single.position, pair.position)] {
+            worst = worst.max((a - b).norm() / a.norm().max(1.0));
+        }
+        worst = worst.max((single.influence - pair.influence).abs() / single.influence.max(1.0));
+    }
+    worst
+}
+
+impl NeighborSums {
+    pub fn empty() -> Self {
+        NeighborSums {
+            separation: Vector3::zeros(),
+            alignment: Vector3::zeros(),
+            position: Vector3::zeros(),
+            influence: 0.0,
+            count: 0,
+            nearest: Real::INFINITY,
+            startle: 0.0,
+        }
+    }
+
+    // Count `other` as a neighbor `distance` away, `away` being the separation it
+    // contributes before the layer coupling
+    pub fn add(&mut self, other: &Bird, away: Vector3<Real>, distance: Real, coupling: Real) {
+        self.separation += away * coupling;
+        let influence = other.influence * coupling;
+        self.alignment += other.velocity * influence;
+        self.position += other.position * influence;
+        self.influence += influence;
+        self.startle = self.startle.max(other.startle);
+
+        self.count += 1;
+        self.nearest = self.nearest.min(distance);
+    }
+
+    // Combine the sums over two separate sets of neighbors
+    pub fn merge(&mut self, other: &NeighborSums) {
+        self.separation += other.separation;
+        self.alignment += other.alignment;
+        self.position += other.position;
+        self.influence += other.influence;
+        self.startle = self.startle.max(other.startle);
+        self.count += other.count;
+        self.nearest = self.nearest.min(other.nearest);
+    }
+
+    // Influence-weighted mean neighbor position, if any neighbors have influence
+    pub fn centre(&self) -> Option<Vector3<Real>> {
+        if self.influence > 0.0 {
+            Some(self.position / self.influence)
+        } else {
+            None
+        }
+    }
+
+    // Where the neighbor centre will be `lookahead` time from now if it moves at the
+    // neighbors' mean velocity, so cohesion leads a turning flock rather than trailing it
+    pub fn predicted_centre(&self, lookahead: Real) -> Option<Vector3<Real>> {
+        let centre = self.centre()?;
+        if lookahead > 0.0 {
+            Some(centre + self.alignment / self.influence * lookahead)
+        } else {
+            Some(centre)
+        }
+    }
+
+    // Radial push away from the neighbor centre when there are more than `target`
+    // neighbors and towards it when there are fewer, in proportion to the excess or
+    // shortfall relative to `target` and reaching `max_force` at double or none.
+    // A bird with no neighbors has no centre to move towards
+    pub fn comfort(&self, bird: &Bird, target: Real, max_force: Real) -> Vector3<Real> {
+        let Some(centre) = self.centre() else { return Vector3::zeros() };
+        let outward = bird.position - centre;
+        let distance = outward.norm();
+        if target <= 0.0 || distance == 0.0 {
+            return Vector3::zeros();
+        }
+        let excess = ((self.count as Real - target) / target).clamp(-1.0, 1.0);
+        outward / distance * excess * max_force
+    }
+
+    // Separation, alignment and cohesion steering, cohering towards `centre` unless
+    // already within `dead_zone` of it
+    pub fn steering(
+        &self,
+        bird: &Bird,
+        centre: Vector3<Real>,
+        dead_zone: Real,
+        max_speed: Real,
+        max_force: Real,
+    ) -> (Vector3<Real>, Vector3<Real>, Vector3<Real>) {
+        if self.count == 0 {
+            return (Vector3::zeros(), Vector3::zeros(), Vector3::zeros());
+        }
+
+        // Separation
+        let separation = steer(self.separation / self.count as Real, bird.velocity, max_speed, max_force);
+
+        // Alignment, towards the heading of the most influential neighbors
+        let alignment = if self.influence > 0.0 {
+            steer(self.alignment / self.influence, bird.velocity, max_speed, max_force)
+        } else {
+            Vector3::zeros()
+        };
+
+        // Cohesion
+        let to_centre = centre - bird.position;
+        let cohesion = if to_centre.norm() > dead_zone {
+            steer(to_centre, bird.velocity, max_speed, max_force)
+        } else {
+            Vector3::zeros()
+        };
+
+        (separation, alignment, cohesion)
+    }
+}
+
+// Separation, alignment and cohesion steering of `bird` relative to `others` within `radius`
+pub fn flocking_forces<'a>(
+    bird: &Bird,
+    others: impl IntoIterator<Item = &'a Bird>,
+    radius: Real,
+    max_speed: Real,
+    max_force: Real,
+) -> (Vector3<Real>, Vector3<Real>, Vector3<Real>) {
+    let sums = neighbor_sums(bird, others, radius);
+    match sums.centre() {
+        Some(centre) => sums.steering(bird, centre, 0.0, max_speed, max_force),
+        None => (Vector3::zeros(), Vector3::zeros(), Vector3::zeros()),
+    }
+}
+
+// Steering away from any predators within the flee radius
+pub fn flee_force(bird: &Bird, predators: &[Bird], params: &SimulationParams) -> Vector3<Real> {
+    let mut away = Vector3::zeros();
+    for predator in predators {
+        let offset = bird.position - predator.position;
+        let distance = offset.norm();
+        if distance > 0.0 && distance < params.flee_radius {
+            away += offset / distance;
+        }
+    }
+    steer(away, bird.velocity, params.max_speed, params.max_force)
+}
+
+// Centre of the most populated cell of a coarse density grid over the space spanning
+// +/- `extent`, with as many cells as DENSITY_CELL_SIZE gives the unscaled space
+pub fn densest_region(birds: &[Bird], extent: Real) -> Vector3<Real> {
+    let cells = (2.0 * DIMENSIONS / DENSITY_CELL_SIZE).ceil() as usize;
+    let cell_size = 2.0 * extent / cells as Real;
+    let cell_of = |v: Real| (((v + extent) / cell_size) as usize).min(cells - 1);
+
+    let mut counts = vec![0u32; cells * cells * cells];
+    for bird in birds {
+        let p = bird.position;
+        counts[(cell_of(p.x) * cells + cell_of(p.y)) * cells + cell_of(p.z)] += 1;
+    }
+
+    let (densest, _) = counts.iter().enumerate().max_by_key(|&(_, count)| *count).unwrap();
+    let cell_centre = |cell: usize| -extent + (cell as Real + 0.5) * cell_size;
+    Vector3::new(
+        cell_centre(densest / (cells * cells)),
+        cell_centre(densest / cells % cells),
+        cell_centre(densest % cells)
+    )
+}
+
+// Move the predator pack: flock among themselves and chase the densest prey
+pub fn update_predators(predators: &mut [Bird], birds: &[Bird], params: &PredatorParams, modes: &[BoundaryMode; 3], extent: Real, dt: Real) {
+    if predators.is_empty() {
+        return;
+    }
+
+    let predators_snapshot = predators.to_vec();
+    let target = densest_region(birds, extent);
+
+    for predator in predators.iter_mut() {
+        let (separation, alignment, cohesion) = flocking_forces(
+            predator,
+            &predators_snapshot,
+            params.perception_radius,
+            params.max_speed,
+            params.max_force,
+        );
+        let chase = steer(target - predator.position, predator.velocity, params.max_speed, params.max_force);
+
+        predator.acceleration =
+            params.separation_weight * separation +
+            params.alignment_weight * alignment +
+            params.cohesion_weight * cohesion +
+            params.chase_weight * chase;
+
+        predator.velocity += predator.acceleration * dt;
+        predator.velocity = limit_vec(predator.velocity, params.max_speed);
+        predator.position += predator.velocity * dt;
+        contain_box(predator, modes, extent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::BirdflockError;
+    use crate::golden::{golden_run, run_on, DETERMINISM_THREADS};
+
+    // Relative difference allowed between pairwise and per-bird sums, no more than
+    // rounding from the summation order
+    const PAIRWISE_TOLERANCE: Real = 1e-5;
+
+    #[test]
+    fn pairwise_sums_match_per_bird_sums() {
+        let birds = golden_run();
+        let radius = SimulationParams::default().perception_radius;
+        let pairwise = pairwise_neighbor_sums(&birds, radius, None);
+        let difference = check_pairwise_sums(&birds, &pairwise, radius, None);
+        assert!(difference <= PAIRWISE_TOLERANCE, "pairwise sums differ by {:e}", difference);
+    }
+
+    #[test]
+    fn pairwise_sums_do_not_depend_on_the_thread_count() -> Result<(), BirdflockError> {
+        let birds = golden_run();
+        let radius = SimulationParams::default().perception_radius;
+        let sums_on = |threads: usize| run_on(threads, || pairwise_neighbor_sums(&birds, radius, None));
+        let bits = |sums: &NeighborSums| {
+            (sums.separation.map(Real::to_bits), sums.alignment.map(Real::to_bits), sums.position.map(Real::to_bits), sums.influence.to_bits())
+        };
+
+        let reference = sums_on(DETERMINISM_THREADS[0])?;
+        for &threads in &DETERMINISM_THREADS[1..] {
+            let differing = reference.iter().zip(&sums_on(threads)?).filter(|(a, b)| bits(a) != bits(b)).count();
+            assert_eq!(differing, 0, "{} birds' pairwise sums on {} threads differ from {} thread", differing, threads, DETERMINISM_THREADS[0]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fmt::Write;
+
+use crate::bird::Bird;
+#[cfg(test)]
+use crate::error::BirdflockError;
+use crate::params::SimulationParams;
+#[cfg(test)]
+use crate::real::Real;
+use crate::simulation::Simulation;
+
+// Small fixed-seed run whose final positions the tests compare against a checked-in CSV,
+// to catch accidental changes to the flocking maths. The CSV is for the default f32
+// build; the `f64` feature draws a different flock from the same seed
+pub(crate) const GOLDEN_SEED:  u64 = 42;
+pub(crate) const GOLDEN_BIRDS: usize = 200;
+const GOLDEN_STEPS:            usize = 100;
+
+// Final bird positions of the golden run
+pub fn golden_run() -> Vec<Bird> {
+    let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+    let birds = (0..GOLDEN_BIRDS).map(|_| Bird::new(&mut rng)).collect();
+    let mut simulation = Simulation::new(SimulationParams::default(), birds, Vec::new());
+    for _ in 0..GOLDEN_STEPS {
+        let _ = simulation.step();
+    }
+    simulation.birds
+}
+
+// Fixed-seed run repeated on thread pools of these sizes, which must agree bit for bit.
+// A few predators, startle, alignment noise and a speed floor bring more of the step into play than the golden run
+#[cfg(test)]
+pub(crate) const DETERMINISM_THREADS: [usize; 3] = [1, 2, 7];
+
+#[cfg(test)]
+pub(crate) fn determinism_run(world_scale: Real, chunk_size: usize) -> Vec<Bird> {
+    let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+    let birds = (0..GOLDEN_BIRDS).map(|_| Bird::new_scaled(world_scale, &mut rng)).collect();
+    let predators = (0..3).map(|_| Bird::new_scaled(world_scale, &mut rng)).collect();
+    let params = SimulationParams { startle_radius: 2.0, min_speed: 0.02, alignment_noise: 0.2, chunk_size, ..SimulationParams::default() }
+        .scaled(world_scale);
+    let mut simulation = Simulation::new(params, birds, predators);
+    for _ in 0..GOLDEN_STEPS {
+        let _ = simulation.step();
+    }
+    simulation.birds
+}
+
+// Whether two birds have the same position and velocity, bit for bit
+#[cfg(test)]
+pub(crate) fn same_bits(a: &Bird, b: &Bird) -> bool {
+    a.position.map(Real::to_bits) == b.position.map(Real::to_bits) && a.velocity.map(Real::to_bits) == b.velocity.map(Real::to_bits)
+}
+
+// `run` on a thread pool of its own with `threads` threads
+#[cfg(test)]
+pub(crate) fn run_on<T: Send, F: FnOnce() -> T + Send>(threads: usize, run: F) -> Result<T, BirdflockError> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|error| BirdflockError::thread_pool(threads, error))?;
+    Ok(pool.install(run))
+}
+
+pub fn positions_csv(birds: &[Bird]) -> String {
+    let mut csv = String::from("x,y,z\n");
+    for bird in birds {
+        let p = bird.position;
+        writeln!(csv, "{:.6},{:.6},{:.6}", p.x, p.y, p.z).unwrap();
+    }
+    csv
+}
+
+// Largest per-axis difference between `birds` and the positions in `csv`
+#[cfg(all(test, not(feature = "f64")))]
+fn compare_positions_csv(birds: &[Bird], csv: &str) -> Result<Real, BirdflockError> {
+    let rows: Vec<&str> = csv.lines().skip(1).filter(|line| !line.trim().is_empty()).collect();
+    if rows.len() != birds.len() {
+        return Err(BirdflockError::Parse(format!("golden has {} birds, run has {}", rows.len(), birds.len())));
+    }
+
+    let mut worst: Real = 0.0;
+    for (number, (row, bird)) in rows.iter().zip(birds).enumerate() {
+        let values: Vec<Real> = row
+            .split(',')
+            .map(|value| value.trim().parse::<Real>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| BirdflockError::Parse(format!("line {}: invalid row '{}'", number + 2, row)))?;
+        if values.len() != 3 {
+            return Err(BirdflockError::Parse(format!("line {}: expected x,y,z", number + 2)));
+        }
+        for (value, position) in values.iter().zip(bird.position.iter()) {
+            worst = worst.max((value - position).abs());
+        }
+    }
+    Ok(worst)
+}
+
+// The checked-in positions are from the f32 build; see GOLDEN_SEED
+#[cfg(all(test, not(feature = "f64")))]
+mod tests {
+    use super::*;
+
+    // Regenerate with `--write-golden golden/positions.csv` after an intended change
+    const GOLDEN_POSITIONS: &str = include_str!("../golden/positions.csv");
+    const GOLDEN_TOLERANCE: Real = 1e-3;
+
+    #[test]
+    fn golden_run_matches_the_checked_in_positions() -> Result<(), BirdflockError> {
+        let difference = compare_positions_csv(&golden_run(), GOLDEN_POSITIONS)?;
+        assert!(difference <= GOLDEN_TOLERANCE, "golden run differs by up to {:.3e}", difference);
+        Ok(())
+    }
+}
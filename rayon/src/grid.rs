@@ -0,0 +1,461 @@
+use nalgebra::Vector3;
+
+use crate::bird::Bird;
+use crate::flocking::{flocking_forces, shaped_neighbor_sums, NeighborSums};
+use crate::params::SimulationParams;
+use crate::real::Real;
+
+const MAX_GRID_CELLS: usize = 64; // per axis, so tiny radii don't allocate huge grids
+
+pub const GRID_ANALYSIS_SAMPLES: usize = 1000;                      // birds sampled by analyse_grid
+pub const GRID_ANALYSIS_SCALES: [Real; 5] = [1.0, 1.5, 2.0, 3.0, 4.0]; // candidate cell sizes, in perception radii
+
+// Uniform grid over the simulation space, +/- `extent` on each axis, with cells at
+// least `radius` wide, so every neighbor of a bird lies in its own cell or one of
+// the 26 around it
+pub struct SpatialGrid {
+    space_min: Real,
+    cells: usize,
+    cell_size: Real,
+    cell_start: Vec<usize>, // indices[cell_start[c]..cell_start[c + 1]] are the birds in cell c
+    indices: Vec<usize>,
+}
+
+impl SpatialGrid {
+    pub fn new(birds: &[Bird], radius: Real, extent: Real) -> Self {
+        let width = 2.0 * extent;
+        let cells = ((width / radius) as usize).clamp(1, MAX_GRID_CELLS);
+        let mut grid = SpatialGrid {
+            space_min: -extent,
+            cells,
+            cell_size: width / cells as Real,
+            cell_start: vec![0; cells * cells * cells + 1],
+            indices: vec![0; birds.len()],
+        };
+
+        // Counting sort by cell, keeping bird order within each cell
+        let bird_cells: Vec<usize> = birds.iter().map(|bird| grid.cell_index(grid.cell_of(bird.position))).collect();
+        for &cell in &bird_cells {
+            grid.cell_start[cell + 1] += 1;
+        }
+        for cell in 0..cells * cells * cells {
+            grid.cell_start[cell + 1] += grid.cell_start[cell];
+        }
+        let mut next = grid.cell_start.clone();
+        for (i, &cell) in bird_cells.iter().enumerate() {
+            grid.indices[next[cell]] = i;
+            next[cell] += 1;
+        }
+        grid
+    }
+
+    // Cell coordinates of a position, birds outside the space go in the edge cells
+    pub fn cell_of(&self, position: Vector3<Real>) -> [usize; 3] {
+        let axis = |v: Real| (((v - self.space_min) / self.cell_size).max(0.0) as usize).min(self.cells - 1);
+        [axis(position.x), axis(position.y), axis(position.z)]
+    }
+
+    fn cell_index(&self, [x, y, z]: [usize; 3]) -> usize {
+        (x * self.cells + y) * self.cells + z
+    }
+
+    // Birds in a single cell
+    pub fn cell(&self, cell: [usize; 3]) -> &[usize] {
+        let index = self.cell_index(cell);
+        &self.indices[self.cell_start[index]..self.cell_start[index + 1]]
+    }
+
+    // Indices of the `k` birds nearest to bird `index`, closest first, searching
+    // shells of cells outwards until no unsearched cell can hold anything closer
+    pub fn k_nearest(&self, birds: &[Bird], index: usize, k: usize) -> Vec<usize> {
+        let position = birds[index].position;
+        let centre = self.cell_of(position);
+        let mut found: Vec<(Real, usize)> = Vec::new();
+
+        for ring in 0..self.cells {
+            let range = |c: usize| c.saturating_sub(ring)..=(c + ring).min(self.cells - 1);
+            for cx in range(centre[0]) {
+                for cy in range(centre[1]) {
+                    for cz in range(centre[2]) {
+                        let shell = cx.abs_diff(centre[0]).max(cy.abs_diff(centre[1])).max(cz.abs_diff(centre[2]));
+                        if shell != ring {
+                            continue;
+                        }
+                        for &i in self.cell([cx, cy, cz]) {
+                            if i != index {
+                                found.push(((birds[i].position - position).norm(), i));
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Anything outside the searched block is at least `ring` cells away
+            if k == 0 {
+                break;
+            }
+            if found.len() >= k {
+                let (_, kth, _) = found.select_nth_unstable_by(k - 1, |a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                if kth.0 <= ring as Real * self.cell_size {
+                    break;
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        found.into_iter().take(k).map(|(_, i)| i).collect()
+    }
+
+    pub fn cell_size(&self) -> Real {
+        self.cell_size
+    }
+
+    // Most birds in any one cell
+    pub fn max_cell_count(&self) -> usize {
+        self.cell_start.windows(2).map(|pair| pair[1] - pair[0]).max().unwrap_or(0)
+    }
+
+    // The 3x3x3 block of cells around `position`, fewer at the edges of the space
+    pub fn block(&self, position: Vector3<Real>) -> impl Iterator<Item = [usize; 3]> + '_ {
+        let [x, y, z] = self.cell_of(position);
+        let range = move |c: usize| c.saturating_sub(1)..(c + 2).min(self.cells);
+        range(x).flat_map(move |cx| range(y).flat_map(move |cy| range(z).map(move |cz| [cx, cy, cz])))
+    }
+
+    // Indices of all birds in the 3x3x3 block of cells around `position`
+    pub fn candidates(&self, position: Vector3<Real>) -> impl Iterator<Item = usize> + '_ {
+        self.block(position).flat_map(move |cell| self.cell(cell).iter().copied())
+    }
+
+    // Every cell that could hold a point within `radius` of `position`, the cube of cells
+    // around it, fewer at the edges of the space
+    pub fn cells_around(&self, position: Vector3<Real>, radius: Real) -> impl Iterator<Item = [usize; 3]> + '_ {
+        let low = self.cell_of(position.add_scalar(-radius));
+        let high = self.cell_of(position.add_scalar(radius));
+        (low[0]..=high[0]).flat_map(move |cx| (low[1]..=high[1]).flat_map(move |cy| (low[2]..=high[2]).map(move |cz| [cx, cy, cz])))
+    }
+
+    // Distance from `position` to the nearest point of a cell, 0 inside it
+    pub fn cell_distance(&self, position: Vector3<Real>, cell: [usize; 3]) -> Real {
+        let mut squared: Real = 0.0;
+        for axis in 0..3 {
+            let low = self.space_min + cell[axis] as Real * self.cell_size;
+            let outside = (low - position[axis]).max(position[axis] - (low + self.cell_size)).max(0.0);
+            squared += outside * outside;
+        }
+        squared.sqrt()
+    }
+
+    // Distance from `position` to the furthest point of a cell
+    pub fn cell_reach(&self, position: Vector3<Real>, cell: [usize; 3]) -> Real {
+        let mut squared: Real = 0.0;
+        for axis in 0..3 {
+            let low = self.space_min + cell[axis] as Real * self.cell_size;
+            let across = (position[axis] - low).abs().max((low + self.cell_size - position[axis]).abs());
+            squared += across * across;
+        }
+        squared.sqrt()
+    }
+
+    // Totals over the birds of every cell, indexed like the grid
+    pub fn summaries(&self, birds: &[Bird]) -> Vec<CellSummary> {
+        self.cell_start
+            .windows(2)
+            .map(|pair| {
+                let mut summary = CellSummary { count: pair[1] - pair[0], ..CellSummary::default() };
+                for &i in &self.indices[pair[0]..pair[1]] {
+                    let bird = &birds[i];
+                    summary.position += bird.position;
+                    summary.velocity += bird.velocity * bird.influence;
+                    summary.weighted_position += bird.position * bird.influence;
+                    summary.influence += bird.influence;
+                    summary.startle = summary.startle.max(birds[i].startle);
+                }
+                summary
+            })
+            .collect()
+    }
+
+    // Neighbor sums where cells further than the approximation distance from the bird,
+    // and wholly within its perception radius, stand in for their birds with their
+    // centroid and totals, on a grid built with cells a fraction of that distance wide
+    // (see APPROXIMATION_CELL_FRACTION). Every bird of such a cell is a neighbor, so
+    // alignment and cohesion stay exact and only the direction of separation from its
+    // birds is approximated. Nearer cells, and those the radius cuts through, are summed
+    // bird by bird, as are the cells at the edges of the space, which also hold any bird
+    // outside it. Layer coupling and the nearest distance only count the birds summed
+    // one by one, the summaries counting every layer alike
+    pub fn approximate_neighbor_sums(
+        &self,
+        bird: &Bird,
+        birds: &[Bird],
+        summaries: &[CellSummary],
+        params: &SimulationParams,
+    ) -> NeighborSums {
+        let radius = params.perception_radius * params.tag_scales(bird.tags).perception;
+        let threshold = params.approximation_distance;
+        let shape = &params.separation_shape;
+        let inner = 1..self.cells - 1;
+        let (summarised, summed): (Vec<[usize; 3]>, Vec<[usize; 3]>) = self
+            .cells_around(bird.position, radius)
+            .filter(|&cell| self.cell_distance(bird.position, cell) < radius)
+            .partition(|&cell| {
+                self.cell_distance(bird.position, cell) > threshold
+                    && self.cell_reach(bird.position, cell) < radius
+                    && cell.iter().all(|c| inner.contains(c))
+            });
+
+        let summed_birds = summed.iter().flat_map(|&cell| self.cell(cell).iter().map(|&i| &birds[i]));
+        let mut sums = shaped_neighbor_sums(bird, summed_birds, radius, Some(shape), Some(&params.layers));
+        let speed = bird.velocity.norm();
+        let heading = if speed > 0.0 { bird.velocity / speed } else { Vector3::zeros() };
+        for cell in summarised {
+            let summary = &summaries[self.cell_index(cell)];
+            if summary.count == 0 {
+                continue;
+            }
+            // Inside the cell, so further away than the threshold
+            let centroid = summary.position / summary.count as Real;
+            let away = (bird.position - centroid).normalize();
+            sums.separation += away * shape.weight(-away.dot(&heading)) * summary.count as Real;
+            sums.alignment += summary.velocity;
+            sums.position += summary.weighted_position;
+            sums.influence += summary.influence;
+            sums.count += summary.count;
+            sums.startle = sums.startle.max(summary.startle);
+        }
+        sums
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct CellSummary {
+    pub count: usize,
+    pub position: Vector3<Real>,         // sum over the birds in the cell
+    pub velocity: Vector3<Real>,         // sum over the birds in the cell, weighted by influence
+    pub weighted_position: Vector3<Real>, // as `position`, weighted by influence
+    pub influence: Real,                 // total over the birds in the cell
+    pub startle: Real,                   // highest in the cell
+}
+
+// Indices of the `k` birds nearest to bird `index`, closest first, by checking every bird
+pub fn brute_force_k_nearest(birds: &[Bird], index: usize, k: usize) -> Vec<usize> {
+    let position = birds[index].position;
+    let mut found: Vec<(Real, usize)> = birds
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(i, other)| ((other.position - position).norm(), i))
+        .collect();
+    found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    found.into_iter().take(k).map(|(_, i)| i).collect()
+}
+
+// Debug check: largest difference between grid and brute-force flocking forces
+pub fn check_grid_forces(birds: &[Bird], grid: &SpatialGrid, params: &SimulationParams) -> Real {
+    let mut worst: Real = 0.0;
+    for bird in birds {
+        let brute = flocking_forces(bird, birds, params.perception_radius, params.max_speed, params.max_force);
+        let candidates = grid.candidates(bird.position).map(|i| &birds[i]);
+        let gridded = flocking_forces(bird, candidates, params.perception_radius, params.max_speed, params.max_force);
+        worst = worst
+            .max((brute.0 - gridded.0).norm())
+            .max((brute.1 - gridded.1).norm())
+            .max((brute.2 - gridded.2).norm());
+    }
+    worst
+}
+
+// How one candidate cell size performs on a flock
+pub struct CellSizeStats {
+    pub cell_size: Real,       // actual width, after fitting a whole number of cells into the space
+    pub cells: usize,          // in the whole grid
+    pub max_per_cell: usize,
+    pub mean_candidates: Real, // birds checked per neighbor query
+}
+
+pub struct GridAnalysis {
+    pub neighbor_counts: [usize; 4], // min, median, 90th percentile and max over the sampled birds
+    pub sizes: Vec<CellSizeStats>,
+    pub recommended: Real,
+}
+
+// Neighbor counts within `radius` and grid occupancy for a few cell sizes, over a sample
+// of the birds. The recommended size minimises the per-bird work of a step: the birds
+// checked per query plus the grid's per-cell bookkeeping spread over the flock
+pub fn analyse_grid(birds: &[Bird], radius: Real, extent: Real) -> GridAnalysis {
+    let stride = (birds.len() / GRID_ANALYSIS_SAMPLES).max(1);
+    let samples: Vec<usize> = (0..birds.len()).step_by(stride).collect();
+
+    let exact = SpatialGrid::new(birds, radius, extent);
+    let mut counts: Vec<usize> = samples
+        .iter()
+        .map(|&i| {
+            exact
+                .candidates(birds[i].position)
+                .filter(|&j| j != i && (birds[j].position - birds[i].position).norm() < radius)
+                .count()
+        })
+        .collect();
+    counts.sort_unstable();
+    let percentile = |p: usize| counts.get((counts.len().max(1) - 1) * p / 100).copied().unwrap_or(0);
+
+    let mut sizes: Vec<CellSizeStats> = Vec::new();
+    for scale in GRID_ANALYSIS_SCALES {
+        let grid = SpatialGrid::new(birds, radius * scale, extent);
+        if sizes.last().is_some_and(|last| last.cells == grid.cells.pow(3)) {
+            continue; // rounded to the same grid as the last size
+        }
+        let checked: usize = samples.iter().map(|&i| grid.candidates(birds[i].position).count()).sum();
+        sizes.push(CellSizeStats {
+            cell_size: grid.cell_size(),
+            cells: grid.cells.pow(3),
+            max_per_cell: grid.max_cell_count(),
+            mean_candidates: checked as Real / samples.len().max(1) as Real,
+        });
+    }
+
+    let cost = |stats: &CellSizeStats| stats.mean_candidates + stats.cells as Real / birds.len().max(1) as Real;
+    let recommended = sizes
+        .iter()
+        .min_by(|a, b| cost(a).total_cmp(&cost(b)))
+        .map_or(radius, |stats| stats.cell_size);
+
+    GridAnalysis {
+        neighbor_counts: [percentile(0), percentile(50), percentile(90), percentile(100)],
+        sizes,
+        recommended,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::metrics::flock_metrics;
+    use crate::params::{Distribution, APPROXIMATION_CELL_FRACTION, GRID_CHECK_TOLERANCE, SPAWN_VELOCITIES};
+    use crate::simulation::Simulation;
+
+    fn flock(positions: Distribution, count: usize) -> Vec<Bird> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..count).map(|_| Bird::sampled(&positions, &SPAWN_VELOCITIES, &mut rng)).collect()
+    }
+
+    #[test]
+    fn grid_forces_match_brute_force_in_one_cell() {
+        // Well inside the middle cell, so every bird is every other's neighbor
+        let params = SimulationParams::default();
+        let birds = flock(Distribution::Uniform { extent: 0.5 }, 200);
+        let grid = SpatialGrid::new(&birds, params.perception_radius, params.extent);
+        assert_eq!(grid.cell(grid.cell_of(Vector3::zeros())).len(), birds.len());
+
+        let difference = check_grid_forces(&birds, &grid, &params);
+        assert!(difference <= GRID_CHECK_TOLERANCE, "grid forces differ by {:e}", difference);
+    }
+
+    #[test]
+    fn grid_forces_match_brute_force_across_many_cells() {
+        // The whole space, so most neighborhoods reach into the cells around
+        let params = SimulationParams::default();
+        let birds = flock(Distribution::Uniform { extent: params.extent }, 3000);
+        let grid = SpatialGrid::new(&birds, params.perception_radius, params.extent);
+        let occupied = grid.cell_start.windows(2).filter(|pair| pair[1] > pair[0]).count();
+        assert!(occupied > grid.cells.pow(3) / 2, "only {} of {} cells occupied", occupied, grid.cells.pow(3));
+
+        let difference = check_grid_forces(&birds, &grid, &params);
+        assert!(difference <= GRID_CHECK_TOLERANCE, "grid forces differ by {:e}", difference);
+    }
+
+    #[test]
+    fn k_nearest_matches_brute_force() {
+        // A dense clump inside a sparse flock, so some searches stop in the first ring and
+        // others go several rings out; ties in distance are broken by index in both
+        let params = SimulationParams::default();
+        let mut birds = flock(Distribution::Uniform { extent: params.extent }, 300);
+        birds.extend(flock(Distribution::Uniform { extent: 0.5 }, 100));
+        let grid = SpatialGrid::new(&birds, params.perception_radius, params.extent);
+
+        for k in [0, 1, 7, 50, birds.len()] {
+            let mismatched = (0..birds.len()).filter(|&i| grid.k_nearest(&birds, i, k) != brute_force_k_nearest(&birds, i, k)).count();
+            assert_eq!(mismatched, 0, "{} birds have different {} nearest neighbors", mismatched, k);
+        }
+    }
+
+    // Spawned well inside the space, so no bird reaches a face of it within the steps the
+    // tests run for, and dense enough that most birds have whole cells within reach
+    fn approximated_flock() -> (Vec<Bird>, SimulationParams) {
+        let params = SimulationParams { approximation_distance: 0.75, ..SimulationParams::default() };
+        (flock(Distribution::Uniform { extent: params.extent / 2.0 }, 4000), params)
+    }
+
+    #[test]
+    fn approximate_sums_keep_every_neighbor() {
+        let (birds, params) = approximated_flock();
+        let cells = SpatialGrid::new(&birds, params.approximation_distance * APPROXIMATION_CELL_FRACTION, params.extent);
+        let summaries = cells.summaries(&birds);
+        let tolerance = params.extent * 1e-5;
+        let mut approximated = 0;
+        for bird in &birds {
+            let exact = shaped_neighbor_sums(bird, &birds, params.perception_radius, None, None);
+            let approximate = cells.approximate_neighbor_sums(bird, &birds, &summaries, &params);
+            assert_eq!(approximate.count, exact.count);
+            assert!((approximate.influence - exact.influence).abs() <= tolerance);
+            assert!((approximate.alignment - exact.alignment).norm() <= tolerance);
+            assert!((approximate.position - exact.position).norm() <= tolerance * exact.count as Real);
+            if (approximate.separation - exact.separation).norm() > tolerance {
+                approximated += 1;
+            }
+        }
+        assert!(approximated > birds.len() / 2, "only {} of {} birds have summarised neighbors", approximated, birds.len());
+    }
+
+    #[test]
+    fn neighbors_within_the_approximation_distance_are_summed_exactly() {
+        // A clump narrower than the distance across its diagonal, spread over several cells
+        let params = SimulationParams { approximation_distance: 0.75, ..SimulationParams::default() };
+        let birds = flock(Distribution::Uniform { extent: 0.2 }, 300);
+        let cells = SpatialGrid::new(&birds, params.approximation_distance * APPROXIMATION_CELL_FRACTION, params.extent);
+        let summaries = cells.summaries(&birds);
+        for bird in &birds {
+            let exact = shaped_neighbor_sums(bird, &birds, params.perception_radius, None, None);
+            let approximate = cells.approximate_neighbor_sums(bird, &birds, &summaries, &params);
+            let difference = (approximate.separation - exact.separation).norm();
+            // Rounding grows with the neighbors summed, in whatever order
+            assert!(difference <= GRID_CHECK_TOLERANCE * exact.count as Real, "separation from the clump differs by {:e}", difference);
+        }
+    }
+
+    #[test]
+    fn approximate_flocks_stay_close_to_exact() {
+        const STEPS: usize = 10;
+        let (birds, params) = approximated_flock();
+        let run = |params: SimulationParams| {
+            let mut simulation = Simulation::new(params, birds.clone(), Vec::new());
+            for _ in 0..STEPS {
+                let _ = simulation.step();
+            }
+            flock_metrics(&simulation.birds)
+        };
+        let exact = run(SimulationParams { approximation_distance: 0.0, ..params.clone() });
+        let approximate = run(params.clone());
+
+        // Only the direction of separation from a summarised neighbor changes, by at most
+        // the angle its cell's diagonal subtends from the approximation distance, so the
+        // separation steering moves by at most that fraction of its limit. Spread over the
+        // flock, those errors point every which way, and the mean velocity moves by about
+        // one bird's over the square root of the flock size, step after step
+        let cell_size = SpatialGrid::new(&birds, params.approximation_distance * APPROXIMATION_CELL_FRACTION, params.extent).cell_size();
+        let angle = (3.0 as Real).sqrt() * cell_size / params.approximation_distance;
+        let per_step = params.separation_weight * params.max_force * angle.min(2.0) / (birds.len() as Real).sqrt();
+        let velocity_tolerance = per_step * params.dt * STEPS as Real;
+        let centroid_tolerance = per_step * params.dt * params.dt * (STEPS * (STEPS + 1) / 2) as Real;
+        let order_tolerance = 2.0 * velocity_tolerance / exact.mean_speed;
+
+        let order_difference = (approximate.order_parameter - exact.order_parameter).abs();
+        let centroid_difference = (approximate.centroid - exact.centroid).norm();
+        assert!(order_difference <= order_tolerance, "order parameters differ by {:e}, more than {:e}", order_difference, order_tolerance);
+        assert!(centroid_difference <= centroid_tolerance, "centroids differ by {:e}, more than {:e}", centroid_difference, centroid_tolerance);
+    }
+}
@@ -0,0 +1,43 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::bird::random_unit_vector;
+use crate::real::Real;
+
+// Transient push on the flock, fading out linearly over its lifetime
+#[derive(Clone)]
+pub struct Gust {
+    pub direction: Vector3<Real>, // unit vector
+    pub strength: Real,          // acceleration at the start of the gust
+    pub lifetime: Real,
+    pub age: Real,
+    pub region: Option<(Vector3<Real>, Real)>, // (centre, radius) it is confined to, None = everywhere
+}
+
+impl Gust {
+    // Gust in a random direction affecting the whole space
+    pub fn random<R: Rng>(rng: &mut R, strength: Real, lifetime: Real) -> Self {
+        Gust {
+            direction: random_unit_vector(rng),
+            strength,
+            lifetime,
+            age: 0.0,
+            region: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.age >= self.lifetime
+    }
+
+    // Acceleration this gust applies at `position`
+    pub fn force_at(&self, position: Vector3<Real>) -> Vector3<Real> {
+        if let Some((centre, radius)) = self.region {
+            if (position - centre).norm() > radius {
+                return Vector3::zeros();
+            }
+        }
+        let fade = (1.0 - self.age / self.lifetime).max(0.0);
+        self.direction * self.strength * fade
+    }
+}
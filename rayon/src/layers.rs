@@ -0,0 +1,27 @@
+use nalgebra::Vector3;
+
+use crate::bird::{limit_vec, Bird};
+use crate::params::LayerParams;
+use crate::real::Real;
+
+// Spread the birds evenly over `count` layers, interleaved by index so every
+// layer starts with a share of the whole spawn volume
+pub fn assign_layers(birds: &mut [Bird], count: usize) {
+    for (i, bird) in birds.iter_mut().enumerate() {
+        bird.layer = layer_of(i, count);
+    }
+}
+
+// Layer of the bird at `index` among `count` layers
+pub fn layer_of(index: usize, count: usize) -> u32 {
+    (index % count.max(1)) as u32
+}
+
+// Vertical steering back to the altitude of the bird's layer, easing off within
+// half a spacing of it so birds settle into the layer rather than overshoot.
+// Horizontal motion is left entirely to the flocking rules
+pub fn layer_force(bird: &Bird, layers: &LayerParams, max_speed: Real, max_force: Real) -> Vector3<Real> {
+    let offset = layers.height(bird.layer) - bird.position.y;
+    let climb = max_speed * (offset / (layers.spacing / 2.0)).clamp(-1.0, 1.0);
+    limit_vec(Vector3::y() * (climb - bird.velocity.y), max_force)
+}
@@ -0,0 +1,73 @@
+// Bird flocking simulation core. Rendering lives in the binary behind the
+// `render` feature, so this builds with just nalgebra, rand and rayon.
+
+// Casts between Real and f32 or f64 are no-ops in one of the two precisions
+#![allow(clippy::unnecessary_cast)]
+
+mod bird;
+mod boundary;
+mod controller;
+mod current;
+mod demo;
+mod distances;
+mod error;
+mod event;
+mod flash;
+mod flocking;
+mod golden;
+mod grid;
+mod gust;
+mod layers;
+mod load;
+mod metrics;
+mod obstacle;
+mod octree;
+mod params;
+mod real;
+mod roost;
+mod scene;
+mod schedule;
+mod shockwave;
+mod simulation;
+mod startle;
+mod stream;
+mod sweep;
+mod waypoint;
+#[cfg(feature = "alloc-stats")]
+mod memory;
+#[cfg(feature = "spawn-image")]
+mod spawn;
+
+pub use bird::*;
+pub use boundary::*;
+pub use controller::*;
+pub use current::*;
+pub use demo::*;
+pub use distances::*;
+pub use error::*;
+pub use event::*;
+pub use flash::*;
+pub use flocking::*;
+pub use golden::{golden_run, positions_csv};
+pub use grid::*;
+pub use gust::*;
+pub use layers::*;
+pub use load::*;
+pub use metrics::*;
+pub use obstacle::*;
+pub use octree::*;
+pub use params::*;
+pub use real::*;
+pub use roost::*;
+pub use scene::*;
+pub use schedule::*;
+pub use shockwave::*;
+pub use simulation::*;
+pub use startle::*;
+pub use stream::*;
+pub use sweep::*;
+pub use waypoint::*;
+#[cfg(feature = "alloc-stats")]
+pub use memory::*;
+#[cfg(feature = "spawn-image")]
+pub use spawn::*;
@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::real::Real;
+
+// Birds and neighbors handled by each rayon thread during the parallel update, to
+// show whether a clustered flock leaves some threads with far more work than others.
+// Integer counts only, so they do not disturb the step's thread-count independence
+pub struct ThreadLoad {
+    birds: Vec<AtomicUsize>,
+    neighbors: Vec<AtomicUsize>,
+}
+
+// (min, mean, max) over the threads since the last take
+pub struct LoadSummary {
+    pub birds: (usize, Real, usize),
+    pub neighbors: (usize, Real, usize),
+}
+
+impl ThreadLoad {
+    pub fn new(threads: usize) -> Self {
+        let counters = || (0..threads.max(1)).map(|_| AtomicUsize::new(0)).collect();
+        ThreadLoad { birds: counters(), neighbors: counters() }
+    }
+
+    // Called from a worker for each bird it updates. Work done outside the pool the
+    // counters were sized for is put on the first thread
+    pub fn record(&self, neighbors: usize) {
+        let thread = rayon::current_thread_index().filter(|&i| i < self.birds.len()).unwrap_or(0);
+        self.birds[thread].fetch_add(1, Ordering::Relaxed);
+        self.neighbors[thread].fetch_add(neighbors, Ordering::Relaxed);
+    }
+
+    // Spread of the counts, resetting them for the next period
+    pub fn take(&self) -> LoadSummary {
+        let spread = |counters: &[AtomicUsize]| {
+            let counts: Vec<usize> = counters.iter().map(|counter| counter.swap(0, Ordering::Relaxed)).collect();
+            let mean = counts.iter().sum::<usize>() as Real / counts.len() as Real;
+            (*counts.iter().min().unwrap(), mean, *counts.iter().max().unwrap())
+        };
+        LoadSummary { birds: spread(&self.birds), neighbors: spread(&self.neighbors) }
+    }
+}
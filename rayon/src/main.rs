@@ -1,351 +1,1349 @@
+// Casts between Real and f32 or f64 are no-ops in one of the two precisions
+#![allow(clippy::unnecessary_cast)]
+
+#[cfg(feature = "render")]
 #[macro_use]
 extern crate glium;
+#[cfg(feature = "render")]
 extern crate winit;
 
-use nalgebra::{Matrix4, Perspective3, Point3, Vector3}; // Add nalgebra for matrix calculations
-use rand::Rng;
-use rayon::prelude::*;
+#[cfg(feature = "render")]
+mod background;
+#[cfg(feature = "render")]
+mod export;
+#[cfg(feature = "render")]
+mod render;
+
+use birdflock::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::ops::ControlFlow;
+use std::str::FromStr;
 use std::time::Instant;
 
+#[cfg(feature = "render")]
+use background::BackgroundSimulation;
+#[cfg(feature = "render")]
+use export::{export_obj, export_scene, VideoRecorder};
+#[cfg(feature = "render")]
+use render::*;
+
+#[cfg(feature = "render")]
 const SHOW_VISUALS: bool = true;
+#[cfg(feature = "render")]
+const SHOW_CLOCK: bool = true; // step and simulated time in the window title
 const SHOW_TIMES: bool = true;
-const SHOW_POSITIONS: bool = false;
 const SHOWTIMES_EVERY: usize = 100;
 const PRINT_EVERY: bool = false;
 
 const SUMMARY_EVERY: usize = 1000;
+const EXIT_AFTER_STEPS: usize = 1000; // unless --exit-after-steps is given, 0 = run until closed
+const SHOW_PERCENTILES: bool = false; // p50/p90/p99/max step time in the summary, for tail latency
+const SHOW_LOAD_BALANCE: bool = false; // birds and neighbors handled per rayon thread in the summary
+
+const SHOW_METRICS: bool = true;   // centroid, order parameter and energy in the summary
+
+// Every heap allocation is counted for the summary's per-step allocation line
+#[cfg(feature = "alloc-stats")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::new();
+
+#[cfg(feature = "render")]
+const BIRD_COUNT_FACTOR: f64 = 1.25;     // + and - grow and shrink the flock by this factor
+#[cfg(feature = "render")]
+const BIRD_COUNT_FPS_FRAMES: usize = 60; // frames timed after a change to report the FPS at the new count
+
+// Keyboard controls, listed at startup
+#[cfg(feature = "render")]
+const CONTROLS: &[(&str, &str)] = &[
+    ("C", "log flock centroid and bounding box"),
+    ("V", "show the perception radius and neighbors of the selected bird"),
+    ("[ ]", "select the previous / next bird"),
+    ("I", "toggle interpolation between fixed-rate steps"),
+    ("O", "export bird positions as an OBJ point cloud"),
+    ("P", "cycle bird colour modes"),
+    ("H", "cycle the depth colour palettes"),
+    ("R", "cycle bird render styles"),
+    ("X", "toggle orthographic / perspective projection"),
+    ("T", "toggle speed-coloured comet tails"),
+    ("K", "toggle the octree cells overlay"),
+    ("M", "toggle the centroid and mean velocity marker"),
+    ("1 2 3", "toggle separation / alignment / cohesion"),
+    ("4-9", "weight presets: separation, alignment or cohesion only, balanced, swarm, streams"),
+    ("G", "trigger a wind gust"),
+    ("F", "set off a flash, a wave of turning spreading from the flock's centre"),
+    ("B", "add a burst of birds at the arrival point"),
+    ("+ -", "grow / shrink the flock, to compare the frame rate at different sizes"),
+    ("L", "reload the scene file"),
+    ("S", "save the current settings as a scene file"),
+    ("D", "start / stop the scripted demo, the scene's or the built-in one"),
+    ("Click", "set off a shockwave"),
+];
+
+// Command line options, e.g. `--threads 4`
+#[derive(Default)]
+struct Args {
+    threads: Option<usize>,
+    seed: Option<u64>,
+    spawn_image: Option<String>,
+    predators: Option<usize>,
+    coherence: Option<Real>,
+    world_scale: Option<Real>,
+    target_order: Option<Real>,
+    scene: Option<String>,
+    demo: bool,
+    headless: bool,
+    measure_speedup: bool,
+    analyse_grid: bool,
+    write_golden: Option<String>,
+    benchmark_neighbors: bool,
+    sweep: Option<String>,
+    exit_after_steps: Option<usize>,
+    hz: Option<f64>,
+    sim_thread: bool,
+    mesh: Option<String>,
+    render_video: Option<String>,
+    video_size: Option<(u32, u32)>,
+    stream_addr: Option<String>,
+    stream_velocities: bool,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--threads" => {
+                let threads: usize = parse_value(&arg, iter.next());
+                if threads == 0 {
+                    usage_error("--threads must be at least 1");
+                }
+                args.threads = Some(threads);
+            }
+            "--seed" => args.seed = Some(parse_value(&arg, iter.next())),
+            "--spawn-image" => args.spawn_image = Some(parse_value(&arg, iter.next())),
+            "--predators" => args.predators = Some(parse_value(&arg, iter.next())),
+            "--coherence" => {
+                let coherence: Real = parse_value(&arg, iter.next());
+                if !(0.0..=1.0).contains(&coherence) {
+                    usage_error("--coherence must be between 0 and 1");
+                }
+                args.coherence = Some(coherence);
+            }
+            "--target-order" => {
+                let target: Real = parse_value(&arg, iter.next());
+                if !(0.0..=1.0).contains(&target) {
+                    usage_error("--target-order must be between 0 and 1");
+                }
+                args.target_order = Some(target);
+            }
+            "--world-scale" => {
+                let scale: Real = parse_value(&arg, iter.next());
+                if !(scale > 0.0 && scale.is_finite()) {
+                    usage_error("--world-scale must be positive");
+                }
+                args.world_scale = Some(scale);
+            }
+            "--scene" => args.scene = Some(parse_value(&arg, iter.next())),
+            "--demo" => args.demo = true,
+            "--headless" => args.headless = true,
+            "--measure-speedup" => args.measure_speedup = true,
+            "--analyse-grid" => args.analyse_grid = true,
+            "--write-golden" => args.write_golden = Some(parse_value(&arg, iter.next())),
+            "--benchmark-neighbors" => args.benchmark_neighbors = true,
+            "--sweep" => args.sweep = Some(parse_value(&arg, iter.next())),
+            "--exit-after-steps" => args.exit_after_steps = Some(parse_value(&arg, iter.next())),
+            "--mesh" => args.mesh = Some(parse_value(&arg, iter.next())),
+            "--hz" => args.hz = Some(parse_value(&arg, iter.next())),
+            "--sim-thread" => args.sim_thread = true,
+            "--render-video" => args.render_video = Some(parse_value(&arg, iter.next())),
+            "--video-size" => args.video_size = Some(parse_size(&arg, iter.next())),
+            "--stream-addr" => args.stream_addr = Some(parse_value(&arg, iter.next())),
+            "--stream-velocities" => args.stream_velocities = true,
+            _ => usage_error(&format!("unknown argument '{}'", arg)),
+        }
+    }
+    args
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    match value {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| usage_error(&format!("invalid value '{}' for {}", value, flag))),
+        None => usage_error(&format!("missing value for {}", flag)),
+    }
+}
+
+// `WIDTHxHEIGHT`, e.g. `1920x1080`
+fn parse_size(flag: &str, value: Option<String>) -> (u32, u32) {
+    let value: String = parse_value(flag, value);
+    let size = value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height): &(u32, u32)| width > 0 && height > 0);
+    size.unwrap_or_else(|| usage_error(&format!("invalid size '{}' for {}, expected WIDTHxHEIGHT", value, flag)))
+}
 
-const NUM_BIRDS: usize = 10000;
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    eprintln!("usage: opengl_rust_glium [--threads N] [--seed N] [--spawn-image PATH] [--predators N] [--coherence X] [--target-order X] [--world-scale X] [--scene PATH] [--demo] [--headless] [--measure-speedup] [--analyse-grid]\n       [--exit-after-steps N] [--hz STEPS_PER_SECOND] [--sim-thread] [--mesh PATH] [--render-video PATH] [--video-size WxH]\n       [--stream-addr HOST:PORT] [--stream-velocities]\n       opengl_rust_glium --write-golden PATH | --benchmark-neighbors | --sweep PATH");
+    std::process::exit(1);
+}
+
+// Per-batch and whole-run step timings, printed as the simulation goes
+struct Timings {
+    step_count: usize,
+    total_steps: usize,
+    perf_start: Instant,
+    summary_start: Instant,
 
-const POV_DISTANCE: f32 = 17.5;
+    total_overhead_time: f64,
+    total_calc_time: f64,
 
-const DIMENSIONS: f32 = 7.5;
-const SPACE_MIN: f32 = -DIMENSIONS;
-const SPACE_MAX: f32 = DIMENSIONS;
+    cumulative_overhead_time: f64,
+    cumulative_calc_time: f64,
 
-const SEPARATION_WEIGHT: f32 = 1.5;    // flock tightness
-const ALIGNMENT_WEIGHT:  f32 = 2.0;    // movement coordination
-const COHESION_WEIGHT:   f32 = 1.5;    // flock unification
-const PERCEPTION_RADIUS: f32 = 1.9;    // flock size
-const MAX_SPEED:         f32 = 0.125;
-const MAX_FORCE:         f32 = 0.03;   // sharpness of movement
+    single_thread_time: Option<f64>, // one step on one thread, to report the parallel speedup
 
+    step_times: Vec<f64>, // ring buffer of the last SUMMARY_EVERY step times, when SHOW_PERCENTILES
+    next_step_time: usize,
 
+    summary_steps: usize,       // since the last summary
+    exit_after: Option<usize>,  // total steps to run, None to keep going
 
-#[derive(Clone)]
-struct Bird {
-    position: Vector3<f32>,
-    velocity: Vector3<f32>,
-    acceleration: Vector3<f32>,
+    #[cfg(feature = "alloc-stats")]
+    summary_allocations: AllocationStats, // at the start of the summary period
 }
 
-impl Bird {
-    // Create a new bird with random position and velocity
-    fn new<R: Rng>(rng: &mut R) -> Self {
-        Bird {
-            position: Vector3::new(
-                rng.random_range(-5.0..5.0),
-                rng.random_range(-5.0..5.0),
-                rng.random_range(-5.0..5.0)
-            ),
-            velocity: Vector3::new(
-                rng.random_range(-1.0..1.0),
-                rng.random_range(-1.0..1.0),
-                rng.random_range(-1.0..1.0)
-            ),
-            acceleration: Vector3::zeros(),
+impl Timings {
+    fn new(exit_after: Option<usize>) -> Self {
+        Timings {
+            step_count: 0,
+            total_steps: 0,
+            perf_start: Instant::now(),
+            summary_start: Instant::now(),
+            total_overhead_time: 0.0,
+            total_calc_time: 0.0,
+            cumulative_overhead_time: 0.0,
+            cumulative_calc_time: 0.0,
+            single_thread_time: None,
+            step_times: Vec::new(),
+            next_step_time: 0,
+            summary_steps: 0,
+            exit_after,
+            #[cfg(feature = "alloc-stats")]
+            summary_allocations: ALLOCATOR.stats(),
         }
     }
+
+    fn record_step_time(&mut self, step_time: f64) {
+        if self.step_times.len() < SUMMARY_EVERY {
+            self.step_times.push(step_time);
+        } else {
+            self.step_times[self.next_step_time] = step_time;
+        }
+        self.next_step_time = (self.next_step_time + 1) % SUMMARY_EVERY;
+    }
+
+    // Nearest-rank percentiles of the buffered step times, in ms
+    fn print_percentiles(&self) {
+        if self.step_times.is_empty() {
+            return;
+        }
+        let mut sorted = self.step_times.clone();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+            sorted[rank - 1] * 1000.0
+        };
+        println!(
+            "Step time p50: {:.3} ms | p90: {:.3} ms | p99: {:.3} ms | max: {:.3} ms",
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            sorted[sorted.len() - 1] * 1000.0
+        );
+    }
+
+    // Heap allocated per step since the last summary, with what's in use now and the most ever in use
+    #[cfg(feature = "alloc-stats")]
+    fn print_allocations(&self, steps: usize) {
+        let stats = ALLOCATOR.stats();
+        let start = self.summary_allocations;
+        let per_step = |count: usize| count as f64 / steps.max(1) as f64;
+        let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+        println!(
+            "Heap per step: {:.0} allocations, {:.1} KiB | In use: {:.1} MiB | Peak: {:.1} MiB",
+            per_step(stats.allocations - start.allocations),
+            per_step(stats.allocated - start.allocated) / 1024.0,
+            mib(stats.live),
+            mib(stats.peak)
+        );
+    }
+
+    // Averages over the steps since the last summary, then start the next period
+    fn print_summary(&mut self, simulations: &[&Simulation]) {
+        let steps = self.summary_steps;
+        let summary_elapsed = self.summary_start.elapsed();
+        let avg_fps = steps as f64 / summary_elapsed.as_secs_f64();
+
+        let avg_calc = (self.cumulative_calc_time / steps as f64) * 1000.0;
+        let avg_overhead = (self.cumulative_overhead_time / steps as f64) * 1000.0;
+
+        println!(
+            "\n\nSimulated {} steps in {:.3} seconds at {:.0} FPS",
+            steps,
+            summary_elapsed.as_secs_f64(),
+            avg_fps
+        );
+        println!(
+            "Average Calculation: {:.3} ms | Average Overhead: {:.3} ms",
+            avg_calc,
+            avg_overhead
+        );
+        if SHOW_PERCENTILES {
+            self.print_percentiles();
+        }
+        if SHOW_LOAD_BALANCE {
+            print_load_balance(simulations);
+        }
+        #[cfg(feature = "alloc-stats")]
+        self.print_allocations(steps);
+        if let Some(single_thread_time) = self.single_thread_time {
+            let threads = rayon::current_num_threads();
+            let speedup = single_thread_time * 1000.0 / avg_calc;
+            println!(
+                "Speedup: {:.2}x over one thread with {} threads ({:.0}% parallel efficiency)",
+                speedup,
+                threads,
+                100.0 * speedup / threads as f64
+            );
+        }
+        if SHOW_METRICS {
+            for (i, simulation) in simulations.iter().enumerate() {
+                let metrics = flock_metrics(&simulation.birds);
+                let label = if simulations.len() > 1 { format!("Flock {} centroid", i + 1) } else { "Centroid".to_string() };
+                println!(
+                    "{}: ({:.3}, {:.3}, {:.3}) | Order parameter: {:.4}",
+                    label,
+                    metrics.centroid.x,
+                    metrics.centroid.y,
+                    metrics.centroid.z,
+                    metrics.order_parameter
+                );
+                let energy = flock_energy(&simulation.birds, simulation.params.max_speed);
+                println!(
+                    "Kinetic energy: {:.4} | Momentum: ({:.3}, {:.3}, {:.3}) | At max speed: {:.1}%",
+                    energy.kinetic_energy,
+                    energy.momentum.x,
+                    energy.momentum.y,
+                    energy.momentum.z,
+                    100.0 * energy.saturated_fraction
+                );
+            }
+        }
+
+        self.summary_steps = 0;
+        self.cumulative_calc_time = 0.0;
+        self.cumulative_overhead_time = 0.0;
+        self.summary_start = Instant::now();
+        #[cfg(feature = "alloc-stats")]
+        {
+            self.summary_allocations = ALLOCATOR.stats();
+        }
+    }
+
+    fn start_step(&mut self) {
+        if SHOW_TIMES && self.step_count == 0 {
+            self.perf_start = Instant::now();
+        }
+
+        if self.total_steps == 0 {
+            self.summary_start = Instant::now();
+            #[cfg(feature = "alloc-stats")]
+            {
+                self.summary_allocations = ALLOCATOR.stats();
+            }
+        }
+    }
+
+    // Record one step, breaking once the last one has been taken
+    fn finish_step(&mut self, calc_time: f64, overhead_time: f64, simulations: &[&Simulation]) -> ControlFlow<()> {
+        self.total_calc_time += calc_time;
+        self.cumulative_calc_time += calc_time;
+        self.total_overhead_time += overhead_time;
+        self.cumulative_overhead_time += overhead_time;
+
+        self.total_steps += 1;
+        if !SHOW_TIMES {
+            return self.finish_run(simulations);
+        }
+        self.step_count += 1;
+        self.summary_steps += 1;
+        if SHOW_PERCENTILES {
+            self.record_step_time(calc_time + overhead_time);
+        }
+
+        if self.step_count.is_multiple_of(SHOWTIMES_EVERY) && PRINT_EVERY {
+            let elapsed = self.perf_start.elapsed();
+            let avg_time_per_step = elapsed.as_secs_f64() / SHOWTIMES_EVERY as f64;
+            let fps = 1.0 / avg_time_per_step;
+
+            let avg_calc_time = self.total_calc_time / SHOWTIMES_EVERY as f64;
+            let avg_overhead = self.total_overhead_time / SHOWTIMES_EVERY as f64;
+
+            println!(
+                "Simulated steps {}-{} in {:.3} seconds ({:.3} ms/step, {:.2} FPS)",
+                self.total_steps - SHOWTIMES_EVERY,
+                self.total_steps,
+                elapsed.as_secs_f64(),
+                avg_time_per_step * 1000.0,
+                fps
+            );
+            println!(
+                "Calculation: {:.3} ms | Overhead: {:.3} ms | Total: {:.3} ms",
+                avg_calc_time * 1000.0,
+                avg_overhead * 1000.0,
+                (avg_calc_time + avg_overhead) * 1000.0
+            );
+
+            // Reset counters for the next batch
+            self.total_calc_time = 0.0;
+            self.total_overhead_time = 0.0;
+            self.perf_start = Instant::now();
+        }
+
+        if self.summary_steps == SUMMARY_EVERY {
+            self.print_summary(simulations);
+        }
+        self.finish_run(simulations)
+    }
+
+    // Break once exit_after steps are done, summarising any since the last summary
+    fn finish_run(&mut self, simulations: &[&Simulation]) -> ControlFlow<()> {
+        if self.exit_after != Some(self.total_steps) {
+            return ControlFlow::Continue(());
+        }
+        if self.summary_steps > 0 {
+            self.print_summary(simulations);
+        }
+        println!("\nSimulation complete after {} steps. Exiting.", self.total_steps);
+        ControlFlow::Break(())
+    }
+
+    // Steps left before the run exits, so a frame never takes more
+    #[cfg(feature = "render")]
+    fn steps_remaining(&self) -> usize {
+        match self.exit_after {
+            Some(exit_after) => exit_after.saturating_sub(self.total_steps),
+            None => usize::MAX,
+        }
+    }
+}
+
+// Spread of the per-thread work since the last summary, with the busiest thread relative to the mean
+fn print_load_balance(simulations: &[&Simulation]) {
+    for (i, simulation) in simulations.iter().enumerate() {
+        let Some(thread_load) = &simulation.thread_load else { continue };
+        let load = thread_load.take();
+        let label = if simulations.len() > 1 { format!("Flock {} load", i + 1) } else { "Load".to_string() };
+        let (min_birds, mean_birds, max_birds) = load.birds;
+        let (min_neighbors, mean_neighbors, max_neighbors) = load.neighbors;
+        println!(
+            "{} per thread (min / mean / max): birds {} / {:.0} / {} ({:.2}x mean) | neighbors {} / {:.0} / {} ({:.2}x mean)",
+            label,
+            min_birds,
+            mean_birds,
+            max_birds,
+            max_birds as Real / mean_birds.max(1.0),
+            min_neighbors,
+            mean_neighbors,
+            max_neighbors,
+            max_neighbors as Real / mean_neighbors.max(1.0)
+        );
+    }
 }
 
-fn wraparound(mut v: Vector3<f32>) -> Vector3<f32> {
-    for i in 0..3 {
-        if v[i] < SPACE_MIN {
-            v[i] = SPACE_MAX - (SPACE_MIN - v[i]) % (SPACE_MAX - SPACE_MIN);
-        } else if v[i] > SPACE_MAX {
-            v[i] = SPACE_MIN + (v[i] - SPACE_MAX) % (SPACE_MAX - SPACE_MIN);
+// Initialize birds with random positions and velocities, one simulation per scene flock
+fn build_simulations(args: &Args) -> Result<Vec<Simulation>, BirdflockError> {
+    let mut scene = match &args.scene {
+        Some(path) => {
+            let scene = Scene::load(path)?;
+            if scene.schedule.is_some() {
+                println!("Following the weight schedule from {}", path);
+            }
+            scene
         }
+        None => Scene::default(),
+    };
+    if args.world_scale.is_some() {
+        scene.world_scale = args.world_scale;
     }
-    v
+
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    #[cfg(feature = "spawn-image")]
+    let spawn_image = match &args.spawn_image {
+        Some(path) => {
+            let spawn_image = SpawnImage::load(path)?;
+            println!("Spawning birds from {} ({}x{})", path, spawn_image.width, spawn_image.height);
+            Some(spawn_image)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "spawn-image"))]
+    if args.spawn_image.is_some() {
+        usage_error("--spawn-image needs the spawn-image feature");
+    }
+
+    let mut params = SimulationParams::default();
+    scene.apply_params(&mut params);
+    let (positions, velocities) = (params.spawn_positions, params.spawn_velocities);
+    (0..scene.flock_count())
+        .map(|index| {
+            #[cfg(feature = "spawn-image")]
+            let mut birds: Vec<Bird> = match &spawn_image {
+                Some(spawn_image) => (0..NUM_BIRDS)
+                    .map(|_| Bird::at_position(spawn_image.sample_position(&mut rng), &velocities, &mut rng))
+                    .collect(),
+                None => (0..NUM_BIRDS).map(|_| Bird::sampled(&positions, &velocities, &mut rng)).collect(),
+            };
+            #[cfg(not(feature = "spawn-image"))]
+            let mut birds: Vec<Bird> = (0..NUM_BIRDS).map(|_| Bird::sampled(&positions, &velocities, &mut rng)).collect();
+            bias_headings(&mut birds, args.coherence.unwrap_or(HEADING_COHERENCE), &mut rng);
+            assign_influence(&mut birds, LEADER_FRACTION, LEADER_INFLUENCE, &mut rng);
+            assign_tags(&mut birds, &params.tag_rules, &mut rng);
+
+            let num_predators = args.predators.unwrap_or(NUM_PREDATORS);
+            let predators: Vec<Bird> = (0..num_predators).map(|_| Bird::new_scaled(params.world_scale, &mut rng)).collect();
+            // Validated once the scene and --world-scale have had their say, not before
+            let mut simulation = Simulation::new(SimulationParams::default(), birds, predators);
+            simulation.set_seed(rng.random());
+            scene.apply(index, &mut simulation);
+            simulation.params.validate()?;
+            Ok(simulation)
+        })
+        .collect()
+}
+
+// Log the mean position and axis-aligned bounding box of the flock
+#[cfg(feature = "render")]
+fn print_flock_extent(birds: &[Bird]) {
+    let centroid = flock_metrics(birds).centroid;
+    let (min, max) = flock_extent(birds);
+    println!(
+        "Centroid: ({:.3}, {:.3}, {:.3}) | Extent: x [{:.3}, {:.3}] y [{:.3}, {:.3}] z [{:.3}, {:.3}]",
+        centroid.x, centroid.y, centroid.z, min.x, max.x, min.y, max.y, min.z, max.z
+    );
 }
 
-fn limit_vec(v: Vector3<f32>, max: f32) -> Vector3<f32> {
-    if v.norm() > max {
-        v.normalize() * max
+// Run `command` on the flocks now, or with --sim-thread pass it to the simulation thread
+// to run before its next step
+#[cfg(feature = "render")]
+fn command_flocks<F>(flocks: &mut [FlockView], background: &Option<BackgroundSimulation>, command: F)
+where
+    F: FnOnce(&mut [FlockView]) + Send + 'static,
+{
+    match background {
+        Some(background) => background.send(Box::new(command)),
+        None => command(flocks),
+    }
+}
+
+// Re-read the scene into the running flocks, keeping their birds. On an error the
+// old settings stay in place, and the flock count can only change on a restart.
+#[cfg(feature = "render")]
+fn reload_scene(path: &str, flocks: &mut [FlockView]) {
+    let scene = match Scene::load(path) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("error: {}, keeping the current scene", err);
+            return;
+        }
+    };
+    if scene.flock_count() != flocks.len() {
+        eprintln!(
+            "error: scene '{}' now has {} flocks but {} are running, keeping the current scene",
+            path,
+            scene.flock_count(),
+            flocks.len()
+        );
+        return;
+    }
+    for (index, flock) in flocks.iter_mut().enumerate() {
+        scene.apply(index, &mut flock.simulation);
+    }
+    println!("Reloaded scene {}", path);
+}
+
+// Step time of brute force, pairwise brute force, the grid, the octree and the distance matrix by flock size, the
+// quickest of NEIGHBOR_BENCHMARK_REPEATS warmed-up runs, with the largest flock where the matrix was quickest as a
+// starting point for DISTANCE_MATRIX_BIRDS
+fn run_neighbor_benchmark() -> ! {
+    println!("{:>8} {:>14} {:>11} {:>10} {:>10} {:>12}", "birds", "brute force ms", "pairwise ms", "grid ms", "octree ms", "matrix ms");
+    let mut crossover = 0;
+    let mut matches = true;
+    for result in neighbor_benchmark() {
+        println!(
+            "{:>8} {:>14.3} {:>11.3} {:>10.3} {:>10.3} {:>12.3}",
+            result.birds,
+            result.brute_force * 1000.0,
+            result.pairwise * 1000.0,
+            result.grid * 1000.0,
+            result.octree * 1000.0,
+            result.matrix * 1000.0
+        );
+        if result.matrix < result.brute_force.min(result.pairwise).min(result.grid).min(result.octree) {
+            crossover = result.birds;
+        }
+        matches &= result.matrix_matches;
+    }
+    if crossover > 0 {
+        println!("Distance matrix quickest up to {} birds", crossover);
     } else {
-        v
+        println!("Distance matrix never quickest");
+    }
+    if matches {
+        std::process::exit(0);
     }
+    eprintln!("Distance matrix results differ from brute force");
+    std::process::exit(1);
 }
 
-fn main() {
-    #[allow(unused_imports)]
-    use glium::{glutin, Surface};
+// Run every combination in a sweep file and write their metrics to its output CSV,
+// by default the sweep file's path with a .csv extension
+fn run_sweep(path: &str) -> Result<(), BirdflockError> {
+    let sweep = Sweep::load(path)?;
+    println!(
+        "Sweeping {} runs of {} birds for {} steps ({} threads)",
+        sweep.run_count(),
+        sweep.birds,
+        sweep.steps,
+        rayon::current_num_threads()
+    );
+    let start = Instant::now();
+    let results = sweep.run()?;
+    let output = sweep.output.clone().unwrap_or_else(|| {
+        std::path::Path::new(path).with_extension("csv").to_string_lossy().into_owned()
+    });
+    std::fs::write(&output, sweep.to_csv(&results)).map_err(|error| BirdflockError::io(&output, error))?;
+    println!("Wrote {} results to {} in {:.1} s", results.len(), output, start.elapsed().as_secs_f64());
+    Ok(())
+}
 
-    let event_loop = glium::winit::event_loop::EventLoop::builder()
+// Regenerate the CSV the golden test compares against, after an intended change
+fn write_golden(path: &str) -> ! {
+    let birds = golden_run();
+    if let Err(err) = std::fs::write(path, positions_csv(&birds)) {
+        eprintln!("error: cannot write '{}': {}", path, err);
+        std::process::exit(1);
+    }
+    println!("Wrote {} golden positions to {}", birds.len(), path);
+    std::process::exit(0);
+}
+
+// Table of neighbor counts and grid occupancy for candidate cell sizes
+fn print_grid_analysis(simulation: &Simulation) {
+    let radius = simulation.params.perception_radius;
+    let analysis = analyse_grid(&simulation.birds, radius, simulation.params.extent);
+    let [min, median, p90, max] = analysis.neighbor_counts;
+    println!("Neighbors within {}: min {} | median {} | p90 {} | max {}", radius, min, median, p90, max);
+    println!("{:>10} {:>8} {:>13} {:>16}", "cell size", "cells", "max per cell", "birds per query");
+    for stats in &analysis.sizes {
+        println!(
+            "{:>10.3} {:>8} {:>13} {:>16.1}",
+            stats.cell_size, stats.cells, stats.max_per_cell, stats.mean_candidates
+        );
+    }
+    println!("Recommended GRID_CELL_SIZE: {:.3}\n", analysis.recommended);
+}
+
+// Calculation time of one step of every flock on a single thread, run on copies
+// so the simulations themselves are not advanced
+fn single_thread_step_time(simulations: &[Simulation]) -> Result<f64, BirdflockError> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
         .build()
-        .expect("event loop building");
-    let (window, display) = glium::backend::glutin::SimpleWindowBuilder::new()
-        .with_title("Bird Flocking Simulation")
-        .build(&event_loop);
+        .map_err(|error| BirdflockError::thread_pool(1, error))?;
+    let time = simulations
+        .iter()
+        .map(|simulation| {
+            let mut copy = Simulation::new(simulation.params.clone(), simulation.birds.clone(), simulation.predators.clone());
+            copy.schedule = simulation.schedule.clone();
+            copy.obstacles = simulation.obstacles.clone();
+            copy.waypoints = simulation.waypoints.clone();
+            let _ = pool.install(|| copy.step());
+            copy.last_calc_time
+        })
+        .sum::<f64>();
+    Ok(time)
+}
 
-    #[derive(Copy, Clone)]
-    struct Vertex {
-        position: [f32; 2],
+// Report what happened in the steps since the events were last printed
+fn print_events(simulation: &mut Simulation) {
+    for (step_index, event) in simulation.take_events() {
+        println!("Step {}: {}", step_index, event);
     }
+}
 
-    implement_vertex!(Vertex, position);
+// Step the simulations without a window until the summary is printed
+fn run_headless(mut simulations: Vec<Simulation>, mut timings: Timings, mut stream: Option<PositionStream>) {
+    loop {
+        timings.start_step();
+        let step_start = Instant::now();
 
-    // Create a triangle shape
-    let vertex1 = Vertex { position: [-0.05, -0.0288] };
-    let vertex2 = Vertex { position: [ 0.00,  0.0577] };
-    let vertex3 = Vertex { position: [ 0.05, -0.0288] };
-    let shape = vec![vertex1, vertex2, vertex3];
+        let mut flow = ControlFlow::Continue(());
+        let mut calc_time = 0.0;
+        for simulation in &mut simulations {
+            if simulation.step().is_break() {
+                flow = ControlFlow::Break(());
+            }
+            print_events(simulation);
+            calc_time += simulation.last_calc_time;
+        }
+        if let Some(stream) = &mut stream {
+            stream.send(&simulations[0]);
+        }
 
-    let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
-    let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let overhead_time = step_start.elapsed().as_secs_f64() - calc_time;
+        let finished = timings.finish_step(calc_time, overhead_time, &simulations.iter().collect::<Vec<_>>());
+        if flow.is_break() || finished.is_break() {
+            break;
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+fn run_windowed(
+    simulations: Vec<Simulation>,
+    mut timings: Timings,
+    args: Args,
+    mut stream: Option<PositionStream>,
+) -> Result<(), BirdflockError> {
+    use glium::Surface;
+    use nalgebra::{Matrix4, Vector3};
 
-    let vertex_shader_src = r#"
+    // Load the mesh before opening the window so a bad file fails straight away
+    let model_mesh = match &args.mesh {
+        Some(path) => {
+            let mesh = load_obj_mesh(path)?;
+            println!("Loaded {} triangles from {}", mesh.len() / 3, path);
+            mesh
+        }
+        None => bird_model_mesh(),
+    };
+
+    let event_loop = glium::winit::event_loop::EventLoop::builder()
+        .build()
+        .map_err(|err| BirdflockError::GlInit(format!("cannot create the event loop: {}", err)))?;
+    // Matching the window to the video keeps the captured frames unscaled
+    let video_size = args.video_size.unwrap_or(VIDEO_SIZE);
+    let window_size = if args.render_video.is_some() { video_size } else { WINDOW_SIZE };
+    let (window, display) = create_display(&event_loop, "Bird Flocking Simulation", window_size)?;
+
+    // Recording takes one frame per step, however long each frame takes to draw
+    let mut video = match &args.render_video {
+        Some(path) => {
+            let video = VideoRecorder::create(&display, path, video_size)?;
+            println!(
+                "Recording to {}, encode with: ffmpeg -f rawvideo -pix_fmt rgba -s {}x{} -r 60 -i {} out.mp4",
+                path, video_size.0, video_size.1, path
+            );
+            Some(video)
+        }
+        None => None,
+    };
+
+    let mut bird_renderer = BirdRenderer::new(&display, &model_mesh)?;
+    if args.mesh.is_some() {
+        bird_renderer.style = RenderStyle::Model;
+    }
+    let line_renderer = LineRenderer::new(&display)?;
+    let tail_renderer = TailRenderer::new(&display)?;
+    let sphere_lines = vertex_buffer(&display, "sphere", &wire_sphere_lines())?;
+    let cube_lines = vertex_buffer(&display, "cube", &wire_cube_lines())?;
+    let ground = vertex_buffer(&display, "ground", &ground_lines())?;
+    let mut octree_overlay = OverlayLines::default();
+
+    // Fullscreen quad used to fade the heatmap each frame
+    let quad = vec![
+        Vertex { position: [-1.0, -1.0] },
+        Vertex { position: [ 1.0, -1.0] },
+        Vertex { position: [-1.0,  1.0] },
+        Vertex { position: [ 1.0,  1.0] },
+    ];
+    let quad_buffer = vertex_buffer(&display, "heatmap quad", &quad)?;
+    let quad_indices = glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip);
+
+    let fade_vertex_shader_src = r#"
         #version 140
 
         in vec2 position;
 
-        uniform mat4 model;
-        uniform mat4 view;
-        uniform mat4 projection;
-
         void main() {
-            gl_Position = projection * view * model * vec4(position, 0.0, 1.0);
+            gl_Position = vec4(position, 0.0, 1.0);
         }
     "#;
 
-    // bird colour
-    let fragment_shader_src = r#"
+    let fade_fragment_shader_src = r#"
         #version 140
 
-        uniform float depth; // z position of the bird
+        uniform float fade;
 
         out vec4 color;
 
         void main() {
-            // Map depth (e.g. -7.5 to 7.5) to [0,1]
-            float t = clamp((depth + 7.5) / 15.0, 0.0, 1.0);
-            // Example: from red (far) to white (close)
-            vec3 near_col = vec3(1.0, 1.0, 1.0);   // white when close
-            vec3 far_col = vec3(1.0, 0.2, 0.2);    // red when far
-            vec3 bird_col = mix(far_col, near_col, 1.0 - t);
-            color = vec4(bird_col, 1.0);
+            color = vec4(0.0, 0.0, 0.0, fade);
         }
     "#;
 
-    let program = glium::Program::from_source(&display, vertex_shader_src, fragment_shader_src, None).unwrap();
+    let fade_program = compile_program(&display, "heatmap fade", fade_vertex_shader_src, fade_fragment_shader_src, false)?;
+    let fade_params = glium::DrawParameters {
+        blend: glium::Blend::alpha_blending(),
+        ..Default::default()
+    };
+    let mut heatmap: Option<glium::texture::Texture2d> = None;
 
-    // Initialize birds with random positions and velocities
-    let mut rng = rand::rng();
-    let mut birds: Vec<Bird> = (0..NUM_BIRDS).map(|_| Bird::new(&mut rng)).collect();
+    let mut camera = Camera::new(aspect_ratio(display.get_framebuffer_dimensions()));
 
-    let mut step_count = 0;
-    let mut total_steps = 0;
-    let mut perf_start = Instant::now();
-    let mut summary_start = Instant::now();
+    // Overlays, exports and selection act on the first flock
+    let (offsets, camera_distance) = flock_layout(simulations.len(), simulations[0].params.extent as f32);
+    let mut flocks: Vec<FlockView> = simulations.into_iter().zip(offsets).map(|(simulation, offset)| FlockView::new(simulation, offset)).collect();
+    let mut centroid_overlays: Vec<OverlayLines> = flocks.iter().map(|_| OverlayLines::default()).collect();
+    camera.set_distance(camera_distance);
 
-    let mut total_overhead_time = 0.0;
-    let mut total_calc_time = 0.0;
+    // What D starts, from its first stage
+    let demo = flocks[0].simulation.demo.clone().unwrap_or_else(Demo::built_in);
 
-    let mut cumulative_overhead_time = 0.0;
-    let mut cumulative_calc_time = 0.0;
+    let mut show_perception = false;
+    let mut selected_bird = 0;
+    let mut cursor = (0.0, 0.0);
 
-    println!("\n\nStarting simulation with {} birds using Rayon", NUM_BIRDS);
-    if SHOW_VISUALS {
-        println!("Visuals enabled.\n");
+    let mut interpolate = INTERPOLATE;
+    let mut show_tails = SHOW_TAILS;
+    let mut show_octree = SHOW_OCTREE;
+    let mut show_centroid = SHOW_CENTROID;
+    let simulation_rate = args.hz.unwrap_or(SIMULATION_RATE);
+    let simulation_rate = if simulation_rate > 0.0 {
+        let clamped = simulation_rate.clamp(1.0, MAX_SIMULATION_RATE);
+        if clamped != simulation_rate {
+            println!("Clamped the simulation rate to {} steps per second", clamped);
+        }
+        clamped
     } else {
-        println!("Visuals disabled.\n");
-    }
+        0.0
+    };
+    let mut accumulator = 0.0;
+    let mut last_frame = Instant::now();
+
+    // With --sim-thread the flocks step on their own thread, and `flocks` is the latest
+    // copy it published, only drawn and read from; changes go through `command_flocks`.
+    // The steps are timed on that thread, leaving the window's timings empty
+    let (mut timings, background) = if args.sim_thread {
+        let mut stream = stream.take();
+        let copies = flocks.iter().map(FlockView::snapshot).collect();
+        let stepped = std::mem::replace(&mut flocks, copies);
+        let background = BackgroundSimulation::spawn(stepped, simulation_rate, move |flocks| {
+            timings.start_step();
+            let step_start = Instant::now();
+
+            let mut flow = ControlFlow::Continue(());
+            let mut calc_time = 0.0;
+            for flock in flocks.iter_mut() {
+                if flock.step().is_break() {
+                    flow = ControlFlow::Break(());
+                }
+                print_events(&mut flock.simulation);
+                calc_time += flock.simulation.last_calc_time;
+            }
+            if let Some(stream) = &mut stream {
+                stream.send(&flocks[0].simulation);
+            }
 
-    #[allow(deprecated)] 
-    let _ = event_loop.run(move |event, window_target| {
+            let overhead_time = step_start.elapsed().as_secs_f64() - calc_time;
+            let simulations: Vec<&Simulation> = flocks.iter().map(|flock| &flock.simulation).collect();
+            let finished = timings.finish_step(calc_time, overhead_time, &simulations);
+            if flow.is_break() || finished.is_break() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        if simulation_rate > 0.0 {
+            println!("Stepping on a separate thread at {} steps per second\n", simulation_rate);
+        } else {
+            println!("Stepping on a separate thread as fast as it goes\n");
+        }
+        (Timings::new(None), Some(background))
+    } else {
+        (timings, None)
+    };
+    let mut published_at = Instant::now();
+
+    // Bird count the + and - keys asked for, applied once before the next step however
+    // many presses arrive in between, and the frames timed since the last change
+    let mut bird_target: Option<usize> = None;
+    let mut count_timing: Option<(Instant, usize)> = None;
+
+    #[allow(deprecated)]
+    event_loop.run(move |event, window_target| {
         match event {
             winit::event::Event::WindowEvent { event, .. } => match event {
                 winit::event::WindowEvent::CloseRequested => window_target.exit(),
 
+                winit::event::WindowEvent::KeyboardInput { event, .. }
+                    if event.state == winit::event::ElementState::Pressed && !event.repeat =>
+                {
+                    use winit::keyboard::KeyCode;
+
+                    if let winit::keyboard::PhysicalKey::Code(code) = event.physical_key {
+                        let bird_count = flocks[0].simulation.birds.len();
+                        match code {
+                            KeyCode::KeyC => print_flock_extent(&flocks[0].simulation.birds),
+                            KeyCode::KeyV => show_perception = !show_perception,
+                            KeyCode::BracketLeft if bird_count > 0 => selected_bird = (selected_bird + bird_count - 1) % bird_count,
+                            KeyCode::BracketRight if bird_count > 0 => selected_bird = (selected_bird + 1) % bird_count,
+                            KeyCode::KeyO => match export_obj(&flocks[0].simulation.birds) {
+                                Ok(path) => println!("Exported {} birds to {}", bird_count, path),
+                                Err(err) => eprintln!("error: {}", err),
+                            },
+                            KeyCode::KeyS => {
+                                let simulations: Vec<&Simulation> = flocks.iter().map(|flock| &flock.simulation).collect();
+                                match export_scene(&simulations) {
+                                    Ok(path) => println!("Saved the current settings to {}", path),
+                                    Err(err) => eprintln!("error: {}", err),
+                                }
+                            }
+                            KeyCode::KeyP => {
+                                bird_renderer.colour_mode = bird_renderer.colour_mode.next();
+                                println!("Colour mode: {:?}", bird_renderer.colour_mode);
+                            }
+                            KeyCode::KeyH => {
+                                bird_renderer.palette = bird_renderer.palette.next();
+                                println!("Depth palette: {:?}", bird_renderer.palette);
+                            }
+                            KeyCode::KeyR => {
+                                bird_renderer.style = bird_renderer.style.next();
+                                println!("Render style: {:?}", bird_renderer.style);
+                            }
+                            KeyCode::KeyX => {
+                                camera.set_projection(camera.projection().next());
+                                println!("Projection: {:?}", camera.projection());
+                            }
+                            KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 => command_flocks(&mut flocks, &background, move |flocks| {
+                                for flock in flocks.iter_mut() {
+                                    let params = &mut flock.simulation.params;
+                                    let enabled = match code {
+                                        KeyCode::Digit1 => &mut params.separation_enabled,
+                                        KeyCode::Digit2 => &mut params.alignment_enabled,
+                                        _ => &mut params.cohesion_enabled,
+                                    };
+                                    *enabled = !*enabled;
+                                }
+                                let params = &flocks[0].simulation.params;
+                                let state = |enabled: bool| if enabled { "on" } else { "off" };
+                                println!(
+                                    "Rules: separation {}, alignment {}, cohesion {}",
+                                    state(params.separation_enabled),
+                                    state(params.alignment_enabled),
+                                    state(params.cohesion_enabled)
+                                );
+                            }),
+                            KeyCode::Digit4
+                            | KeyCode::Digit5
+                            | KeyCode::Digit6
+                            | KeyCode::Digit7
+                            | KeyCode::Digit8
+                            | KeyCode::Digit9 => {
+                                let index = match code {
+                                    KeyCode::Digit4 => 0,
+                                    KeyCode::Digit5 => 1,
+                                    KeyCode::Digit6 => 2,
+                                    KeyCode::Digit7 => 3,
+                                    KeyCode::Digit8 => 4,
+                                    _ => 5,
+                                };
+                                let (name, weights) = WEIGHT_PRESETS[index];
+                                command_flocks(&mut flocks, &background, move |flocks| {
+                                    for flock in flocks.iter_mut() {
+                                        flock.simulation.params.set_rule_weights(weights);
+                                    }
+                                });
+                                println!(
+                                    "Preset {}: separation {}, alignment {}, cohesion {}",
+                                    name, weights[0], weights[1], weights[2]
+                                );
+                            }
+                            KeyCode::KeyG => {
+                                command_flocks(&mut flocks, &background, |flocks| {
+                                    for flock in flocks.iter_mut() {
+                                        flock.simulation.trigger_gust();
+                                    }
+                                });
+                                println!("Triggered a wind gust");
+                            }
+                            KeyCode::KeyF => {
+                                command_flocks(&mut flocks, &background, |flocks| {
+                                    for flock in flocks.iter_mut() {
+                                        let centroid = flock_metrics(&flock.simulation.birds).centroid;
+                                        flock.simulation.trigger_flash(centroid);
+                                    }
+                                });
+                                println!("Set off a flash");
+                            }
+                            KeyCode::KeyB => command_flocks(&mut flocks, &background, |flocks| {
+                                for flock in flocks.iter_mut() {
+                                    let arrivals = flock.simulation.params.arrivals.clone();
+                                    flock.simulation.add_birds(arrivals.count, &arrivals.spawn);
+                                }
+                                let simulation = &flocks[0].simulation;
+                                println!("Added {} birds, {} in the flock", simulation.params.arrivals.count, simulation.birds.len());
+                            }),
+                            KeyCode::Equal | KeyCode::NumpadAdd | KeyCode::Minus | KeyCode::NumpadSubtract => {
+                                let current = bird_target.unwrap_or(bird_count);
+                                bird_target = Some(if matches!(code, KeyCode::Equal | KeyCode::NumpadAdd) {
+                                    ((current as f64 * BIRD_COUNT_FACTOR).round() as usize).max(current + 1)
+                                } else {
+                                    (current as f64 / BIRD_COUNT_FACTOR).round() as usize
+                                });
+                            }
+                            KeyCode::KeyL => match &args.scene {
+                                Some(path) => {
+                                    let path = path.clone();
+                                    command_flocks(&mut flocks, &background, move |flocks| reload_scene(&path, flocks));
+                                }
+                                None => println!("No scene file to reload, start with --scene PATH"),
+                            },
+                            KeyCode::KeyT => {
+                                show_tails = !show_tails;
+                                println!("Tails {}", if show_tails { "on" } else { "off" });
+                            }
+                            KeyCode::KeyD => {
+                                let demo = demo.clone();
+                                command_flocks(&mut flocks, &background, move |flocks| {
+                                    let running = flocks.iter().any(|flock| flock.simulation.demo.is_some());
+                                    for flock in flocks.iter_mut() {
+                                        flock.simulation.demo = if running { None } else { Some(demo.clone()) };
+                                    }
+                                    println!("Demo {}", if running { "stopped" } else { "started" });
+                                });
+                            }
+                            KeyCode::KeyK => {
+                                show_octree = !show_octree;
+                                if show_octree {
+                                    let octree = Octree::new(&flocks[0].simulation.birds);
+                                    println!("Octree overlay on ({} leaves, {} levels deep)", octree.leaves().count(), octree.depth());
+                                } else {
+                                    println!("Octree overlay off");
+                                }
+                            }
+                            KeyCode::KeyM => {
+                                show_centroid = !show_centroid;
+                                println!("Centroid marker {}", if show_centroid { "on" } else { "off" });
+                            }
+                            KeyCode::KeyI => {
+                                interpolate = !interpolate;
+                                println!("Interpolation {}", if interpolate { "on" } else { "off" });
+                            }
+                            _ => (),
+                        }
+                        let selectable = selected_bird < bird_count;
+                        if show_perception && selectable && matches!(code, KeyCode::KeyV | KeyCode::BracketLeft | KeyCode::BracketRight) {
+                            println!(
+                                "Selected bird {} ({} neighbors)",
+                                selected_bird,
+                                flocks[0].simulation.neighbors_of(selected_bird).len()
+                            );
+                        }
+                    }
+                },
+
+                winit::event::WindowEvent::CursorMoved { position, .. } => cursor = (position.x, position.y),
+
+                winit::event::WindowEvent::MouseInput {
+                    state: winit::event::ElementState::Pressed,
+                    button: winit::event::MouseButton::Left,
+                    ..
+                } => {
+                    // Shock whichever flock's region was clicked
+                    let window_size = window.inner_size().into();
+                    let clicked = flocks.iter().enumerate().find_map(|(index, flock)| {
+                        camera.set_offset(flock.offset);
+                        let centre = camera.cursor_to_world(cursor, window_size).map(|centre| centre.cast::<Real>())?;
+                        (centre.amax() <= flock.simulation.params.extent).then_some((index, centre))
+                    });
+                    if let Some((index, centre)) = clicked {
+                        command_flocks(&mut flocks, &background, move |flocks| flocks[index].simulation.trigger_shockwave(centre));
+                    }
+                },
+
                 winit::event::WindowEvent::Resized(window_size) => {
                     display.resize(window_size.into());
+                    camera.set_aspect_ratio(aspect_ratio(window_size.into()));
                 },
 
                 winit::event::WindowEvent::RedrawRequested => {
-                    if SHOW_TIMES && step_count == 0 {
-                        perf_start = Instant::now();
+                    let frame_start = Instant::now();
+
+                    // The simulation thread's latest copy, exiting once it has stopped
+                    if let Some(background) = &background {
+                        let finished = background.is_finished();
+                        if let Some((copy, at)) = background.take_published() {
+                            flocks = copy;
+                            published_at = at;
+                        }
+                        if finished {
+                            window_target.exit();
+                        }
                     }
-    
-                    if total_steps == 0 {
-                        summary_start = Instant::now();
+
+                    // One step per frame, or as many as are due at the fixed rate, or none
+                    // when they are taken on the simulation thread
+                    let steps = if background.is_some() {
+                        0
+                    } else if simulation_rate > 0.0 && video.is_none() {
+                        accumulator += last_frame.elapsed().as_secs_f64();
+                        let due = (accumulator * simulation_rate) as usize;
+                        accumulator -= due as f64 / simulation_rate;
+                        if due > MAX_STEPS_PER_FRAME {
+                            accumulator = 0.0;
+                        }
+                        due.min(MAX_STEPS_PER_FRAME)
+                    } else {
+                        1
+                    };
+                    let steps = steps.min(timings.steps_remaining());
+                    last_frame = frame_start;
+
+                    if let Some((start, frames)) = &mut count_timing {
+                        *frames += 1;
+                        if *frames == BIRD_COUNT_FPS_FRAMES {
+                            let fps = BIRD_COUNT_FPS_FRAMES as f64 / start.elapsed().as_secs_f64();
+                            println!("{} birds: {:.0} FPS", flocks[0].simulation.birds.len(), fps);
+                            count_timing = None;
+                        }
+                    }
+                    if let Some(target) = bird_target.take() {
+                        let before = flocks[0].simulation.birds.len();
+                        command_flocks(&mut flocks, &background, move |flocks| {
+                            for flock in flocks.iter_mut() {
+                                flock.set_bird_count(target);
+                            }
+                        });
+                        println!("Bird count {} -> {}, timing the next {} frames", before, target, BIRD_COUNT_FPS_FRAMES);
+                        count_timing = Some((Instant::now(), 0));
                     }
-    
-                    let step_start = Instant::now();
 
                     // --- Flocking update (parallel) ---
-                    let birds_snapshot = birds.clone();
+                    let mut calc_times = Vec::with_capacity(steps);
+                    for _ in 0..steps {
+                        timings.start_step();
+                        let mut calc_time = 0.0;
+                        for flock in &mut flocks {
+                            if flock.step().is_break() {
+                                window_target.exit();
+                            }
+                            print_events(&mut flock.simulation);
+                            calc_time += flock.simulation.last_calc_time;
+                        }
+                        if let Some(stream) = &mut stream {
+                            stream.send(&flocks[0].simulation);
+                        }
+                        calc_times.push(calc_time);
+                    }
 
-                    let calc_start = Instant::now();
+                    // How far into the next step this frame falls, from the fixed-rate clock
+                    // or the time since the simulation thread last published
+                    let alpha = if simulation_rate > 0.0 && interpolate && video.is_none() {
+                        let fraction = match background {
+                            Some(_) => (published_at.elapsed().as_secs_f64() * simulation_rate).min(1.0),
+                            None => accumulator * simulation_rate,
+                        };
+                        Some(fraction as Real)
+                    } else {
+                        None
+                    };
 
-                    birds.par_iter_mut().enumerate().for_each(|(i, bird)| {
-                        let mut separation = Vector3::zeros();
-                        let mut alignment = Vector3::zeros();
-                        let mut cohesion = Vector3::zeros();
-                        let mut total = 0;
+                    if SHOW_CLOCK {
+                        let simulation = &flocks[0].simulation;
+                        window.set_title(&format!(
+                            "Bird Flocking Simulation | step {} | time {:.1}",
+                            simulation.step_index, simulation.time
+                        ));
+                    }
 
-                        for other in &birds_snapshot {
-                            let distance = (bird.position - other.position).norm();
-                            if distance > 0.0 && distance < PERCEPTION_RADIUS {
+                    // --- Rendering ---
+                    if SHOW_VISUALS {
+                        let mut target = display.draw();
+                        // A failed draw ends the frame early, but it still has to be finished
+                        let drawn = (|| -> Result<(), BirdflockError> {
 
-                                separation += (bird.position - other.position) / distance;
-                                alignment += other.velocity;
-                                cohesion += other.position;
+                            if SHOW_HEATMAP {
+                                // Fade the accumulated frames instead of clearing, then copy to the screen
+                                let dimensions = display.get_framebuffer_dimensions();
+                                let heatmap_texture = match heatmap.take() {
+                                    Some(texture) if texture.dimensions() == dimensions => heatmap.insert(texture),
+                                    _ => heatmap.insert(new_heatmap(&display, dimensions)?),
+                                };
+                                let mut heatmap_target = heatmap_texture.as_surface();
 
-                                total += 1;
+                                heatmap_target.draw(&quad_buffer, quad_indices, &fade_program, &uniform! { fade: HEATMAP_FADE }, &fade_params)
+                                    .map_err(|err| BirdflockError::GlDraw(format!("heatmap fade: {}", err)))?;
+                                for flock in &flocks {
+                                    let (birds, predators) = flock.drawn_birds(alpha);
+                                    camera.set_offset(flock.offset);
+                                    bird_renderer.layers = flock.simulation.params.layers.count;
+                                    let scale = flock.simulation.params.world_scale as f32;
+                                    bird_renderer.draw(&mut heatmap_target, &birds, &mut camera, false, None, scale)?;
+                                    bird_renderer.draw(&mut heatmap_target, &predators, &mut camera, false, Some(PREDATOR_COLOUR), PREDATOR_SCALE * scale)?;
+                                }
+                                heatmap_target.fill(&target, glium::uniforms::MagnifySamplerFilter::Nearest);
+                            } else {
+                                target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+                                if SHOW_GROUND || SHOW_SHADOWS {
+                                    for flock in &flocks {
+                                        camera.set_offset(flock.offset);
+                                        let extent = flock.simulation.params.extent;
+                                        let height = (GROUND_HEIGHT - 1.0) * extent;
+                                        if SHOW_GROUND {
+                                            let plane = Matrix4::new_translation(&Vector3::new(0.0, height, 0.0))
+                                                * Matrix4::new_nonuniform_scaling(&Vector3::new(extent, 1.0, extent));
+                                            line_renderer.draw(&mut target, &ground, plane, &mut camera, GROUND_COLOUR)?;
+                                        }
+                                        if SHOW_SHADOWS {
+                                            bird_renderer.draw_shadows(&mut target, &flock.drawn_birds(alpha).0, &mut camera, height as f32)?;
+                                        }
+                                    }
+                                }
+                                for flock in &flocks {
+                                    let (birds, predators) = flock.drawn_birds(alpha);
+                                    camera.set_offset(flock.offset);
+                                    bird_renderer.layers = flock.simulation.params.layers.count;
+                                    let scale = flock.simulation.params.world_scale as f32;
+                                    bird_renderer.draw(&mut target, &birds, &mut camera, true, None, scale)?;
+                                    bird_renderer.draw(&mut target, &predators, &mut camera, true, Some(PREDATOR_COLOUR), PREDATOR_SCALE * scale)?;
+                                    if show_tails {
+                                        tail_renderer.draw(&mut target, &birds, &flock.tails, flock.simulation.params.extent, &mut camera)?;
+                                    }
+                                }
                             }
-                        }
 
-                        if total > 0 {
-                            // Separation
-                            separation /= total as f32;
-                            if separation.norm() > 0.0 {
-                                separation = separation.normalize() * MAX_SPEED - bird.velocity;
-                                separation = limit_vec(separation, MAX_FORCE);
+                            for flock in &flocks {
+                                camera.set_offset(flock.offset);
+                                for obstacle in &flock.simulation.obstacles {
+                                    let sphere = Matrix4::new_translation(&obstacle.position) * Matrix4::new_scaling(obstacle.radius);
+                                    line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, OBSTACLE_COLOUR)?;
+                                }
+                                if let Some(path) = &flock.simulation.waypoints {
+                                    for (i, waypoint) in path.waypoints().iter().enumerate() {
+                                        let (radius, colour) = if i == path.active() {
+                                            (flock.simulation.params.waypoint_threshold, ACTIVE_WAYPOINT_COLOUR)
+                                        } else {
+                                            (WAYPOINT_SIZE, WAYPOINT_COLOUR)
+                                        };
+                                        let sphere = Matrix4::new_translation(waypoint) * Matrix4::new_scaling(radius);
+                                        line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, colour)?;
+                                    }
+                                }
+                                for shockwave in &flock.simulation.shockwaves {
+                                    let sphere = Matrix4::new_translation(&shockwave.centre) * Matrix4::new_scaling(shockwave.radius());
+                                    line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, SHOCKWAVE_COLOUR)?;
+                                }
+                                for flash in &flock.simulation.flashes {
+                                    let sphere = Matrix4::new_translation(&flash.centre) * Matrix4::new_scaling(flash.radius());
+                                    line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, FLASH_COLOUR)?;
+                                }
                             }
 
-                            // Alignment
-                            alignment /= total as f32;
-                            if alignment.norm() > 0.0 {
-                                alignment = alignment.normalize() * MAX_SPEED - bird.velocity;
-                                alignment = limit_vec(alignment, MAX_FORCE);
+                            for flock in &flocks {
+                                let params = &flock.simulation.params;
+                                if params.boundary != Boundary::Box {
+                                    camera.set_offset(flock.offset);
+                                    let sphere = Matrix4::new_scaling(params.boundary_radius);
+                                    line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, BOUNDARY_COLOUR)?;
+                                }
                             }
 
-                            // Cohesion
-                            cohesion /= total as f32;
-                            cohesion = cohesion - bird.position;
-                            if cohesion.norm() > 0.0 {
-                                cohesion = cohesion.normalize() * MAX_SPEED - bird.velocity;
-                                cohesion = limit_vec(cohesion, MAX_FORCE);
+                            if flocks.len() > 1 {
+                                // Box each flock's region so they read as separate experiments
+                                for flock in &flocks {
+                                    camera.set_offset(flock.offset);
+                                    line_renderer.draw(&mut target, &cube_lines, Matrix4::new_scaling(flock.simulation.params.extent), &mut camera, FLOCK_BOX_COLOUR)?;
+                                }
                             }
-                        }
-                        
-                        // Combine with weights
-                        bird.acceleration =
-                            SEPARATION_WEIGHT * separation +
-                            ALIGNMENT_WEIGHT * alignment +
-                            COHESION_WEIGHT * cohesion;
-                        
-                        // Velocity update and limit speed
-                        bird.velocity += bird.acceleration;
-                        if bird.velocity.norm() > MAX_SPEED {
-                            bird.velocity = bird.velocity.normalize() * MAX_SPEED;
-                        }
-                        
-                        // Position update
-                        bird.position += bird.velocity;
-                        bird.position = wraparound(bird.position);
 
-                        if SHOW_POSITIONS {
-                            println!(
-                                "Bird {}: pos={:?} vel={:?} sep={:?} ali={:?} coh={:?}",
-                                i, bird.position, bird.velocity, separation, alignment, cohesion
-                            );
-                        }
-                    });
+                            if show_octree {
+                                // The tree the octree search would build this step, whether or not it's in use
+                                let octree = Octree::new(&flocks[0].drawn_birds(alpha).0);
+                                camera.set_offset(flocks[0].offset);
+                                let lines = octree_lines(&octree);
+                                if let Err(err) = octree_overlay.draw(&display, &mut target, &line_renderer, &lines, &mut camera, OCTREE_COLOUR) {
+                                    eprintln!("error: {}, hiding the octree", err);
+                                    show_octree = false;
+                                }
+                            }
 
-                    let calc_time = calc_start.elapsed().as_secs_f64();
-                    total_calc_time += calc_time;
-                    cumulative_calc_time += calc_time;
+                            if show_centroid {
+                                for (flock, overlay) in flocks.iter().zip(&mut centroid_overlays) {
+                                    let size = CENTROID_MARKER_SIZE * flock.simulation.params.world_scale;
+                                    let marker = centroid_marker_lines(&flock.drawn_birds(alpha).0, size);
+                                    camera.set_offset(flock.offset);
+                                    if let Err(err) = overlay.draw(&display, &mut target, &line_renderer, &marker, &mut camera, CENTROID_COLOUR) {
+                                        eprintln!("error: {}, hiding the centroid marker", err);
+                                        show_centroid = false;
+                                        break;
+                                    }
+                                }
+                            }
 
-                    // --- Rendering ---
-                    if SHOW_VISUALS {
-                        let mut target = display.draw();
-                        target.clear_color(0.0, 0.0, 0.0, 1.0);
-
-                        let perspective = Perspective3::new(1.0, std::f32::consts::FRAC_PI_3, 0.1, 100.0);
-                        let projection_matrix: [[f32; 4]; 4] = *perspective.as_matrix().as_ref();
-                        let eye = Point3::new(0.0, 0.0, POV_DISTANCE);
-                        let look = Point3::origin();
-                        let up = Vector3::y();
-                        let view_matrix: [[f32; 4]; 4] = *Matrix4::look_at_rh(&eye, &look, &up).as_ref();
-
-                        for bird in &birds {
-                            let model_matrix = [
-                                [1.0, 0.0, 0.0, 0.0],
-                                [0.0, 1.0, 0.0, 0.0],
-                                [0.0, 0.0, 1.0, 0.0],
-                                [bird.position.x, bird.position.y, bird.position.z, 1.0],
-                            ];
-                            let uniforms = uniform! {
-                                model: model_matrix,
-                                view: view_matrix,
-                                projection: projection_matrix,
-                                depth: bird.position.z, // Pass z position
-                            };
-                            target.draw(&vertex_buffer, &indices, &program, &uniforms, &Default::default()).unwrap();
-                        }
+                            // Removals can leave the selection past the end of the flock
+                            selected_bird = selected_bird.min(flocks[0].simulation.birds.len().saturating_sub(1));
+                            if show_perception && selected_bird < flocks[0].simulation.birds.len() {
+                                // Highlight the selected bird, its neighbors and its perception sphere
+                                let simulation = &flocks[0].simulation;
+                                let (birds, _) = flocks[0].drawn_birds(alpha);
+                                camera.set_offset(flocks[0].offset);
+                                let selected = &birds[selected_bird];
+                                let neighbors: Vec<Bird> = simulation
+                                    .neighbors_of(selected_bird)
+                                    .into_iter()
+                                    .map(|i| birds[i].clone())
+                                    .collect();
+                                bird_renderer.draw(&mut target, &neighbors, &mut camera, false, Some(NEIGHBOR_COLOUR), simulation.params.world_scale as f32)?;
+                                bird_renderer.draw(&mut target, std::slice::from_ref(selected), &mut camera, false, Some(SELECTED_COLOUR), 1.5 * simulation.params.world_scale as f32)?;
 
-                        target.finish().unwrap();
-                    }
+                                let sphere = Matrix4::new_translation(&selected.position)
+                                    * Matrix4::new_scaling(simulation.perception_radius_of(selected_bird));
+                                line_renderer.draw(&mut target, &sphere_lines, sphere, &mut camera, PERCEPTION_COLOUR)?;
+                            }
 
-                    let overhead_time = step_start.elapsed().as_secs_f64() - calc_time;
-                    total_overhead_time += overhead_time;
-                    cumulative_overhead_time += overhead_time;
-                    
-                    if SHOW_TIMES {
-                        step_count += 1;
-                        total_steps += 1;
-    
-                        // In the RedrawRequested event handler, replace the print sections:
-
-                        if step_count % SHOWTIMES_EVERY == 0 && PRINT_EVERY {
-                            let elapsed = perf_start.elapsed();
-                            let avg_time_per_step = elapsed.as_secs_f64() / SHOWTIMES_EVERY as f64;
-                            let fps = 1.0 / avg_time_per_step;
-                            
-                            let avg_calc_time = total_calc_time / SHOWTIMES_EVERY as f64;
-                            let avg_overhead = total_overhead_time / SHOWTIMES_EVERY as f64;
+                            if let Some(video) = &mut video {
+                                if let Err(err) = video.capture(&target) {
+                                    eprintln!("error: {}, stopping recording after {} frames", err, video.frames);
+                                    window_target.exit();
+                                }
+                            }
 
-                            println!(
-                                "Simulated steps {}-{} in {:.3} seconds ({:.3} ms/step, {:.2} FPS)",
-                                total_steps - SHOWTIMES_EVERY,
-                                total_steps,
-                                elapsed.as_secs_f64(),
-                                avg_time_per_step * 1000.0,
-                                fps
-                            );
-                            println!(
-                                "Calculation: {:.3} ms | Overhead: {:.3} ms | Total: {:.3} ms",
-                                avg_calc_time * 1000.0,
-                                avg_overhead * 1000.0,
-                                (avg_calc_time + avg_overhead) * 1000.0
-                            );
-                            
-                            // Reset counters for the next batch
-                            total_calc_time = 0.0;
-                            total_overhead_time = 0.0;
-                            perf_start = Instant::now();
+                            Ok(())
+                        })();
+                        let finished = target.finish().map_err(|err| BirdflockError::GlDraw(format!("cannot show the frame: {}", err)));
+                        if let Err(err) = drawn.and(finished) {
+                            eprintln!("error: {}", err);
+                            window_target.exit();
                         }
+                    }
 
-                        if total_steps % SUMMARY_EVERY == 0 {
-                            let summary_elapsed = summary_start.elapsed();
-                            let avg_fps = SUMMARY_EVERY as f64 / summary_elapsed.as_secs_f64();
-
-                            let avg_calc = (cumulative_calc_time / SUMMARY_EVERY as f64) * 1000.0;
-                            let avg_overhead = (cumulative_overhead_time / SUMMARY_EVERY as f64) * 1000.0;
-
-                            println!(
-                                "\n\nSimulated {} steps in {:.3} seconds at {:.0} FPS",
-                                SUMMARY_EVERY,
-                                summary_elapsed.as_secs_f64(),
-                                avg_fps
-                            );
-                            println!(
-                                "Average Calculation: {:.3} ms | Average Overhead: {:.3} ms",
-                                avg_calc,
-                                avg_overhead
-                            );
-                            println!("\nSimulation complete. Exiting.");
+                    // Rendering overhead is shared between the steps taken this frame
+                    let calc_total: f64 = calc_times.iter().sum();
+                    let overhead_time = frame_start.elapsed().as_secs_f64() - calc_total;
+                    for calc_time in calc_times {
+                        let overhead_share = overhead_time / steps as f64;
+                        let simulations: Vec<&Simulation> = flocks.iter().map(|flock| &flock.simulation).collect();
+                        if timings.finish_step(calc_time, overhead_share, &simulations).is_break() {
                             window_target.exit();
                         }
                     }
@@ -357,5 +1355,124 @@ fn main() {
             },
             _ => (),
         };
-    });
+    })
+    .map_err(|err| BirdflockError::GlInit(format!("the event loop failed: {}", err)))
+}
+
+fn main() -> Result<(), BirdflockError> {
+    let args = parse_args();
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|error| BirdflockError::thread_pool(threads, error))?;
+    }
+
+    if let Some(path) = &args.write_golden {
+        write_golden(path);
+    }
+    if args.benchmark_neighbors {
+        run_neighbor_benchmark();
+    }
+    if let Some(path) = &args.sweep {
+        return run_sweep(path);
+    }
+
+    if args.video_size.is_some() && args.render_video.is_none() {
+        usage_error("--video-size needs --render-video");
+    }
+    if args.render_video.is_some() && (args.headless || !cfg!(feature = "render")) {
+        usage_error("--render-video draws through the window, so needs the render feature and no --headless");
+    }
+    if args.sim_thread && (args.headless || !cfg!(feature = "render")) {
+        usage_error("--sim-thread steps the flocks beside the window, so needs the render feature and no --headless");
+    }
+    if args.sim_thread && args.render_video.is_some() {
+        usage_error("--render-video records one frame per step, so can't run with --sim-thread");
+    }
+    if args.stream_velocities && args.stream_addr.is_none() {
+        usage_error("--stream-velocities needs --stream-addr");
+    }
+
+    let mut simulations = build_simulations(&args)?;
+    if SHOW_LOAD_BALANCE {
+        for simulation in &mut simulations {
+            simulation.thread_load = Some(ThreadLoad::new(rayon::current_num_threads()));
+        }
+    }
+    if let Some(target) = args.target_order {
+        for simulation in &mut simulations {
+            simulation.set_controller(order_controller(target, ORDER_CONTROLLER_GAIN));
+        }
+    }
+    if args.demo && simulations[0].demo.is_none() {
+        for simulation in &mut simulations {
+            simulation.demo = Some(Demo::built_in());
+        }
+    }
+    let simulation = &simulations[0];
+
+    println!(
+        "\n\nStarting simulation with {} birds using Rayon ({} threads)",
+        NUM_BIRDS,
+        rayon::current_num_threads()
+    );
+    if simulations.len() > 1 {
+        println!("Running {} flocks side by side", simulations.len());
+    }
+    if !simulation.predators.is_empty() {
+        println!("Hunting with {} predators", simulation.predators.len());
+    }
+    if let Some(seed) = args.seed {
+        println!("Using seed {}", seed);
+    }
+    if let Some(target) = args.target_order {
+        println!("Holding the order parameter at {} through the cohesion weight", target);
+    }
+    if let Some(demo) = &simulation.demo {
+        println!("Running a {}-stage demo", demo.stages().len());
+    }
+
+    if args.analyse_grid {
+        print_grid_analysis(simulation);
+    }
+
+    // The first flock's birds after every step, to a client listening at the address
+    let stream = match &args.stream_addr {
+        Some(address) => {
+            let stream = PositionStream::connect(address, args.stream_velocities)?;
+            let what = if args.stream_velocities { "positions and velocities" } else { "positions" };
+            println!("Streaming bird {} to {}", what, address);
+            Some(stream)
+        }
+        None => None,
+    };
+
+    let exit_after = args.exit_after_steps.unwrap_or(EXIT_AFTER_STEPS);
+    let mut timings = Timings::new(if exit_after > 0 { Some(exit_after) } else { None });
+    if args.measure_speedup {
+        let single_thread_time = single_thread_step_time(&simulations)?;
+        println!("Single-threaded step: {:.3} ms", single_thread_time * 1000.0);
+        timings.single_thread_time = Some(single_thread_time);
+    }
+
+    #[cfg(feature = "render")]
+    if !args.headless {
+        if SHOW_VISUALS {
+            println!("Visuals enabled.\n");
+        } else {
+            println!("Visuals disabled.\n");
+        }
+        println!("Controls:");
+        for (key, action) in CONTROLS {
+            println!("  {:<6} {}", key, action);
+        }
+        println!();
+
+        return run_windowed(simulations, timings, args, stream);
+    }
+
+    println!("Running headless.\n");
+    run_headless(simulations, timings, stream);
+    Ok(())
 }
@@ -0,0 +1,91 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The system allocator, counting allocations and the bytes they request, and tracking
+// the bytes in use and their peak, so the summary can show how much a step allocates.
+// The binary installs it as the global allocator with the `alloc-stats` feature
+pub struct CountingAllocator {
+    allocations: AtomicUsize,
+    allocated: AtomicUsize,
+    live: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+// The counts since the program started
+#[derive(Clone, Copy, Default)]
+pub struct AllocationStats {
+    pub allocations: usize,
+    pub allocated: usize, // bytes requested, including by reallocations that grew
+    pub live: usize,      // bytes currently allocated
+    pub peak: usize,      // most bytes allocated at once
+}
+
+impl CountingAllocator {
+    pub const fn new() -> Self {
+        CountingAllocator {
+            allocations: AtomicUsize::new(0),
+            allocated: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> AllocationStats {
+        AllocationStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            allocated: self.allocated.load(Ordering::Relaxed),
+            live: self.live.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+        }
+    }
+
+    fn grew(&self, bytes: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.allocated.fetch_add(bytes, Ordering::Relaxed);
+        let live = self.live.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+impl Default for CountingAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.live.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    // Counted as an allocation of the extra bytes when it grows, since that is what
+    // a Vec pushing past its capacity costs, and as a free of the difference when it shrinks
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            if new_size >= layout.size() {
+                self.grew(new_size - layout.size());
+            } else {
+                self.live.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,111 @@
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::bird::Bird;
+use crate::real::Real;
+
+pub const METRICS_CHUNK: usize = 1024;      // birds per partial sum in the metrics reduction
+pub const SATURATION_TOLERANCE: Real = 1e-3; // fraction of max speed counted as at the clamp
+
+// Flock-wide measurements for reporting
+pub struct FlockMetrics {
+    pub centroid: Vector3<Real>,
+    pub order_parameter: Real, // length of the mean heading, 0 = disordered, 1 = aligned
+    pub mean_speed: Real,
+}
+
+// Partial sums are taken over fixed-size chunks in parallel and then combined
+// in chunk order, so the result depends only on the bird order and not on how
+// rayon schedules the work. A plain par_iter().sum() is slightly faster, but it
+// combines partials in whatever order work stealing produces, so the low bits
+// of the result vary from run to run even with a fixed seed.
+pub fn flock_metrics(birds: &[Bird]) -> FlockMetrics {
+    let partials: Vec<(Vector3<Real>, Vector3<Real>, Real)> = birds
+        .par_chunks(METRICS_CHUNK)
+        .map(|chunk| {
+            let mut position_sum = Vector3::zeros();
+            let mut heading_sum = Vector3::zeros();
+            let mut speed_sum = 0.0;
+            for bird in chunk {
+                position_sum += bird.position;
+                let speed = bird.velocity.norm();
+                if speed > 0.0 {
+                    heading_sum += bird.velocity / speed;
+                }
+                speed_sum += speed;
+            }
+            (position_sum, heading_sum, speed_sum)
+        })
+        .collect();
+
+    let mut position_sum = Vector3::zeros();
+    let mut heading_sum = Vector3::zeros();
+    let mut speed_sum = 0.0;
+    for (positions, headings, speeds) in &partials {
+        position_sum += positions;
+        heading_sum += headings;
+        speed_sum += speeds;
+    }
+
+    let count = birds.len().max(1) as Real;
+    FlockMetrics {
+        centroid: position_sum / count,
+        order_parameter: heading_sum.norm() / count,
+        mean_speed: speed_sum / count,
+    }
+}
+
+// Aggregate kinematic quantities, treating every bird as unit mass
+pub struct FlockEnergy {
+    pub kinetic_energy: Real,    // sum of 0.5 * v^2
+    pub momentum: Vector3<Real>, // sum of velocities
+    pub saturated_fraction: Real, // fraction of birds at the max speed clamp
+}
+
+// Single pass over the birds, reduced in chunk order like flock_metrics
+pub fn flock_energy(birds: &[Bird], max_speed: Real) -> FlockEnergy {
+    let saturated_speed = max_speed * (1.0 - SATURATION_TOLERANCE);
+    let partials: Vec<(Real, Vector3<Real>, usize)> = birds
+        .par_chunks(METRICS_CHUNK)
+        .map(|chunk| {
+            let mut energy_sum = 0.0;
+            let mut momentum_sum = Vector3::zeros();
+            let mut saturated = 0;
+            for bird in chunk {
+                let speed_squared = bird.velocity.norm_squared();
+                energy_sum += 0.5 * speed_squared;
+                momentum_sum += bird.velocity;
+                if speed_squared >= saturated_speed * saturated_speed {
+                    saturated += 1;
+                }
+            }
+            (energy_sum, momentum_sum, saturated)
+        })
+        .collect();
+
+    let mut kinetic_energy = 0.0;
+    let mut momentum = Vector3::zeros();
+    let mut saturated = 0;
+    for (energy, chunk_momentum, chunk_saturated) in &partials {
+        kinetic_energy += energy;
+        momentum += chunk_momentum;
+        saturated += chunk_saturated;
+    }
+
+    FlockEnergy {
+        kinetic_energy,
+        momentum,
+        saturated_fraction: saturated as Real / birds.len().max(1) as Real,
+    }
+}
+
+// Axis-aligned bounding box of the flock as (min, max) corners
+pub fn flock_extent(birds: &[Bird]) -> (Vector3<Real>, Vector3<Real>) {
+    let mut min = Vector3::repeat(Real::INFINITY);
+    let mut max = Vector3::repeat(Real::NEG_INFINITY);
+    for bird in birds {
+        min = min.inf(&bird.position);
+        max = max.sup(&bird.position);
+    }
+    (min, max)
+}
@@ -0,0 +1,66 @@
+use nalgebra::Vector3;
+
+use crate::bird::Bird;
+use crate::flocking::steer;
+use crate::params::SimulationParams;
+use crate::real::Real;
+
+// Sphere the flock steers around, static or moving under gravity and bouncing off the bounds
+#[derive(Clone)]
+pub struct Obstacle {
+    pub position: Vector3<Real>,
+    pub velocity: Vector3<Real>,
+    pub radius: Real,
+    pub moving: bool,
+}
+
+impl Obstacle {
+    // The same obstacle in a world `factor` times the size
+    pub fn scaled(&self, factor: Real) -> Self {
+        Obstacle {
+            position: self.position * factor,
+            velocity: self.velocity * factor,
+            radius: self.radius * factor,
+            moving: self.moving,
+        }
+    }
+
+    // Fall under `gravity` and bounce off the walls of the simulation space, +/- `extent`
+    pub fn update(&mut self, gravity: Real, extent: Real, dt: Real) {
+        if !self.moving {
+            return;
+        }
+        self.velocity.y -= gravity * dt;
+        self.position += self.velocity * dt;
+
+        for axis in 0..3 {
+            let (low, high) = (-extent + self.radius, extent - self.radius);
+            if self.position[axis] < low {
+                self.position[axis] = low;
+                self.velocity[axis] = self.velocity[axis].abs();
+            } else if self.position[axis] > high {
+                self.position[axis] = high;
+                self.velocity[axis] = -self.velocity[axis].abs();
+            }
+        }
+    }
+}
+
+// Steering away from obstacles, both where they are and where they will be after the
+// lookahead time, so birds part ahead of a moving sphere and escape one they are inside
+pub fn obstacle_force(bird: &Bird, obstacles: &[Obstacle], params: &SimulationParams) -> Vector3<Real> {
+    let mut away = Vector3::zeros();
+    for obstacle in obstacles {
+        let predicted = obstacle.position + obstacle.velocity * params.obstacle_lookahead;
+        for centre in [obstacle.position, predicted] {
+            let offset = bird.position - centre;
+            let distance = offset.norm();
+            let reach = obstacle.radius + params.obstacle_margin;
+            if distance > 0.0 && distance < reach {
+                // Stronger the deeper the bird is, at least 1 once inside the sphere
+                away += offset / distance * (reach - distance) / params.obstacle_margin.max(Real::EPSILON);
+            }
+        }
+    }
+    steer(away, bird.velocity, params.max_speed, params.max_force)
+}
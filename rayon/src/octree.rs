@@ -0,0 +1,225 @@
+use nalgebra::Vector3;
+
+use crate::bird::Bird;
+use crate::flocking::flocking_forces;
+use crate::params::SimulationParams;
+use crate::real::Real;
+
+pub const OCTREE_LEAF_BIRDS: usize = 16; // a node holding more than this is split into octants
+pub const OCTREE_MAX_DEPTH:  usize = 10; // so birds at the same point don't split forever
+
+// Octree over the flock's bounding cube, splitting only where birds crowd together,
+// so a dense knot gets small cells without the thin surroundings paying for them
+// the way a fine uniform grid would
+pub struct Octree {
+    nodes: Vec<OctreeNode>,
+    indices: Vec<usize>, // indices[node.start..node.end] are the birds under a node
+}
+
+#[derive(Clone, Copy)]
+pub struct OctreeNode {
+    pub min: Vector3<Real>,  // lowest corner of the node's cube
+    pub size: Real,          // edge length
+    children: Option<usize>, // first of eight consecutive nodes, None for a leaf
+    start: usize,
+    end: usize,
+}
+
+impl OctreeNode {
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_none()
+    }
+
+    pub fn count(&self) -> usize {
+        self.end - self.start
+    }
+
+    // Distance from `position` to the nearest point of the cube, 0 inside it
+    fn distance(&self, position: Vector3<Real>) -> Real {
+        let mut squared: Real = 0.0;
+        for axis in 0..3 {
+            let outside = (self.min[axis] - position[axis]).max(position[axis] - (self.min[axis] + self.size)).max(0.0);
+            squared += outside * outside;
+        }
+        squared.sqrt()
+    }
+}
+
+impl Octree {
+    pub fn new(birds: &[Bird]) -> Self {
+        let mut min = Vector3::repeat(Real::INFINITY);
+        let mut max = Vector3::repeat(Real::NEG_INFINITY);
+        for bird in birds {
+            min = min.inf(&bird.position);
+            max = max.sup(&bird.position);
+        }
+        let size = if birds.is_empty() { 1.0 } else { (max - min).max().max(Real::EPSILON) * 1.001 };
+        let root = OctreeNode {
+            min: if birds.is_empty() { Vector3::zeros() } else { min },
+            size,
+            children: None,
+            start: 0,
+            end: birds.len(),
+        };
+
+        let mut octree = Octree { nodes: vec![root], indices: (0..birds.len()).collect() };
+        octree.split(0, birds, 0);
+        octree
+    }
+
+    // Sort a crowded node's birds into its octants, keeping their order within each
+    fn split(&mut self, node: usize, birds: &[Bird], depth: usize) {
+        let OctreeNode { min, size, start, end, .. } = self.nodes[node];
+        if end - start <= OCTREE_LEAF_BIRDS || depth >= OCTREE_MAX_DEPTH {
+            return;
+        }
+
+        let half = size / 2.0;
+        let octant = |position: Vector3<Real>| {
+            let side = |axis: usize| usize::from(position[axis] >= min[axis] + half);
+            side(0) << 2 | side(1) << 1 | side(2)
+        };
+        let mut counts = [0; 8];
+        for &i in &self.indices[start..end] {
+            counts[octant(birds[i].position)] += 1;
+        }
+        let mut next = [0; 8];
+        for child in 1..8 {
+            next[child] = next[child - 1] + counts[child - 1];
+        }
+        let mut sorted = vec![0; end - start];
+        for &i in &self.indices[start..end] {
+            let child = octant(birds[i].position);
+            sorted[next[child]] = i;
+            next[child] += 1;
+        }
+        self.indices[start..end].copy_from_slice(&sorted);
+
+        let first = self.nodes.len();
+        self.nodes[node].children = Some(first);
+        let mut child_start = start;
+        for (child, &count) in counts.iter().enumerate() {
+            let corner = Vector3::new((child >> 2) & 1, (child >> 1) & 1, child & 1).map(|bit| bit as Real * half);
+            self.nodes.push(OctreeNode {
+                min: min + corner,
+                size: half,
+                children: None,
+                start: child_start,
+                end: child_start + count,
+            });
+            child_start += count;
+        }
+        for child in first..first + 8 {
+            self.split(child, birds, depth + 1);
+        }
+    }
+
+    pub fn leaves(&self) -> impl Iterator<Item = &OctreeNode> {
+        self.nodes.iter().filter(|node| node.is_leaf())
+    }
+
+    pub fn depth(&self) -> usize {
+        let root = self.nodes[0].size;
+        self.leaves().map(|leaf| (root / leaf.size).log2().round() as usize).max().unwrap_or(0)
+    }
+
+    // Indices of all birds in the leaves within `radius` of `position`, which includes
+    // every bird within `radius`, in the same order for the same flock
+    pub fn candidates(&self, position: Vector3<Real>, radius: Real) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.count() == 0 || node.distance(position) > radius {
+                continue;
+            }
+            match node.children {
+                Some(first) => stack.extend((first..first + 8).rev()),
+                None => found.extend_from_slice(&self.indices[node.start..node.end]),
+            }
+        }
+        found
+    }
+
+    // Indices of the `k` birds nearest to bird `index`, closest first, searching ever
+    // wider spheres until one holds `k` birds, which must then include the nearest `k`
+    pub fn k_nearest(&self, birds: &[Bird], index: usize, k: usize) -> Vec<usize> {
+        let position = birds[index].position;
+        let root = &self.nodes[0];
+        let mut radius = root.size / (1 << OCTREE_MAX_DEPTH) as Real;
+        loop {
+            let mut found: Vec<(Real, usize)> = self
+                .candidates(position, radius)
+                .into_iter()
+                .filter(|&i| i != index)
+                .map(|i| ((birds[i].position - position).norm(), i))
+                .collect();
+            let within = found.iter().filter(|(distance, _)| *distance <= radius).count();
+            if within >= k || root.distance(position) + root.size * (3.0 as Real).sqrt() <= radius {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                return found.into_iter().take(k).map(|(_, i)| i).collect();
+            }
+            radius *= 2.0;
+        }
+    }
+}
+
+// Debug check: largest difference between octree and brute-force flocking forces
+pub fn check_octree_forces(birds: &[Bird], octree: &Octree, params: &SimulationParams) -> Real {
+    let mut worst: Real = 0.0;
+    for bird in birds {
+        let brute = flocking_forces(bird, birds, params.perception_radius, params.max_speed, params.max_force);
+        let candidates = octree.candidates(bird.position, params.perception_radius);
+        let searched = flocking_forces(bird, candidates.iter().map(|&i| &birds[i]), params.perception_radius, params.max_speed, params.max_force);
+        worst = worst
+            .max((brute.0 - searched.0).norm())
+            .max((brute.1 - searched.1).norm())
+            .max((brute.2 - searched.2).norm());
+    }
+    worst
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::grid::brute_force_k_nearest;
+    use crate::params::{Distribution, GRID_CHECK_TOLERANCE, SPAWN_VELOCITIES};
+
+    // A dense knot, which the tree splits deep, inside a thin flock over the whole space,
+    // and a few birds stacked on one point, which split down to OCTREE_MAX_DEPTH
+    fn knotted_flock() -> Vec<Bird> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut spawn = |positions: Distribution, count: usize| -> Vec<Bird> {
+            (0..count).map(|_| Bird::sampled(&positions, &SPAWN_VELOCITIES, &mut rng)).collect()
+        };
+        let extent = SimulationParams::default().extent;
+        let mut birds = spawn(Distribution::Uniform { extent }, 500);
+        birds.extend(spawn(Distribution::Normal { mean: [2.0, -1.0, 0.5], stddev: 0.3 }, 500));
+        birds.extend(spawn(Distribution::Uniform { extent: 0.0 }, 2 * OCTREE_LEAF_BIRDS));
+        birds
+    }
+
+    #[test]
+    fn octree_forces_match_brute_force() {
+        let params = SimulationParams::default();
+        let birds = knotted_flock();
+        let octree = Octree::new(&birds);
+
+        let difference = check_octree_forces(&birds, &octree, &params);
+        assert!(difference <= GRID_CHECK_TOLERANCE, "octree forces differ by {:e}", difference);
+    }
+
+    #[test]
+    fn octree_k_nearest_matches_brute_force() {
+        let birds = knotted_flock();
+        let octree = Octree::new(&birds);
+
+        for k in [0, 1, 7, 50, birds.len()] {
+            let mismatched = (0..birds.len()).filter(|&i| octree.k_nearest(&birds, i, k) != brute_force_k_nearest(&birds, i, k)).count();
+            assert_eq!(mismatched, 0, "{} birds have different {} nearest neighbors", mismatched, k);
+        }
+    }
+}
@@ -0,0 +1,817 @@
+// Simulation constants and the runtime parameters derived from them
+
+use crate::error::BirdflockError;
+use crate::real::Real;
+
+pub const DEBUG_CHECKS: bool = false;  // per-step correctness checks (slow)
+pub const SHOW_POSITIONS: bool = false;
+pub const POSITIONS_EVERY: usize = 100;  // steps between position logs
+pub const POSITIONS_FIRST: usize = 0;    // log birds POSITIONS_FIRST..POSITIONS_FIRST + POSITIONS_COUNT
+pub const POSITIONS_COUNT: usize = 5;
+
+pub const NUM_BIRDS: usize = 10000;
+
+pub const DIMENSIONS: Real = 7.5;
+pub const SPACE_MIN: Real = -DIMENSIONS;
+pub const SPACE_MAX: Real = DIMENSIONS;
+pub const WORLD_SCALE: Real = 1.0; // size of the world relative to the one these constants describe, see SimulationParams::scaled
+
+// World boundary, the cube or a sphere about the origin
+pub const BOUNDARY: Boundary = Boundary::Box;
+pub const BOUNDARY_MODES: [BoundaryMode; 3] = [BoundaryMode::Wrap; 3]; // what each x, y, z face of the cube does
+pub const BOUNDARY_RADIUS: Real = DIMENSIONS;
+pub const BOUNDARY_WEIGHT: Real = 2.0;
+pub const BOUNDARY_MARGIN: Real = 1.5; // soft sphere steering starts this far inside the radius
+pub const BOUNDARY_LOOKAHEAD: Real = 10.0; // time ahead the outward motion is projected, so fast birds turn sooner
+
+pub const NUM_PREDATORS: usize = 0;
+
+pub const SPAWN_EXTENT: Real = 5.0; // birds spawn within +/- this on each axis
+pub const SPAWN_POSITIONS: Distribution = Distribution::Uniform { extent: SPAWN_EXTENT };
+pub const SPAWN_VELOCITIES: Distribution = Distribution::Uniform { extent: 1.0 };
+pub const HEADING_COHERENCE: Real = 0.0; // initial velocity bias towards a shared heading, 0 = random, 1 = identical
+pub const LEADER_FRACTION: Real = 0.0;   // fraction of birds spawned as leaders, 0 = all followers
+pub const LEADER_INFLUENCE: Real = 5.0;  // leaders' weight in neighbors' alignment and cohesion, followers have 1
+
+pub const SEPARATION_WEIGHT: Real = 1.5;   // flock tightness
+pub const ALIGNMENT_WEIGHT:  Real = 2.0;   // movement coordination
+pub const COHESION_WEIGHT:   Real = 1.5;   // flock unification
+pub const PERCEPTION_RADIUS: Real = 1.9;   // flock size
+pub const PERCEPTION_PULSE_AMPLITUDE: Real = 0.0; // the radius swings sinusoidally this fraction either way, so the flock breathes, 0 = constant
+pub const PERCEPTION_PULSE_PERIOD: Real = 300.0; // time for one full swing out and back
+// Separation from neighbors directly ahead, to the side and behind, blended by angle
+// from the heading, e.g. a low side weight for elongated formations. Equal weights
+// keep separation the same in every direction
+pub const SEPARATION_FRONT:  Real = 1.0;
+pub const SEPARATION_SIDE:   Real = 1.0;
+pub const SEPARATION_BACK:   Real = 1.0;
+pub const MAX_SPEED:         Real = 0.125;
+pub const MAX_FORCE:         Real = 0.03;  // sharpness of movement
+pub const MAX_TURN_RATE:     Real = crate::real::consts::PI; // most a heading turns per unit time, in radians, PI = unconstrained
+pub const MIN_SPEED:         Real = 0.0;   // speed floor, 0 = birds may stop
+pub const DRAG:              Real = 0.0;   // air resistance, 0 = coast freely
+pub const GRAVITY:           Real = 0.0;   // downward acceleration on the birds, 0 = pure boids
+pub const LIFT_COEFFICIENT:  Real = 0.0;   // upward acceleration per unit speed, birds hover at GRAVITY / LIFT_COEFFICIENT
+pub const TIME_STEP:         Real = 1.0;   // time covered by one simulation step
+pub const CENTRE_ATTRACTION: Real = 0.0;   // pull of the flock centroid towards the origin
+pub const WAYPOINT_ATTRACTION: Real = 0.003; // pull of the flock centroid towards a scene's active waypoint
+pub const WAYPOINT_THRESHOLD: Real = 1.5;  // centroid distance at which the flock moves on to the next waypoint
+pub const FLEE_RADIUS:       Real = 2.0;   // prey react to predators within this
+pub const FLEE_WEIGHT:       Real = 3.0;
+pub const OBSTACLE_WEIGHT:   Real = 4.0;
+pub const OBSTACLE_MARGIN:   Real = 1.0;   // birds start avoiding this far from an obstacle's surface
+pub const OBSTACLE_LOOKAHEAD: Real = 10.0; // time ahead a moving obstacle's position is predicted
+pub const OBSTACLE_GRAVITY:  Real = 0.002; // downward acceleration of moving obstacles
+pub const GUST_RATE:         Real = 0.0;   // chance of a random gust each step, 0 = only when triggered
+pub const GUST_STRENGTH:     Real = 0.02;
+pub const GUST_LIFETIME:     Real = 60.0;  // time a gust takes to die away
+pub const SHOCKWAVE_SPEED:   Real = 0.1;   // growth of a shockwave's radius per unit time
+pub const SHOCKWAVE_STRENGTH: Real = 0.1;
+pub const SHOCKWAVE_WIDTH:   Real = 1.0;   // thickness of the shell that pushes birds
+pub const SHOCKWAVE_LIFETIME: Real = 60.0;
+pub const FLASH_SPEED:       Real = 0.2;   // growth of a flash's radius per unit time
+pub const FLASH_STRENGTH:    Real = 0.05;  // turning acceleration on the wavefront
+pub const FLASH_WIDTH:       Real = 0.5;   // thickness of the wavefront that turns birds
+pub const FLASH_LIFETIME:    Real = 60.0;
+pub const STARTLE_RADIUS:    Real = 0.0;   // predators or moving obstacles this close startle a bird, 0 = off
+pub const STARTLE_DECAY:     Real = 0.03;  // fraction of the startle lost per unit time
+pub const STARTLE_SPREAD:    Real = 0.85;  // fraction of a neighbor's startle a bird catches
+pub const STARTLE_CONTAGION: Real = 0.3;   // caught startle below this is ignored
+pub const STARTLE_SPEED_BOOST: Real = 0.6; // extra max speed when fully startled, as a fraction
+pub const STARTLE_SEPARATION_BOOST: Real = 1.0; // extra separation weight when fully startled, as a fraction
+pub const CURRENT:           Current = Current::Off; // ambient air flow carrying the birds
+pub const CURRENT_STRENGTH:  Real = 0.02;  // speed of the current
+pub const CURRENT_DIRECTION: [Real; 3] = [1.0, 0.0, 0.0]; // for Current::Drift
+pub const CURRENT_SCALE:     Real = 10.0;  // width of a Current::Curl swirl
+pub const ALIGNMENT_NOISE:   Real = 0.0;   // random offset to the heading each bird aligns to, relative to a unit heading, 0 = exact
+pub const COHESION_DEAD_ZONE: Real = 0.0;  // no cohesion within this of the neighbor centre
+pub const COHESION_MEMORY:   Real = 0.0;   // decay of the remembered neighbor centre, 0 = instantaneous
+pub const COHESION_LOOKAHEAD: Real = 0.0;  // time ahead the neighbor centre is predicted at their mean velocity, 0 = where it is now
+pub const ACCELERATION_SMOOTHING: Real = 1.0; // blend towards the new acceleration, 1 = no smoothing
+pub const INTEGRATION: Integration = Integration::AddThenClamp;
+pub const TOPOLOGICAL_NEIGHBORS: usize = 0; // flock with the k nearest instead of a radius, 0 = off (starlings use ~7)
+pub const COMFORT_DENSITY:   Real = 0.0;   // neighbors a bird settles at, pushed out of its neighbor centre above and in below, 0 = off
+pub const COMFORT_WEIGHT:    Real = 1.0;
+pub const CRITICAL_RADIUS:   Real = 0.0;   // a neighbor this close makes separation the priority, 0 = off
+pub const CRITICAL_SUPPRESSION: Real = 0.1; // alignment and cohesion scale while a neighbor is that close
+pub const USE_GRID:          bool = true;  // spatial grid neighbor search, false = brute force
+pub const USE_OCTREE:        bool = false; // adaptive octree neighbor search in place of the grid, for tightly clustered flocks
+pub const PAIRWISE_FORCES:   bool = false; // brute force measures each pair once for both birds, with an isotropic separation shape
+pub const APPROXIMATION_DISTANCE: Real = 0.0; // grid cells further than this are summarised by their centroid, 0 = exact
+pub const APPROXIMATION_CELL_FRACTION: Real = 0.5; // width of the summarised cells relative to that distance
+pub const GRID_CELL_SIZE:    Real = 0.0;   // grid cell width, raised to at least the perception radius
+pub const CHUNK_SIZE:        usize = 0;    // birds per task in the parallel update, split into fixed contiguous chunks, 0 = rayon's adaptive splitting
+pub const DISTANCE_MATRIX_BIRDS: usize = 0; // flocks up to this size reuse a pairwise distance matrix, 0 = never (see --benchmark-neighbors)
+pub const GRID_CHECK_TOLERANCE: Real = 1e-5; // allowed grid vs brute-force force difference
+
+// Watchdog for a flock that has frozen into a static clump
+pub const STALL_ACTION: StallAction = StallAction::Off;
+pub const STALL_ORDER_THRESHOLD: Real = 0.1;  // stalled while the order parameter is below this
+pub const STALL_SPEED_THRESHOLD: Real = 0.01; // and the mean speed is below this
+pub const STALL_STEPS:           usize = 500; // for this many consecutive steps
+
+// Named (separation, alignment, cohesion) weights for showing what each rule
+// contributes, alone and together
+pub const WEIGHT_PRESETS: [(&str, [Real; 3]); 6] = [
+    ("separation only", [SEPARATION_WEIGHT, 0.0, 0.0]),
+    ("alignment only",  [0.0, ALIGNMENT_WEIGHT, 0.0]),
+    ("cohesion only",   [0.0, 0.0, COHESION_WEIGHT]),
+    ("balanced",        [SEPARATION_WEIGHT, ALIGNMENT_WEIGHT, COHESION_WEIGHT]),
+    ("swarm",           [2.0, 0.2, 2.5]),  // tight milling with little shared heading
+    ("streams",         [1.0, 4.0, 0.5]),  // long parallel lanes that rarely merge
+];
+
+// Day/night cycle: the flock roams by day, and by night is pulled in and slowed
+// to settle at the roost until dawn releases it
+pub const ROOST_DAY:      Real = 0.0;    // time the flock roams each cycle, 0 = no roost
+pub const ROOST_NIGHT:    Real = 1000.0; // time it spends at the roost
+pub const ROOST_POSITION: [Real; 3] = [0.0, SPACE_MIN + 1.0, 0.0];
+pub const ROOST_WEIGHT:   Real = 3.0;
+pub const ROOST_RADIUS:   Real = 2.0;    // birds slow down within this of the roost
+pub const ROOST_SPEED:    Real = 0.3;    // max speed at night, as a fraction
+
+// Stratified flocking: birds are split between horizontal layers, each pulled to
+// its own altitude, and see birds in other layers with less weight the further apart
+// the layers are, so the flock forms bands that drift and shear past each other
+pub const LAYER_COUNT:    usize = 1;     // number of layers, 1 = ordinary 3D flocking
+pub const LAYER_COUPLING: Real = 0.2;    // weight of a neighbor one layer away, squared two layers away and so on
+pub const LAYER_SPACING:  Real = 1.0;    // height between layers, within PERCEPTION_RADIUS so neighboring layers interact
+pub const LAYER_WEIGHT:   Real = 1.0;    // pull towards the layer's altitude
+
+// Birds joining mid-run burst out from a point, on the B key or every ARRIVAL_EVERY steps
+pub const ARRIVAL_EVERY:     usize = 0;       // steps between arrivals, 0 = only when triggered
+pub const ARRIVAL_COUNT:     usize = 50;      // birds per arrival
+pub const ARRIVAL_MAX_BIRDS: usize = 20000;   // periodic arrivals stop once the flock is this large
+pub const ARRIVAL_POSITION:  [Real; 3] = [0.0, 0.0, 0.0];
+pub const ARRIVAL_RADIUS:    Real = 0.5;      // birds appear within this of the position
+pub const ARRIVAL_SPEED:     Real = 0.1;      // initial speed, outwards from the position
+pub const ARRIVAL_TAGS:      u32 = 0;         // tags every arriving bird carries, on top of any from the tag rules
+pub const STRAGGLER_STEPS:   usize = 0;       // birds alone for this many steps are removed, 0 = never
+
+// Each bird carries a bitset of tags, and a tag rule scales the perception radius, top
+// speed and steering force of the birds with its tag set, e.g. far-sighted scouts or a
+// tired, slower group. A rule gives its tag to a fraction of the flock, chosen at random
+// at the start and again among arrivals. Rules are set with `tag` lines in a scene
+pub const TAG_BITS: u32 = u32::BITS; // tags are numbered 0 to TAG_BITS - 1
+
+// Predators flock among themselves and chase the densest prey
+pub const PREDATOR_SEPARATION_WEIGHT: Real = 1.5;
+pub const PREDATOR_ALIGNMENT_WEIGHT:  Real = 1.0;
+pub const PREDATOR_COHESION_WEIGHT:   Real = 0.5;
+pub const PREDATOR_CHASE_WEIGHT:      Real = 1.5;
+pub const PREDATOR_PERCEPTION_RADIUS: Real = 4.0;
+pub const PREDATOR_MAX_SPEED:         Real = 0.15;
+pub const PREDATOR_MAX_FORCE:         Real = 0.02;
+pub const PREDATOR_CATCH_RADIUS:      Real = 0.0; // prey this close to a predator are caught and removed, 0 = no kills
+pub const DENSITY_CELL_SIZE:          Real = 1.5; // grid used to find the densest prey
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Boundary {
+    Box,        // the simulation cube, its faces handled by BOUNDARY_MODES
+    SoftSphere, // steer back inside the sphere
+    HardSphere, // reflect off the sphere
+}
+
+// Per-axis handling of the cube's faces, e.g. wrap in x and z but bounce in y
+// for an open field between a ground and a ceiling
+#[derive(Copy, Clone, PartialEq)]
+pub enum BoundaryMode {
+    Wrap,   // leave through one face and re-enter through the opposite one
+    Bounce, // mirror back inside with that velocity component reversed
+    Clamp,  // stop at the face, losing the outward velocity
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Current {
+    Off,
+    Drift,  // uniform flow along CURRENT_DIRECTION
+    Vortex, // rotation about the vertical axis through the origin
+    Curl,   // swirling cells
+}
+
+// Random vectors for spawning birds, drawn from the seeded spawn RNG
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Distribution {
+    Uniform { extent: Real },              // each axis uniform over +/- extent
+    Normal { mean: [Real; 3], stddev: Real }, // each axis normal about its mean
+    Shell { radius: Real },                // uniform over a sphere's surface, e.g. every bird at the same speed
+}
+
+impl Distribution {
+    // The distribution with every vector it draws multiplied by `factor`
+    pub fn scaled(self, factor: Real) -> Distribution {
+        match self {
+            Distribution::Uniform { extent } => Distribution::Uniform { extent: extent * factor },
+            Distribution::Normal { mean, stddev } => Distribution::Normal { mean: mean.map(|x| x * factor), stddev: stddev * factor },
+            Distribution::Shell { radius } => Distribution::Shell { radius: radius * factor },
+        }
+    }
+}
+
+// How the acceleration is applied to a bird's velocity each step
+#[derive(Copy, Clone, PartialEq)]
+pub enum Integration {
+    // Add it and clamp the result to max speed. Steering against a slow bird's motion
+    // can carry it through zero and flip it round in a single step, and at the cap the
+    // forward part of the steering dilutes the turn before the clamp throws it away
+    AddThenClamp,
+    // Turn the heading by the sideways part and change speed by the forward part
+    // alone, so a bird at max speed turns fully and braking only slows a bird down.
+    // Birds no longer flick round on the spot, and the flock jitters less and
+    // settles into a more ordered, somewhat slower flow
+    TurnThenSpeed,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum StallAction {
+    Off,
+    Log,    // report the stall
+    Reseed, // report it and scatter the flock to new random positions
+}
+
+// Runtime simulation parameters, defaulting to the constants above
+#[derive(Clone)]
+pub struct SimulationParams {
+    pub world_scale: Real, // size relative to the base parameters, set through `scaled`
+    pub extent: Real,     // the simulation cube spans +/- this on each axis
+    pub separation_weight: Real,
+    pub alignment_weight: Real,
+    pub cohesion_weight: Real,
+    pub separation_enabled: bool, // disabled rules keep their weight for when they are re-enabled
+    pub alignment_enabled: bool,
+    pub cohesion_enabled: bool,
+    pub perception_radius: Real,
+    pub perception_pulse_amplitude: Real,
+    pub perception_pulse_period: Real,
+    pub separation_shape: SeparationShape,
+    pub max_speed: Real,
+    pub min_speed: Real,
+    pub max_force: Real,
+    pub max_turn_rate: Real,
+    pub drag: Real,
+    pub gravity: Real,
+    pub lift_coefficient: Real,
+    pub dt: Real,
+    pub centre_attraction: Real,
+    pub waypoint_attraction: Real,
+    pub waypoint_threshold: Real,
+    pub flee_radius: Real,
+    pub flee_weight: Real,
+    pub obstacle_weight: Real,
+    pub obstacle_margin: Real,
+    pub obstacle_lookahead: Real,
+    pub obstacle_gravity: Real,
+    pub gust_rate: Real,
+    pub gust_strength: Real,
+    pub gust_lifetime: Real,
+    pub shockwave_speed: Real,
+    pub shockwave_strength: Real,
+    pub shockwave_width: Real,
+    pub shockwave_lifetime: Real,
+    pub flash_speed: Real,
+    pub flash_strength: Real,
+    pub flash_width: Real,
+    pub flash_lifetime: Real,
+    pub startle_radius: Real,
+    pub startle_decay: Real,
+    pub startle_spread: Real,
+    pub startle_contagion: Real,
+    pub startle_speed_boost: Real,
+    pub startle_separation_boost: Real,
+    pub current: Current,
+    pub current_strength: Real,
+    pub current_direction: [Real; 3],
+    pub current_scale: Real,
+    pub alignment_noise: Real,
+    pub cohesion_dead_zone: Real,
+    pub cohesion_memory: Real,
+    pub cohesion_lookahead: Real,
+    pub comfort_density: Real,
+    pub comfort_weight: Real,
+    pub acceleration_smoothing: Real,
+    pub integration: Integration,
+    pub spawn_positions: Distribution,
+    pub spawn_velocities: Distribution,
+    pub tag_rules: Vec<TagRule>,
+    pub topological_neighbors: usize,
+    pub critical_radius: Real,
+    pub critical_suppression: Real,
+    pub use_grid: bool,
+    pub use_octree: bool,
+    pub pairwise_forces: bool,
+    pub grid_cell_size: Real,
+    pub distance_matrix_birds: usize,
+    pub chunk_size: usize,
+    pub approximation_distance: Real,
+    pub boundary: Boundary,
+    pub boundary_modes: [BoundaryMode; 3],
+    pub boundary_radius: Real,
+    pub boundary_weight: Real,
+    pub boundary_margin: Real,
+    pub boundary_lookahead: Real,
+    pub stall: StallParams,
+    pub roost: RoostParams,
+    pub layers: LayerParams,
+    pub arrivals: ArrivalParams,
+    pub straggler_steps: usize,
+    pub predator: PredatorParams,
+}
+
+// Separation weight by where a neighbor is relative to the bird's heading
+#[derive(Clone, Copy, PartialEq)]
+pub struct SeparationShape {
+    pub front: Real,
+    pub side: Real,
+    pub back: Real,
+}
+
+impl SeparationShape {
+    pub fn is_isotropic(&self) -> bool {
+        self.front == self.side && self.side == self.back
+    }
+
+    // Weight for a neighbor at `cos_angle` from the heading, from `back` at -1
+    // through `side` at 0 to `front` at 1
+    pub fn weight(&self, cos_angle: Real) -> Real {
+        if cos_angle >= 0.0 {
+            self.side + (self.front - self.side) * cos_angle
+        } else {
+            self.side - (self.back - self.side) * cos_angle
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StallParams {
+    pub action: StallAction,
+    pub order_threshold: Real,
+    pub speed_threshold: Real,
+    pub steps: usize,
+}
+
+#[derive(Clone)]
+pub struct RoostParams {
+    pub day: Real,
+    pub night: Real,
+    pub position: [Real; 3],
+    pub weight: Real,
+    pub radius: Real,
+    pub speed: Real,
+}
+
+impl RoostParams {
+    // Each cycle starts at dawn, so a run begins with the day phase
+    pub fn is_night(&self, time: f64) -> bool {
+        if self.day <= 0.0 || self.night <= 0.0 {
+            return false;
+        }
+        time % (self.day + self.night) as f64 >= self.day as f64
+    }
+}
+
+#[derive(Clone)]
+pub struct LayerParams {
+    pub count: usize,
+    pub coupling: Real,
+    pub spacing: Real,
+    pub weight: Real,
+}
+
+impl LayerParams {
+    // Weight of a neighbor in layer `other` as seen from layer `layer`
+    pub fn coupling_between(&self, layer: u32, other: u32) -> Real {
+        if layer == other {
+            1.0
+        } else {
+            self.coupling.powi(layer.abs_diff(other) as i32)
+        }
+    }
+
+    // Altitude of `layer`, the layers stacked bottom to top about the middle of the space
+    pub fn height(&self, layer: u32) -> Real {
+        (layer as Real - (self.count.max(1) - 1) as Real / 2.0) * self.spacing
+    }
+}
+
+// Birds with bit `tag` of their tags set see `perception` times as far and are limited
+// to `max_speed` and `max_force` times the usual top speed and steering force
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TagRule {
+    pub tag: u32,
+    pub fraction: Real, // of the flock given the tag when spawned
+    pub perception: Real,
+    pub max_speed: Real,
+    pub max_force: Real,
+}
+
+// How a bird's tags scale its limits, 1 for untagged birds
+pub struct TagScales {
+    pub perception: Real,
+    pub max_speed: Real,
+    pub max_force: Real,
+}
+
+// Where and how fast birds added mid-run appear
+#[derive(Clone)]
+pub struct SpawnConfig {
+    pub position: [Real; 3],
+    pub radius: Real,
+    pub speed: Real,
+    pub tags: u32,
+}
+
+#[derive(Clone)]
+pub struct ArrivalParams {
+    pub every: usize,
+    pub count: usize,
+    pub max_birds: usize,
+    pub spawn: SpawnConfig,
+}
+
+#[derive(Clone)]
+pub struct PredatorParams {
+    pub separation_weight: Real,
+    pub alignment_weight: Real,
+    pub cohesion_weight: Real,
+    pub chase_weight: Real,
+    pub perception_radius: Real,
+    pub max_speed: Real,
+    pub max_force: Real,
+    pub catch_radius: Real,
+}
+
+impl Default for SimulationParams {
+    fn default() -> Self {
+        SimulationParams {
+            world_scale: 1.0,
+            extent: DIMENSIONS,
+            separation_weight: SEPARATION_WEIGHT,
+            alignment_weight: ALIGNMENT_WEIGHT,
+            cohesion_weight: COHESION_WEIGHT,
+            separation_enabled: true,
+            alignment_enabled: true,
+            cohesion_enabled: true,
+            perception_radius: PERCEPTION_RADIUS,
+            perception_pulse_amplitude: PERCEPTION_PULSE_AMPLITUDE,
+            perception_pulse_period: PERCEPTION_PULSE_PERIOD,
+            separation_shape: SeparationShape {
+                front: SEPARATION_FRONT,
+                side: SEPARATION_SIDE,
+                back: SEPARATION_BACK,
+            },
+            max_speed: MAX_SPEED,
+            min_speed: MIN_SPEED,
+            max_force: MAX_FORCE,
+            max_turn_rate: MAX_TURN_RATE,
+            drag: DRAG,
+            gravity: GRAVITY,
+            lift_coefficient: LIFT_COEFFICIENT,
+            dt: TIME_STEP,
+            centre_attraction: CENTRE_ATTRACTION,
+            waypoint_attraction: WAYPOINT_ATTRACTION,
+            waypoint_threshold: WAYPOINT_THRESHOLD,
+            flee_radius: FLEE_RADIUS,
+            flee_weight: FLEE_WEIGHT,
+            obstacle_weight: OBSTACLE_WEIGHT,
+            obstacle_margin: OBSTACLE_MARGIN,
+            obstacle_lookahead: OBSTACLE_LOOKAHEAD,
+            obstacle_gravity: OBSTACLE_GRAVITY,
+            gust_rate: GUST_RATE,
+            gust_strength: GUST_STRENGTH,
+            gust_lifetime: GUST_LIFETIME,
+            shockwave_speed: SHOCKWAVE_SPEED,
+            shockwave_strength: SHOCKWAVE_STRENGTH,
+            shockwave_width: SHOCKWAVE_WIDTH,
+            shockwave_lifetime: SHOCKWAVE_LIFETIME,
+            flash_speed: FLASH_SPEED,
+            flash_strength: FLASH_STRENGTH,
+            flash_width: FLASH_WIDTH,
+            flash_lifetime: FLASH_LIFETIME,
+            startle_radius: STARTLE_RADIUS,
+            startle_decay: STARTLE_DECAY,
+            startle_spread: STARTLE_SPREAD,
+            startle_contagion: STARTLE_CONTAGION,
+            startle_speed_boost: STARTLE_SPEED_BOOST,
+            startle_separation_boost: STARTLE_SEPARATION_BOOST,
+            current: CURRENT,
+            current_strength: CURRENT_STRENGTH,
+            current_direction: CURRENT_DIRECTION,
+            current_scale: CURRENT_SCALE,
+            alignment_noise: ALIGNMENT_NOISE,
+            cohesion_dead_zone: COHESION_DEAD_ZONE,
+            cohesion_memory: COHESION_MEMORY,
+            cohesion_lookahead: COHESION_LOOKAHEAD,
+            comfort_density: COMFORT_DENSITY,
+            comfort_weight: COMFORT_WEIGHT,
+            acceleration_smoothing: ACCELERATION_SMOOTHING,
+            integration: INTEGRATION,
+            spawn_positions: SPAWN_POSITIONS,
+            spawn_velocities: SPAWN_VELOCITIES,
+            tag_rules: Vec::new(),
+            topological_neighbors: TOPOLOGICAL_NEIGHBORS,
+            critical_radius: CRITICAL_RADIUS,
+            critical_suppression: CRITICAL_SUPPRESSION,
+            use_grid: USE_GRID,
+            use_octree: USE_OCTREE,
+            pairwise_forces: PAIRWISE_FORCES,
+            grid_cell_size: GRID_CELL_SIZE,
+            distance_matrix_birds: DISTANCE_MATRIX_BIRDS,
+            chunk_size: CHUNK_SIZE,
+            approximation_distance: APPROXIMATION_DISTANCE,
+            boundary: BOUNDARY,
+            boundary_modes: BOUNDARY_MODES,
+            boundary_radius: BOUNDARY_RADIUS,
+            boundary_weight: BOUNDARY_WEIGHT,
+            boundary_margin: BOUNDARY_MARGIN,
+            boundary_lookahead: BOUNDARY_LOOKAHEAD,
+            stall: StallParams {
+                action: STALL_ACTION,
+                order_threshold: STALL_ORDER_THRESHOLD,
+                speed_threshold: STALL_SPEED_THRESHOLD,
+                steps: STALL_STEPS,
+            },
+            roost: RoostParams {
+                day: ROOST_DAY,
+                night: ROOST_NIGHT,
+                position: ROOST_POSITION,
+                weight: ROOST_WEIGHT,
+                radius: ROOST_RADIUS,
+                speed: ROOST_SPEED,
+            },
+            layers: LayerParams {
+                count: LAYER_COUNT,
+                coupling: LAYER_COUPLING,
+                spacing: LAYER_SPACING,
+                weight: LAYER_WEIGHT,
+            },
+            arrivals: ArrivalParams {
+                every: ARRIVAL_EVERY,
+                count: ARRIVAL_COUNT,
+                max_birds: ARRIVAL_MAX_BIRDS,
+                spawn: SpawnConfig {
+                    position: ARRIVAL_POSITION,
+                    radius: ARRIVAL_RADIUS,
+                    speed: ARRIVAL_SPEED,
+                    tags: ARRIVAL_TAGS,
+                },
+            },
+            straggler_steps: STRAGGLER_STEPS,
+            predator: PredatorParams {
+                separation_weight: PREDATOR_SEPARATION_WEIGHT,
+                alignment_weight: PREDATOR_ALIGNMENT_WEIGHT,
+                cohesion_weight: PREDATOR_COHESION_WEIGHT,
+                chase_weight: PREDATOR_CHASE_WEIGHT,
+                perception_radius: PREDATOR_PERCEPTION_RADIUS,
+                max_speed: PREDATOR_MAX_SPEED,
+                max_force: PREDATOR_MAX_FORCE,
+                catch_radius: PREDATOR_CATCH_RADIUS,
+            },
+        }
+        .scaled(WORLD_SCALE)
+    }
+}
+
+impl SimulationParams {
+    // (separation, alignment, cohesion) weights with disabled rules zeroed
+    pub fn rule_weights(&self) -> (Real, Real, Real) {
+        let weight = |enabled: bool, weight: Real| if enabled { weight } else { 0.0 };
+        (
+            weight(self.separation_enabled, self.separation_weight),
+            weight(self.alignment_enabled, self.alignment_weight),
+            weight(self.cohesion_enabled, self.cohesion_weight),
+        )
+    }
+
+    // How a bird with `tags` has its limits scaled, by every rule it matches
+    pub fn tag_scales(&self, tags: u32) -> TagScales {
+        let mut scales = TagScales { perception: 1.0, max_speed: 1.0, max_force: 1.0 };
+        for rule in self.tag_rules.iter().filter(|rule| tags & (1 << rule.tag) != 0) {
+            scales.perception *= rule.perception;
+            scales.max_speed *= rule.max_speed;
+            scales.max_force *= rule.max_force;
+        }
+        scales
+    }
+
+    // Perception radius at `time`, between (1 - amplitude) and (1 + amplitude) times the
+    // set radius over each pulse period, and the set radius when there's no pulse
+    pub fn perception_radius_at(&self, time: f64) -> Real {
+        if self.perception_pulse_amplitude == 0.0 {
+            return self.perception_radius;
+        }
+        let phase = (time / self.perception_pulse_period as f64).fract() as Real * crate::real::consts::TAU;
+        self.perception_radius * (1.0 + self.perception_pulse_amplitude * phase.sin())
+    }
+
+    // Furthest any bird can see, for a bird with every tag that widens its view, which
+    // the grid's cells must be at least as wide as
+    pub fn max_perception_radius(&self) -> Real {
+        self.perception_radius * self.tag_rules.iter().map(|rule| rule.perception.max(1.0)).product::<Real>()
+    }
+
+    // Approximation distances from this on leave no room between them and the furthest
+    // any bird sees for a whole summarised cell, whose diagonal is sqrt(3) widths
+    pub fn max_approximation_distance(&self) -> Real {
+        self.max_perception_radius() / (1.0 + APPROXIMATION_CELL_FRACTION * (3.0 as Real).sqrt())
+    }
+
+    // Reject values the step can't work with, named as in scene files
+    pub fn validate(&self) -> Result<(), BirdflockError> {
+        let invalid = |message: String| Err(BirdflockError::InvalidParams(message));
+        let positive = [
+            ("world_scale", self.world_scale),
+            ("extent", self.extent),
+            ("perception_radius", self.perception_radius),
+            ("max_speed", self.max_speed),
+            ("dt", self.dt),
+            ("perception_pulse_period", self.perception_pulse_period),
+        ];
+        for (name, value) in positive {
+            if value.is_nan() || value <= 0.0 {
+                return invalid(format!("{} must be positive, not {}", name, value));
+            }
+        }
+        let non_negative = [
+            ("min_speed", self.min_speed),
+            ("max_force", self.max_force),
+            ("max_turn_rate", self.max_turn_rate),
+            ("drag", self.drag),
+            ("alignment_noise", self.alignment_noise),
+            ("cohesion_lookahead", self.cohesion_lookahead),
+            ("comfort_density", self.comfort_density),
+            ("grid_cell_size", self.grid_cell_size),
+            ("approximation_distance", self.approximation_distance),
+            ("roost_radius", self.roost.radius),
+            ("arrival_radius", self.arrivals.spawn.radius),
+            ("arrival_speed", self.arrivals.spawn.speed),
+            ("predator_catch_radius", self.predator.catch_radius),
+        ];
+        for (name, value) in non_negative {
+            if value.is_nan() || value < 0.0 {
+                return invalid(format!("{} must not be negative, not {}", name, value));
+            }
+        }
+        // A distance that leaves no room for a summarised cell changes nothing
+        if self.approximation_distance >= self.max_approximation_distance() {
+            return invalid(format!(
+                "approximation_distance must be below {} for perception radius {}, or 0 for exact sums, not {}",
+                self.max_approximation_distance(),
+                self.max_perception_radius(),
+                self.approximation_distance
+            ));
+        }
+        if self.layers.count > 1 && (self.layers.spacing.is_nan() || self.layers.spacing <= 0.0) {
+            return invalid(format!("layer_spacing must be positive, not {}", self.layers.spacing));
+        }
+        if self.layers.count == 0 {
+            return invalid("layers must be at least 1".to_string());
+        }
+        if !(self.layers.coupling >= 0.0 && self.layers.coupling <= 1.0) {
+            return invalid(format!("layer_coupling must be in [0, 1], not {}", self.layers.coupling));
+        }
+        for (name, distribution) in [("spawn_positions", self.spawn_positions), ("spawn_velocities", self.spawn_velocities)] {
+            let (what, value) = match distribution {
+                Distribution::Uniform { extent } => ("extent", extent),
+                Distribution::Normal { stddev, .. } => ("stddev", stddev),
+                Distribution::Shell { radius } => ("radius", radius),
+            };
+            if value.is_nan() || value < 0.0 {
+                return invalid(format!("{} {} must not be negative, not {}", name, what, value));
+            }
+        }
+        for rule in &self.tag_rules {
+            if rule.tag >= TAG_BITS {
+                return invalid(format!("tag {} is beyond the last tag, {}", rule.tag, TAG_BITS - 1));
+            }
+            if !(0.0..=1.0).contains(&rule.fraction) {
+                return invalid(format!("tag {} fraction must be in [0, 1], not {}", rule.tag, rule.fraction));
+            }
+            for (name, value) in [("perception", rule.perception), ("max_speed", rule.max_speed)] {
+                if value.is_nan() || value <= 0.0 {
+                    return invalid(format!("tag {} {} must be positive, not {}", rule.tag, name, value));
+                }
+            }
+            if rule.max_force.is_nan() || rule.max_force < 0.0 {
+                return invalid(format!("tag {} max_force must not be negative, not {}", rule.tag, rule.max_force));
+            }
+        }
+        if self.min_speed > self.max_speed {
+            return invalid(format!("min_speed {} is above max_speed {}", self.min_speed, self.max_speed));
+        }
+        if !(self.perception_pulse_amplitude >= 0.0 && self.perception_pulse_amplitude < 1.0) {
+            return invalid(format!("perception_pulse_amplitude must be in [0, 1), not {}", self.perception_pulse_amplitude));
+        }
+        if !(self.acceleration_smoothing > 0.0 && self.acceleration_smoothing <= 1.0) {
+            return invalid(format!("acceleration_smoothing must be in (0, 1], not {}", self.acceleration_smoothing));
+        }
+        if !(self.cohesion_memory >= 0.0 && self.cohesion_memory < 1.0) {
+            return invalid(format!("cohesion_memory must be in [0, 1), not {}", self.cohesion_memory));
+        }
+        Ok(())
+    }
+
+    // The same flock in a world `world_scale` times the size of the base parameters,
+    // behaving the same way to scale, so `scaled(1.0)` gives the base back. Time is left
+    // alone, so one step still covers `dt` and every length, speed and acceleration
+    // scales linearly:
+    //   lengths: extent and every radius, margin, threshold, spacing, width and position,
+    //     the grid cell size and approximation distance, and the spawn positions
+    //   speeds: max and min speed, the current, shockwave and flash growth, the arrival and stall
+    //     speeds and the spawn velocities
+    //   accelerations: max force, gravity, gusts, shockwaves, flashes and obstacle gravity
+    // Weights, fractions, counts, times and rates per unit time are unchanged, as are
+    // centre and waypoint attraction, drag and lift, which multiply a length or speed
+    // that already scales. Nothing here scales quadratically; the squared quantities
+    // are outputs, so the kinetic energy grows as world_scale squared and momentum
+    // linearly, while neighbor counts and the order parameter stay the same
+    pub fn scaled(&self, world_scale: Real) -> SimulationParams {
+        let s = world_scale / self.world_scale;
+        let scale = |v: [Real; 3]| v.map(|x| x * s);
+        let mut scaled = self.clone();
+        scaled.world_scale = world_scale;
+
+        // Lengths
+        scaled.extent *= s;
+        scaled.perception_radius *= s;
+        scaled.waypoint_threshold *= s;
+        scaled.flee_radius *= s;
+        scaled.obstacle_margin *= s;
+        scaled.shockwave_width *= s;
+        scaled.flash_width *= s;
+        scaled.startle_radius *= s;
+        scaled.current_scale *= s;
+        scaled.cohesion_dead_zone *= s;
+        scaled.critical_radius *= s;
+        scaled.grid_cell_size *= s;
+        scaled.approximation_distance *= s;
+        scaled.boundary_radius *= s;
+        scaled.boundary_margin *= s;
+        scaled.roost.position = scale(self.roost.position);
+        scaled.roost.radius *= s;
+        scaled.layers.spacing *= s;
+        scaled.arrivals.spawn.position = scale(self.arrivals.spawn.position);
+        scaled.arrivals.spawn.radius *= s;
+        scaled.predator.perception_radius *= s;
+        scaled.predator.catch_radius *= s;
+        scaled.spawn_positions = self.spawn_positions.scaled(s);
+
+        // Speeds
+        scaled.max_speed *= s;
+        scaled.min_speed *= s;
+        scaled.current_strength *= s;
+        scaled.shockwave_speed *= s;
+        scaled.flash_speed *= s;
+        scaled.stall.speed_threshold *= s;
+        scaled.arrivals.spawn.speed *= s;
+        scaled.predator.max_speed *= s;
+        scaled.spawn_velocities = self.spawn_velocities.scaled(s);
+
+        // Accelerations
+        scaled.max_force *= s;
+        scaled.gravity *= s;
+        scaled.gust_strength *= s;
+        scaled.shockwave_strength *= s;
+        scaled.flash_strength *= s;
+        scaled.obstacle_gravity *= s;
+        scaled.predator.max_force *= s;
+        scaled
+    }
+
+    // Set the (separation, alignment, cohesion) weights, re-enabling any disabled rules
+    pub fn set_rule_weights(&mut self, [separation, alignment, cohesion]: [Real; 3]) {
+        self.separation_weight = separation;
+        self.alignment_weight = alignment;
+        self.cohesion_weight = cohesion;
+        self.separation_enabled = true;
+        self.alignment_enabled = true;
+        self.cohesion_enabled = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::determinism_run;
+
+    #[test]
+    fn scaling_the_world_scales_the_flock_exactly() {
+        // Multiplying by a power of two is exact, so the birds must end up exactly at the
+        // unscaled positions times the scale, unless `scaled` missed some length, speed or force
+        let reference = determinism_run(1.0, 0);
+        for scale in [0.5 as Real, 2.0] {
+            let birds = determinism_run(scale, 0);
+            let differing = reference.iter().zip(&birds).filter(|(a, b)| a.position * scale != b.position).count();
+            assert_eq!(differing, 0, "{} birds at world scale {} are not where the unscaled run puts them, scaled", differing, scale);
+        }
+    }
+
+    #[test]
+    fn approximation_distances_leaving_no_room_for_a_summarised_cell_are_rejected() {
+        let params = SimulationParams::default();
+        let valid = |approximation_distance: Real| SimulationParams { approximation_distance, ..params.clone() }.validate().is_ok();
+        let limit = params.max_approximation_distance();
+        assert!(limit < params.perception_radius);
+        assert!(valid(0.0) && valid(limit * 0.99));
+        assert!(!valid(limit) && !valid(params.perception_radius));
+    }
+}
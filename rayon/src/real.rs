@@ -0,0 +1,18 @@
+// Float type of positions, velocities and all of the simulation's maths: f32 by
+// default for speed, or f64 with the `f64` feature for long runs where f32 rounding
+// accumulates into noticeable drift. Drawing and exports convert to f32 at that
+// boundary. `consts` is the matching std::f32 or std::f64 constants module
+
+#[cfg(not(feature = "f64"))]
+mod precision {
+    pub type Real = f32;
+    pub use std::f32::consts;
+}
+
+#[cfg(feature = "f64")]
+mod precision {
+    pub type Real = f64;
+    pub use std::f64::consts;
+}
+
+pub use precision::*;
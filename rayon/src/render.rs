@@ -0,0 +1,1509 @@
+use glium::Surface;
+use nalgebra::{Matrix4, Orthographic3, Perspective3, Point3, Vector3, Vector4};
+
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+use birdflock::{retain_unremoved, Bird, BirdflockError, Octree, Simulation, ALIGNMENT_WEIGHT, COHESION_WEIGHT, LEADER_INFLUENCE,
+    MAX_FORCE, MAX_SPEED, Real, SEPARATION_WEIGHT};
+
+pub const POV_DISTANCE: f32 = 17.5;
+pub const PROJECTION_MODE: ProjectionMode = ProjectionMode::Perspective;
+pub const FIELD_OF_VIEW: f32 = std::f32::consts::FRAC_PI_3; // vertical, for the perspective projection
+
+pub const RENDER_STYLE: RenderStyle = RenderStyle::Triangle;
+pub const COLOUR_MODE:  ColourMode = ColourMode::Depth;
+pub const DEPTH_PALETTE: DepthPalette = DepthPalette::RedWhite;
+
+// Custom depth palette, from far to near
+pub const CUSTOM_FAR_COLOUR:  [f32; 3] = [0.1, 0.1, 0.1];
+pub const CUSTOM_NEAR_COLOUR: [f32; 3] = [0.9, 0.9, 0.9];
+
+// Speed colour mode, from slow to MAX_SPEED
+pub const SLOW_COLOUR: [f32; 3] = [0.2, 0.3, 1.0];
+pub const FAST_COLOUR: [f32; 3] = [1.0, 0.9, 0.2];
+
+// Influence colour mode, from ordinary followers to LEADER_INFLUENCE
+pub const FOLLOWER_COLOUR: [f32; 3] = [0.5, 0.5, 0.6];
+pub const LEADER_COLOUR:   [f32; 3] = [1.0, 0.3, 0.8];
+
+// Force colour mode, dim for birds steering gently to glowing at MAX_ACCELERATION,
+// the most the three flocking rules can steer at their default weights
+pub const CALM_COLOUR:     [f32; 3] = [0.15, 0.1, 0.3];
+pub const STRAINED_COLOUR: [f32; 3] = [1.0, 0.95, 0.7];
+pub const MAX_ACCELERATION: Real = MAX_FORCE * (SEPARATION_WEIGHT + ALIGNMENT_WEIGHT + COHESION_WEIGHT);
+
+// Age colour mode, from newly arrived birds to those at least OLD_AGE steps old,
+// so arrivals and removals show up as the flock's colours turning over
+pub const YOUNG_COLOUR: [f32; 3] = [0.4, 1.0, 0.5];
+pub const OLD_COLOUR:   [f32; 3] = [0.6, 0.25, 0.15];
+pub const OLD_AGE:      u32 = 1000;
+
+// Layered flocking tints each layer's birds, cycling through these bottom to top
+pub const LAYER_COLOURS: [[f32; 3]; 4] = [[0.2, 0.4, 1.0], [0.2, 1.0, 0.5], [1.0, 0.9, 0.2], [1.0, 0.3, 0.8]];
+pub const LAYER_TINT: f32 = 0.3; // how far the colour mode's colour is mixed towards the layer's
+
+// Lighting for the 3D model style
+pub const AMBIENT_LIGHT: f32 = 0.35;                 // 1 = unlit
+pub const LIGHT_DIRECTION: [f32; 3] = [0.3, 1.0, 0.6];
+pub const RIM_DIRECTION: [f32; 3] = [0.0, 0.5, -1.0];
+pub const RIM_STRENGTH: f32 = 0.4;                   // 0 = no rim light
+
+// Point style, for flocks too large to draw a triangle per bird
+pub const POINT_SIZE: f32 = 40.0;     // size in pixels at unit distance from the camera
+pub const MAX_POINT_SIZE: f32 = 6.0;  // points never grow beyond this, or shrink below 1 pixel
+
+// The triangle and model styles issue a draw call per bird, and tens of thousands queued
+// before the frame is finished can pile up in the driver. Flushing every DRAW_FLUSH_EVERY
+// birds starts the GPU on them sooner. The point style, and the LOD points, go out as one
+// draw from one buffer, so they queue a single call however many birds there are and
+// never need this; they are the style to use for very large flocks
+pub const DRAW_FLUSH_EVERY: usize = 0; // 0 = leave flushing to the end of the frame
+
+// Level of detail for the triangle and model styles: birds further than LOD_DISTANCE
+// from the camera shrink across LOD_BAND into points, and are only points beyond it
+pub const LOD_DISTANCE: f32 = 0.0;  // 0 = always draw the full style
+pub const LOD_BAND: f32 = 5.0;
+
+pub const PREDATOR_COLOUR: [f32; 3] = [0.2, 0.8, 1.0];
+pub const PREDATOR_SCALE:  f32 = 2.0;
+
+pub const ANCHORED_COLOUR: [f32; 3] = [0.6, 1.0, 0.6];
+
+// Ground grid across the space with a flat shadow on it under every bird, each shown on its own
+pub const SHOW_GROUND: bool = false;
+pub const SHOW_SHADOWS: bool = false;
+pub const GROUND_HEIGHT: Real = 0.0;                       // above the floor of the space, as a fraction of its extent
+pub const GROUND_DIVISIONS: usize = 10;                    // grid squares along each side
+pub const GROUND_COLOUR: [f32; 4] = [0.3, 0.35, 0.3, 0.5];
+pub const SHADOW_COLOUR: [f32; 3] = [0.12, 0.12, 0.12];
+
+pub const OBSTACLE_COLOUR: [f32; 4] = [1.0, 0.5, 0.1, 0.6];
+pub const SHOCKWAVE_COLOUR: [f32; 4] = [1.0, 1.0, 1.0, 0.4];
+pub const FLASH_COLOUR: [f32; 4] = [1.0, 0.9, 0.4, 0.3];
+pub const WAYPOINT_COLOUR: [f32; 4] = [0.8, 0.4, 1.0, 0.3];
+pub const ACTIVE_WAYPOINT_COLOUR: [f32; 4] = [0.8, 0.4, 1.0, 0.7]; // drawn at the advance threshold
+pub const WAYPOINT_SIZE: Real = 0.2;
+pub const BOUNDARY_COLOUR: [f32; 4] = [0.4, 0.6, 0.9, 0.3];  // spherical world boundary
+
+// Perception radius overlay
+pub const SELECTED_COLOUR:  [f32; 3] = [1.0, 1.0, 0.2];
+pub const NEIGHBOR_COLOUR:  [f32; 3] = [0.3, 1.0, 0.3];
+pub const PERCEPTION_COLOUR: [f32; 4] = [0.3, 1.0, 0.3, 0.35];
+
+// Octree overlay, the occupied leaf cells over the first flock
+pub const SHOW_OCTREE: bool = false;
+pub const OCTREE_COLOUR: [f32; 4] = [0.9, 0.7, 0.3, 0.25];
+
+// Centroid overlay, a crosshair at each flock's centroid with a line along its mean velocity
+pub const SHOW_CENTROID: bool = false;
+pub const CENTROID_COLOUR: [f32; 4] = [1.0, 0.3, 0.6, 0.9];
+pub const CENTROID_MARKER_SIZE: Real = 0.4;    // half the width of the crosshair
+pub const CENTROID_VELOCITY_TIME: Real = 40.0; // the line ends where the centroid drifts to in this time
+
+// Comet tails, in each bird's speed colour at the head fading out along its recent path
+pub const SHOW_TAILS: bool = false;
+pub const TAIL_LENGTH: usize = 12;   // steps of history behind each bird
+pub const TAIL_OPACITY: f32 = 0.8;   // at the head
+pub const TAIL_FADE: f32 = 1.5;      // falloff of the opacity towards the tail, 1 = linear
+
+pub const SHOW_HEATMAP: bool = false;  // long-exposure trail accumulation
+pub const HEATMAP_FADE: f32 = 0.02;    // fraction of the heatmap faded each frame
+
+// Fixed-rate stepping, decoupled from the display refresh. Each step covers the
+// params' dt of simulated time, so the flock moves at rate * dt time units per real
+// second: a higher rate speeds it up and a lower one plays it in slow motion
+pub const SIMULATION_RATE: f64 = 60.0;    // steps per second, 0 = one step per frame
+pub const MAX_SIMULATION_RATE: f64 = 1000.0; // upper clamp for --hz, the lower one is 1
+pub const MAX_STEPS_PER_FRAME: usize = 5; // catch-up limit after a slow frame
+pub const INTERPOLATE: bool = true;       // blend between the last two steps when drawing
+
+pub const MESH_SIZE: f32 = 0.07;        // --mesh models are centred and scaled to this radius, like the dart
+
+pub const WINDOW_SIZE: (u32, u32) = (800, 480);  // initial window size, except when recording
+pub const VIDEO_SIZE: (u32, u32) = (1280, 720); // --render-video frame size unless --video-size is given
+
+// Side-by-side flocks
+pub const FLOCK_GAP: f32 = 2.0;                           // space between neighbouring flock regions
+pub const FLOCK_BOX_COLOUR: [f32; 4] = [0.5, 0.5, 0.5, 0.6];
+
+// Every shader is written for GLSL 1.40 and retried as 1.30 on drivers that reject it
+pub const GLSL_VERSION: &str = "#version 140";
+pub const FALLBACK_GLSL_VERSION: &str = "#version 130";
+
+// Compile the `name` shaders, falling back to FALLBACK_GLSL_VERSION if they fail.
+// If that fails too, the error carries the driver's log and the GL version and
+// renderer rather than an opaque unwrap.
+pub fn compile_program<F: glium::backend::Facade>(
+    facade: &F,
+    name: &str,
+    vertex_shader: &str,
+    fragment_shader: &str,
+    uses_point_size: bool,
+) -> Result<glium::Program, BirdflockError> {
+    let compile = |vertex_shader: &str, fragment_shader: &str| {
+        glium::Program::new(
+            facade,
+            glium::program::ProgramCreationInput::SourceCode {
+                vertex_shader,
+                tessellation_control_shader: None,
+                tessellation_evaluation_shader: None,
+                geometry_shader: None,
+                fragment_shader,
+                transform_feedback_varyings: None,
+                outputs_srgb: true,
+                uses_point_size,
+            },
+        )
+    };
+    let err = match compile(vertex_shader, fragment_shader) {
+        Ok(program) => return Ok(program),
+        Err(err) => err,
+    };
+
+    let fallback = |source: &str| source.replacen(GLSL_VERSION, FALLBACK_GLSL_VERSION, 1);
+    match compile(&fallback(vertex_shader), &fallback(fragment_shader)) {
+        Ok(program) => {
+            eprintln!("warning: the {} shaders only compiled as {}: {}", name, FALLBACK_GLSL_VERSION, err);
+            Ok(program)
+        }
+        Err(fallback_err) => {
+            let context = facade.get_context();
+            let log = format!(
+                "  as {}: {}\n  as {}: {}\nOpenGL {} on {} ({}), the renderer needs GLSL 1.30 or later. --headless runs without a window.",
+                GLSL_VERSION,
+                err,
+                FALLBACK_GLSL_VERSION,
+                fallback_err,
+                context.get_opengl_version_string(),
+                context.get_opengl_renderer_string(),
+                context.get_opengl_vendor_string()
+            );
+            Err(BirdflockError::ShaderCompile { name: name.to_string(), log })
+        }
+    }
+}
+
+// Open the window and its OpenGL context with a depth buffer, as glium's
+// SimpleWindowBuilder does but returning an error wherever that would panic
+pub fn create_display(
+    event_loop: &glium::winit::event_loop::EventLoop<()>,
+    title: &str,
+    (width, height): (u32, u32),
+) -> Result<(glium::winit::window::Window, glium::Display<glium::glutin::surface::WindowSurface>), BirdflockError> {
+    use glium::glutin::config::ConfigTemplateBuilder;
+    use glium::glutin::context::ContextAttributesBuilder;
+    use glium::glutin::display::GetGlDisplay;
+    use glium::glutin::prelude::*;
+    use glium::glutin::surface::{SurfaceAttributesBuilder, WindowSurface};
+    use raw_window_handle::HasWindowHandle;
+    use std::num::NonZeroU32;
+
+    let gl_init = |action: &str, err: &dyn std::fmt::Display| BirdflockError::GlInit(format!("cannot {}: {}", action, err));
+
+    let attributes = glium::winit::window::Window::default_attributes()
+        .with_title(title)
+        .with_inner_size(glium::winit::dpi::PhysicalSize::new(width, height));
+    let template = ConfigTemplateBuilder::new().with_depth_size(24);
+    // glutin gives the config picker no way to decline, so with no config to pick it
+    // unwinds, without the panic hook, and is caught here as an error like the rest
+    struct NoConfig;
+    let built = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        glutin_winit::DisplayBuilder::new()
+            .with_window_attributes(Some(attributes))
+            .build(event_loop, template, |mut configs| configs.next().unwrap_or_else(|| std::panic::resume_unwind(Box::new(NoConfig))))
+    }))
+    .map_err(|_| BirdflockError::GlInit("no OpenGL config with a depth buffer".to_string()))?;
+    let (window, gl_config) = built.map_err(|err| gl_init("open a window", &err))?;
+    let window = window.ok_or_else(|| BirdflockError::GlInit("no window was created".to_string()))?;
+    let handle = window.window_handle().map_err(|err| gl_init("get the window handle", &err))?.as_raw();
+
+    let (width, height): (u32, u32) = window.inner_size().into();
+    let (width, height) = match (NonZeroU32::new(width), NonZeroU32::new(height)) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Err(BirdflockError::GlInit("the window has no area".to_string())),
+    };
+    let surface_attributes = SurfaceAttributesBuilder::<WindowSurface>::new().build(handle, width, height);
+    let surface = unsafe { gl_config.display().create_window_surface(&gl_config, &surface_attributes) }
+        .map_err(|err| gl_init("create the window surface", &err))?;
+    let context_attributes = ContextAttributesBuilder::new().build(Some(handle));
+    let context = unsafe { gl_config.display().create_context(&gl_config, &context_attributes) }
+        .map_err(|err| gl_init("create the OpenGL context", &err))?
+        .make_current(&surface)
+        .map_err(|err| gl_init("make the OpenGL context current", &err))?;
+    let display = glium::Display::from_context_surface(context, surface).map_err(|err| gl_init("use the OpenGL context", &err))?;
+    Ok((window, display))
+}
+
+// Turns a failed draw call into an error naming what was being drawn
+fn draw_error(what: &'static str) -> impl Fn(glium::DrawError) -> BirdflockError {
+    move |err| BirdflockError::GlDraw(format!("{}: {}", what, err))
+}
+
+// Vertex buffer created while setting up, where failing means the GL context is unusable
+pub fn vertex_buffer<F: glium::backend::Facade, T: glium::Vertex>(
+    facade: &F,
+    name: &str,
+    data: &[T],
+) -> Result<glium::VertexBuffer<T>, BirdflockError> {
+    glium::VertexBuffer::new(facade, data)
+        .map_err(|err| BirdflockError::GlInit(format!("cannot create the {} vertex buffer: {}", name, err)))
+}
+
+#[derive(Copy, Clone)]
+pub struct Vertex {
+    pub position: [f32; 2],
+}
+
+implement_vertex!(Vertex, position);
+
+// Camera with projection and view matrices cached until its inputs change
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ProjectionMode {
+    Perspective,
+    Orthographic, // no foreshortening, framing the target as the perspective view does
+}
+
+impl ProjectionMode {
+    pub fn next(self) -> Self {
+        match self {
+            ProjectionMode::Perspective => ProjectionMode::Orthographic,
+            ProjectionMode::Orthographic => ProjectionMode::Perspective,
+        }
+    }
+}
+
+pub struct Camera {
+    aspect_ratio: f32,
+    projection: ProjectionMode,
+    eye: Point3<f32>,
+    target: Point3<f32>,
+    offset: Vector3<f32>, // world translation applied before viewing, to place a flock
+    dirty: bool,
+    projection_matrix: [[f32; 4]; 4],
+    view_matrix: [[f32; 4]; 4],
+}
+
+impl Camera {
+    pub fn new(aspect_ratio: f32) -> Self {
+        Camera {
+            aspect_ratio,
+            projection: PROJECTION_MODE,
+            eye: Point3::new(0.0, 0.0, POV_DISTANCE),
+            target: Point3::origin(),
+            offset: Vector3::zeros(),
+            dirty: true,
+            projection_matrix: [[0.0; 4]; 4],
+            view_matrix: [[0.0; 4]; 4],
+        }
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        if aspect_ratio != self.aspect_ratio {
+            self.aspect_ratio = aspect_ratio;
+            self.dirty = true;
+        }
+    }
+
+    pub fn projection(&self) -> ProjectionMode {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: ProjectionMode) {
+        if projection != self.projection {
+            self.projection = projection;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_distance(&mut self, distance: f32) {
+        self.eye = Point3::new(0.0, 0.0, distance);
+        self.dirty = true;
+    }
+
+    pub fn set_offset(&mut self, offset: Vector3<f32>) {
+        if offset != self.offset {
+            self.offset = offset;
+            self.dirty = true;
+        }
+    }
+
+    // Point under the cursor on the plane through the target facing the camera, in
+    // the coordinates of the flock at the current offset
+    pub fn cursor_to_world(&mut self, (x, y): (f64, f64), (width, height): (u32, u32)) -> Option<Vector3<f32>> {
+        let (view, projection) = self.matrices();
+        let inverse = (Matrix4::from(projection) * Matrix4::from(view)).try_inverse()?;
+        let ndc_x = 2.0 * x as f32 / width.max(1) as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y as f32 / height.max(1) as f32;
+        let unproject = |ndc_z: f32| {
+            let point = inverse * Vector4::new(ndc_x, ndc_y, ndc_z, 1.0);
+            point.xyz() / point.w
+        };
+        let (near, far) = (unproject(-1.0), unproject(1.0));
+
+        let normal = self.eye - self.target;
+        let along = normal.dot(&(far - near));
+        if along == 0.0 {
+            return None;
+        }
+        let t = normal.dot(&(self.target.coords - self.offset - near)) / along;
+        Some(near + (far - near) * t)
+    }
+
+    // (view, projection), recomputed only when marked dirty
+    pub fn matrices(&mut self) -> ([[f32; 4]; 4], [[f32; 4]; 4]) {
+        if self.dirty {
+            self.projection_matrix = match self.projection {
+                ProjectionMode::Perspective => {
+                    *Perspective3::new(self.aspect_ratio, FIELD_OF_VIEW, 0.1, 100.0).as_matrix().as_ref()
+                }
+                ProjectionMode::Orthographic => {
+                    // Sized to what the perspective view shows at the target's distance
+                    let half_height = (self.eye - self.target).norm() * (FIELD_OF_VIEW / 2.0).tan();
+                    let half_width = half_height * self.aspect_ratio;
+                    *Orthographic3::new(-half_width, half_width, -half_height, half_height, 0.1, 100.0).as_matrix().as_ref()
+                }
+            };
+            let up = Vector3::y();
+            let view = Matrix4::look_at_rh(&self.eye, &self.target, &up) * Matrix4::new_translation(&self.offset);
+            self.view_matrix = *view.as_ref();
+            self.dirty = false;
+        }
+        (self.view_matrix, self.projection_matrix)
+    }
+}
+
+pub fn aspect_ratio((width, height): (u32, u32)) -> f32 {
+    width as f32 / height.max(1) as f32
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderStyle {
+    Triangle, // flat camera-facing triangle
+    Model,    // lit 3D dart facing along the velocity
+    Points,   // one GL point per bird, sized by distance, in a single draw call
+}
+
+impl RenderStyle {
+    pub fn next(self) -> Self {
+        match self {
+            RenderStyle::Triangle => RenderStyle::Model,
+            RenderStyle::Model => RenderStyle::Points,
+            RenderStyle::Points => RenderStyle::Triangle,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColourMode {
+    Depth,     // far to near through the depth palette
+    Speed,     // SLOW_COLOUR to FAST_COLOUR
+    Heading,   // direction of flight as RGB
+    Influence, // FOLLOWER_COLOUR to LEADER_COLOUR
+    Force,     // CALM_COLOUR to STRAINED_COLOUR by acceleration, where the flock fights itself
+    Age,       // YOUNG_COLOUR to OLD_COLOUR by steps in the flock
+}
+
+impl ColourMode {
+    pub fn next(self) -> Self {
+        match self {
+            ColourMode::Depth => ColourMode::Speed,
+            ColourMode::Speed => ColourMode::Heading,
+            ColourMode::Heading => ColourMode::Influence,
+            ColourMode::Influence => ColourMode::Force,
+            ColourMode::Force => ColourMode::Age,
+            ColourMode::Age => ColourMode::Depth,
+        }
+    }
+
+    // Colour for a bird, or None to leave it to the shader's depth colouring
+    pub fn colour_of(self, bird: &Bird) -> Option<[f32; 3]> {
+        match self {
+            ColourMode::Depth => None,
+            ColourMode::Speed => {
+                let t = (bird.velocity.norm() / MAX_SPEED).min(1.0);
+                Some(Vector3::from(SLOW_COLOUR).lerp(&Vector3::from(FAST_COLOUR), t as f32).into())
+            }
+            ColourMode::Heading => {
+                let speed = bird.velocity.norm();
+                let heading = if speed > 0.0 { bird.velocity / speed } else { Vector3::zeros() };
+                Some((heading * 0.5 + Vector3::repeat(0.5)).cast::<f32>().into())
+            }
+            ColourMode::Influence => {
+                let t = if LEADER_INFLUENCE > 1.0 {
+                    ((bird.influence - 1.0) / (LEADER_INFLUENCE - 1.0)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                Some(Vector3::from(FOLLOWER_COLOUR).lerp(&Vector3::from(LEADER_COLOUR), t as f32).into())
+            }
+            ColourMode::Force => {
+                let t = (bird.acceleration.norm() / MAX_ACCELERATION).min(1.0);
+                Some(Vector3::from(CALM_COLOUR).lerp(&Vector3::from(STRAINED_COLOUR), t as f32).into())
+            }
+            ColourMode::Age => {
+                let t = (bird.age as f32 / OLD_AGE as f32).min(1.0);
+                Some(Vector3::from(YOUNG_COLOUR).lerp(&Vector3::from(OLD_COLOUR), t).into())
+            }
+        }
+    }
+}
+
+// Colours for the depth colour mode, passed to the shaders as (far, middle, near) and
+// blended linearly through the middle one
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DepthPalette {
+    RedWhite,  // red far to white near
+    Viridis,   // purple far through teal to yellow near, readable with most colour blindness
+    BlueWhite, // deep blue far to white near
+    Fire,      // dark red far through orange to pale yellow near
+    Custom,    // CUSTOM_FAR_COLOUR to CUSTOM_NEAR_COLOUR
+}
+
+impl DepthPalette {
+    pub fn next(self) -> Self {
+        match self {
+            DepthPalette::RedWhite => DepthPalette::Viridis,
+            DepthPalette::Viridis => DepthPalette::BlueWhite,
+            DepthPalette::BlueWhite => DepthPalette::Fire,
+            DepthPalette::Fire => DepthPalette::Custom,
+            DepthPalette::Custom => DepthPalette::RedWhite,
+        }
+    }
+
+    // (far, middle, near)
+    pub fn colours(self) -> ([f32; 3], [f32; 3], [f32; 3]) {
+        let between = |far: [f32; 3], near: [f32; 3]| (far, Vector3::from(far).lerp(&Vector3::from(near), 0.5).into(), near);
+        match self {
+            DepthPalette::RedWhite => between([1.0, 0.2, 0.2], [1.0, 1.0, 1.0]),
+            DepthPalette::Viridis => ([0.267, 0.005, 0.329], [0.128, 0.567, 0.551], [0.993, 0.906, 0.144]),
+            DepthPalette::BlueWhite => between([0.1, 0.2, 0.7], [1.0, 1.0, 1.0]),
+            DepthPalette::Fire => ([0.35, 0.02, 0.0], [1.0, 0.4, 0.0], [1.0, 0.95, 0.6]),
+            DepthPalette::Custom => between(CUSTOM_FAR_COLOUR, CUSTOM_NEAR_COLOUR),
+        }
+    }
+}
+
+// Birds placed `alpha` of the way from their `previous` positions to the current ones.
+// Birds that wrapped around the space, +/- `extent`, are drawn where they are rather than streaking across,
+// as are birds added since the last step, which have no previous position.
+pub fn interpolated_birds(birds: &[Bird], previous: &[Vector3<Real>], alpha: Real, extent: Real) -> Vec<Bird> {
+    birds
+        .iter()
+        .enumerate()
+        .map(|(i, bird)| {
+            let mut bird = bird.clone();
+            if let Some(&from) = previous.get(i) {
+                let step = bird.position - from;
+                if step.amax() < extent {
+                    bird.position = from + step * alpha;
+                }
+            }
+            bird
+        })
+        .collect()
+}
+
+// The flock's positions over the last TAIL_LENGTH steps, the oldest overwritten first
+#[derive(Clone)]
+pub struct TailHistory {
+    snapshots: Vec<Vec<Vector3<Real>>>,
+    newest: usize,
+}
+
+impl TailHistory {
+    pub fn new() -> Self {
+        TailHistory { snapshots: Vec::new(), newest: 0 }
+    }
+
+    pub fn record(&mut self, birds: &[Bird]) {
+        let positions = birds.iter().map(|bird| bird.position);
+        if self.snapshots.len() < TAIL_LENGTH {
+            self.snapshots.push(positions.collect());
+            self.newest = self.snapshots.len() - 1;
+        } else {
+            self.newest = (self.newest + 1) % TAIL_LENGTH;
+            let snapshot = &mut self.snapshots[self.newest];
+            snapshot.clear();
+            snapshot.extend(positions);
+        }
+    }
+
+    // Forget the birds a step removed, so the rest keep their tails
+    pub fn remove(&mut self, removed: &[bool]) {
+        for snapshot in &mut self.snapshots {
+            retain_unremoved(snapshot, removed);
+        }
+    }
+
+    // Snapshots from the newest to the oldest
+    pub fn snapshots(&self) -> impl Iterator<Item = &[Vector3<Real>]> {
+        let count = self.snapshots.len();
+        (0..count).map(move |age| &self.snapshots[(self.newest + count - age) % count][..])
+    }
+}
+
+// A simulation drawn in its own region of the window, keeping the positions
+// from before its last step for interpolation and before the steps prior for tails
+pub struct FlockView {
+    pub simulation: Simulation,
+    pub offset: Vector3<f32>,
+    pub tails: TailHistory,
+    previous_birds: Vec<Vector3<Real>>,
+    previous_predators: Vec<Vector3<Real>>,
+}
+
+impl FlockView {
+    pub fn new(simulation: Simulation, offset: Vector3<f32>) -> Self {
+        let mut view = FlockView {
+            simulation,
+            offset,
+            tails: TailHistory::new(),
+            previous_birds: Vec::new(),
+            previous_predators: Vec::new(),
+        };
+        view.remember_positions();
+        view
+    }
+
+    fn remember_positions(&mut self) {
+        self.previous_birds.clear();
+        self.previous_birds.extend(self.simulation.birds.iter().map(|bird| bird.position));
+        self.previous_predators.clear();
+        self.previous_predators.extend(self.simulation.predators.iter().map(|bird| bird.position));
+    }
+
+    pub fn step(&mut self) -> ControlFlow<()> {
+        self.remember_positions();
+        self.tails.record(&self.simulation.birds);
+        let flow = self.simulation.step();
+        let removed = self.simulation.removed_last_step();
+        retain_unremoved(&mut self.previous_birds, removed);
+        self.tails.remove(removed);
+        flow
+    }
+
+    // Grow the flock to `count` with birds from the spawn distributions, or shrink it by
+    // removing birds at random, keeping the interpolation and tail histories in step
+    pub fn set_bird_count(&mut self, count: usize) {
+        let current = self.simulation.birds.len();
+        if count > current {
+            self.simulation.add_spawned_birds(count - current);
+        } else if count < current {
+            self.simulation.remove_random_birds(current - count);
+            let removed = self.simulation.removed_last_step();
+            retain_unremoved(&mut self.previous_birds, removed);
+            self.tails.remove(removed);
+        }
+    }
+
+    // Copy of the view, the previous positions and tails included, to draw on another
+    // thread while this one carries on stepping
+    pub fn snapshot(&self) -> FlockView {
+        FlockView {
+            simulation: self.simulation.snapshot(),
+            offset: self.offset,
+            tails: self.tails.clone(),
+            previous_birds: self.previous_birds.clone(),
+            previous_predators: self.previous_predators.clone(),
+        }
+    }
+
+    // (birds, predators) to draw, interpolated from the previous step when `alpha` is given
+    pub fn drawn_birds(&self, alpha: Option<Real>) -> (Cow<'_, [Bird]>, Cow<'_, [Bird]>) {
+        match alpha {
+            Some(alpha) => (
+                Cow::Owned(interpolated_birds(&self.simulation.birds, &self.previous_birds, alpha, self.simulation.params.extent)),
+                Cow::Owned(interpolated_birds(&self.simulation.predators, &self.previous_predators, alpha, self.simulation.params.extent)),
+            ),
+            None => (Cow::Borrowed(&self.simulation.birds[..]), Cow::Borrowed(&self.simulation.predators[..])),
+        }
+    }
+}
+
+// Region offsets for `count` flocks, each in a space spanning +/- `extent`, laid out in a
+// near-square grid, and the camera distance that fits them at the unscaled size
+pub fn flock_layout(count: usize, extent: f32) -> (Vec<Vector3<f32>>, f32) {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns);
+    let spacing = 2.0 * extent + FLOCK_GAP;
+    let offsets = (0..count)
+        .map(|i| {
+            let column = (i % columns) as f32 - (columns - 1) as f32 / 2.0;
+            let row = (rows - 1) as f32 / 2.0 - (i / columns) as f32;
+            Vector3::new(column * spacing, row * spacing, 0.0)
+        })
+        .collect();
+    (offsets, POV_DISTANCE * columns.max(rows) as f32)
+}
+
+#[derive(Copy, Clone)]
+pub struct MeshVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+}
+
+implement_vertex!(MeshVertex, position, normal);
+
+#[derive(Copy, Clone)]
+pub struct PointVertex {
+    position: [f32; 3],
+    colour: [f32; 3],
+    has_colour: f32, // 0 = depth colouring
+    tint: [f32; 4],  // mixed over the colour by its alpha
+}
+
+implement_vertex!(PointVertex, position, colour, has_colour, tint);
+
+// Dart pointing along +z with flat-shaded faces, sized like the triangle
+pub fn bird_model_mesh() -> Vec<MeshVertex> {
+    let nose = Vector3::new(0.0, 0.0, 0.07);
+    let left = Vector3::new(-0.06, 0.0, -0.04);
+    let right = Vector3::new(0.06, 0.0, -0.04);
+    let top = Vector3::new(0.0, 0.02, -0.03);
+    let bottom = Vector3::new(0.0, -0.01, -0.03);
+    let faces = [
+        [nose, left, top],
+        [nose, top, right],
+        [nose, right, bottom],
+        [nose, bottom, left],
+        [left, bottom, top],
+        [top, bottom, right],
+    ];
+
+    let mut mesh = Vec::new();
+    for [a, b, c] in faces {
+        let normal: Vector3<f32> = (b - a).cross(&(c - a)).normalize();
+        for corner in [a, b, c] {
+            mesh.push(MeshVertex { position: corner.into(), normal: normal.into() });
+        }
+    }
+    mesh
+}
+
+// Read the positions, normals and faces of an OBJ file into a flat triangle list.
+// Polygons are fanned into triangles, faces without normals are flat-shaded, and
+// the model is centred and scaled to MESH_SIZE so any modelling units work.
+// Birds face along +z, so the model's nose should point that way.
+pub fn load_obj_mesh(path: &str) -> Result<Vec<MeshVertex>, BirdflockError> {
+    let text = std::fs::read_to_string(path).map_err(|error| BirdflockError::io(path, error))?;
+    let error = |line: usize, message: &str| BirdflockError::Parse(format!("{}:{}: {}", path, line + 1, message));
+
+    let mut positions: Vec<Vector3<f32>> = Vec::new();
+    let mut normals: Vec<Vector3<f32>> = Vec::new();
+    let mut faces: Vec<Vec<(usize, Option<usize>)>> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some(kind @ ("v" | "vn")) => {
+                let values: Vec<f32> = fields.take(3).map(|field| field.parse().unwrap_or(f32::NAN)).collect();
+                if values.len() < 3 || values.iter().any(|value| !value.is_finite()) {
+                    return Err(error(number, &format!("expected three numbers after '{}'", kind)));
+                }
+                let vector = Vector3::new(values[0], values[1], values[2]);
+                if kind == "v" { positions.push(vector) } else { normals.push(vector) }
+            }
+            Some("f") => {
+                // Corners are v, v/vt, v//vn or v/vt/vn, 1-based or negative from the end
+                let resolve = |field: &str, count: usize| -> Option<usize> {
+                    let index: i64 = field.parse().ok()?;
+                    let index = if index < 0 { count as i64 + index } else { index - 1 };
+                    (0..count as i64).contains(&index).then_some(index as usize)
+                };
+                let mut face = Vec::new();
+                for corner in fields {
+                    let mut parts = corner.split('/');
+                    let position = parts.next().and_then(|field| resolve(field, positions.len()));
+                    let position = position.ok_or_else(|| error(number, &format!("bad vertex index in '{}'", corner)))?;
+                    let normal = match parts.nth(1).filter(|field| !field.is_empty()) {
+                        Some(field) => Some(
+                            resolve(field, normals.len())
+                                .ok_or_else(|| error(number, &format!("bad normal index in '{}'", corner)))?,
+                        ),
+                        None => None,
+                    };
+                    face.push((position, normal));
+                }
+                if face.len() < 3 {
+                    return Err(error(number, "a face needs at least three corners"));
+                }
+                faces.push(face);
+            }
+            _ => {} // comments, texture coordinates, groups and materials are ignored
+        }
+    }
+    if faces.is_empty() {
+        return Err(BirdflockError::Parse(format!("mesh '{}' has no faces", path)));
+    }
+
+    let (min, max) = positions.iter().fold(
+        (Vector3::repeat(f32::INFINITY), Vector3::repeat(f32::NEG_INFINITY)),
+        |(min, max), p| (min.inf(p), max.sup(p)),
+    );
+    let centre = (min + max) / 2.0;
+    let radius = positions.iter().map(|p| (p - centre).norm()).fold(0.0, f32::max);
+    if radius == 0.0 {
+        return Err(BirdflockError::Parse(format!("mesh '{}' has no extent", path)));
+    }
+    let scale = MESH_SIZE / radius;
+
+    let mut mesh = Vec::new();
+    for face in &faces {
+        for i in 1..face.len() - 1 {
+            let corners = [face[0], face[i], face[i + 1]];
+            let [a, b, c] = corners.map(|(position, _)| (positions[position] - centre) * scale);
+            let flat = (b - a).cross(&(c - a));
+            let flat = if flat.norm() > 0.0 { flat.normalize() } else { Vector3::y() };
+            for ((_, normal), position) in corners.iter().zip([a, b, c]) {
+                let normal = normal
+                    .map(|normal| normals[normal].normalize())
+                    .filter(|normal| normal.iter().all(|v| v.is_finite()));
+                mesh.push(MeshVertex { position: position.into(), normal: normal.unwrap_or(flat).into() });
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+// Model matrix placing a scaled bird at its position and, if oriented, facing along its velocity
+pub fn bird_model_matrix(bird: &Bird, oriented: bool, scale: f32) -> [[f32; 4]; 4] {
+    let translation = Matrix4::new_translation(&bird.position.cast::<f32>()) * Matrix4::new_scaling(scale);
+    let velocity = bird.velocity.cast::<f32>();
+    let speed = velocity.norm();
+    if !oriented || speed == 0.0 {
+        return *translation.as_ref();
+    }
+
+    let direction = velocity / speed;
+    let up = if direction.y.abs() > 0.99 { Vector3::x() } else { Vector3::y() };
+    let rotation = nalgebra::Rotation3::face_towards(&direction, &up);
+    *(translation * rotation.to_homogeneous()).as_ref()
+}
+
+fn point_vertex(bird: &Bird, has_colour: bool, colour: [f32; 3], tint: [f32; 4]) -> PointVertex {
+    PointVertex {
+        position: bird.position.cast::<f32>().into(),
+        colour,
+        has_colour: if has_colour { 1.0 } else { 0.0 },
+        tint,
+    }
+}
+
+// Tint for a bird's layer when the flock is split into `layers`, none for a single layer
+pub fn layer_tint(bird: &Bird, layers: usize) -> [f32; 4] {
+    if layers <= 1 {
+        return [0.0; 4];
+    }
+    let [r, g, b] = LAYER_COLOURS[bird.layer as usize % LAYER_COLOURS.len()];
+    [r, g, b, LAYER_TINT]
+}
+
+// How far a bird at this camera distance is through the LOD band, 0 = full style, 1 = point
+pub fn lod_blend(distance: f32) -> f32 {
+    if LOD_DISTANCE <= 0.0 {
+        return 0.0;
+    }
+    if LOD_BAND <= 0.0 {
+        return if distance > LOD_DISTANCE { 1.0 } else { 0.0 };
+    }
+    ((distance - LOD_DISTANCE) / LOD_BAND).clamp(0.0, 1.0)
+}
+
+// Everything needed to draw the flock with the selected render style
+pub struct BirdRenderer {
+    pub style: RenderStyle,
+    pub colour_mode: ColourMode,
+    pub palette: DepthPalette,
+    pub layers: usize, // layers the flock is split into, tinted when more than one
+    triangle_buffer: glium::VertexBuffer<Vertex>,
+    triangle_program: glium::Program,
+    model_buffer: glium::VertexBuffer<MeshVertex>,
+    model_program: glium::Program,
+    point_program: glium::Program,
+    context: Rc<glium::backend::Context>, // point buffers are rebuilt from the birds every draw
+}
+
+impl BirdRenderer {
+    // The model style draws model_mesh, either the built-in dart or one loaded with --mesh
+    pub fn new<F: glium::backend::Facade>(facade: &F, model_mesh: &[MeshVertex]) -> Result<Self, BirdflockError> {
+        // Create a triangle shape
+        let vertex1 = Vertex { position: [-0.05, -0.0288] };
+        let vertex2 = Vertex { position: [ 0.00,  0.0577] };
+        let vertex3 = Vertex { position: [ 0.05, -0.0288] };
+        let shape = vec![vertex1, vertex2, vertex3];
+
+        let triangle_buffer = vertex_buffer(facade, "triangle", &shape)?;
+
+        let vertex_shader_src = r#"
+            #version 140
+
+            in vec2 position;
+
+            uniform mat4 model;
+            uniform mat4 view;
+            uniform mat4 projection;
+
+            void main() {
+                gl_Position = projection * view * model * vec4(position, 0.0, 1.0);
+            }
+        "#;
+
+        // bird colour
+        let fragment_shader_src = r#"
+            #version 140
+
+            uniform float depth; // z position of the bird
+            uniform bool has_colour;
+            uniform vec3 colour;
+            uniform vec4 tint; // layer colour, mixed in by its alpha
+            uniform vec3 depth_far; // the depth palette, see DepthPalette
+            uniform vec3 depth_middle;
+            uniform vec3 depth_near;
+
+            out vec4 color;
+
+            void main() {
+                // Map depth (e.g. -7.5 to 7.5) to [0,1], 1 when close
+                float nearness = 1.0 - clamp((depth + 7.5) / 15.0, 0.0, 1.0);
+                vec3 depth_col = nearness < 0.5
+                    ? mix(depth_far, depth_middle, 2.0 * nearness)
+                    : mix(depth_middle, depth_near, 2.0 * nearness - 1.0);
+                vec3 bird_col = has_colour ? colour : depth_col;
+                color = vec4(mix(bird_col, tint.rgb, tint.a), 1.0);
+            }
+        "#;
+
+        let triangle_program = compile_program(facade, "triangle", vertex_shader_src, fragment_shader_src, false)?;
+
+        let model_buffer = vertex_buffer(facade, "model", model_mesh)?;
+
+        let model_vertex_shader_src = r#"
+            #version 140
+
+            in vec3 position;
+            in vec3 normal;
+
+            uniform mat4 model;
+            uniform mat4 view;
+            uniform mat4 projection;
+
+            out vec3 v_normal;
+            out vec3 v_world_position;
+
+            void main() {
+                vec4 world_position = model * vec4(position, 1.0);
+                v_normal = mat3(model) * normal;
+                v_world_position = world_position.xyz;
+                gl_Position = projection * view * world_position;
+            }
+        "#;
+
+        // Depth colour lit by a key light, an ambient term and a rim/back light
+        let model_fragment_shader_src = r#"
+            #version 140
+
+            in vec3 v_normal;
+            in vec3 v_world_position;
+
+            uniform float depth;
+            uniform bool has_colour;
+            uniform vec3 colour;
+            uniform vec4 tint;
+            uniform vec3 eye;
+            uniform vec3 light_direction; // towards the key light
+            uniform vec3 rim_direction;   // towards the back light
+            uniform float ambient;
+            uniform float rim_strength;
+            uniform vec3 depth_far;
+            uniform vec3 depth_middle;
+            uniform vec3 depth_near;
+
+            out vec4 color;
+
+            void main() {
+                float nearness = 1.0 - clamp((depth + 7.5) / 15.0, 0.0, 1.0);
+                vec3 depth_col = nearness < 0.5
+                    ? mix(depth_far, depth_middle, 2.0 * nearness)
+                    : mix(depth_middle, depth_near, 2.0 * nearness - 1.0);
+                vec3 bird_col = has_colour ? colour : depth_col;
+                bird_col = mix(bird_col, tint.rgb, tint.a);
+
+                // Faces are two-sided, so light whichever side the camera sees
+                vec3 view_dir = normalize(eye - v_world_position);
+                vec3 n = normalize(v_normal);
+                if (dot(n, view_dir) < 0.0) {
+                    n = -n;
+                }
+
+                float diffuse = max(dot(n, normalize(light_direction)), 0.0);
+                float fresnel = pow(1.0 - dot(n, view_dir), 2.0);
+                float rim = rim_strength * fresnel * (0.5 + 0.5 * dot(n, normalize(rim_direction)));
+
+                color = vec4(bird_col * (ambient + (1.0 - ambient) * diffuse) + vec3(rim), 1.0);
+            }
+        "#;
+
+        let model_program = compile_program(facade, "model", model_vertex_shader_src, model_fragment_shader_src, false)?;
+
+        let point_vertex_shader_src = r#"
+            #version 140
+
+            in vec3 position;
+            in vec3 colour;
+            in float has_colour;
+            in vec4 tint;
+
+            uniform mat4 view;
+            uniform mat4 projection;
+            uniform float point_size;
+            uniform float max_point_size;
+
+            out vec3 v_colour;
+            out float v_has_colour;
+            out vec4 v_tint;
+            out float v_depth;
+
+            void main() {
+                vec4 view_position = view * vec4(position, 1.0);
+                gl_Position = projection * view_position;
+                gl_PointSize = clamp(point_size / max(-view_position.z, 0.1), 1.0, max_point_size);
+                v_colour = colour;
+                v_has_colour = has_colour;
+                v_tint = tint;
+                v_depth = position.z;
+            }
+        "#;
+
+        let point_fragment_shader_src = r#"
+            #version 140
+
+            in vec3 v_colour;
+            in float v_has_colour;
+            in vec4 v_tint;
+            in float v_depth;
+
+            uniform vec3 depth_far;
+            uniform vec3 depth_middle;
+            uniform vec3 depth_near;
+
+            out vec4 color;
+
+            void main() {
+                float nearness = 1.0 - clamp((v_depth + 7.5) / 15.0, 0.0, 1.0);
+                vec3 depth_col = nearness < 0.5
+                    ? mix(depth_far, depth_middle, 2.0 * nearness)
+                    : mix(depth_middle, depth_near, 2.0 * nearness - 1.0);
+                vec3 bird_col = v_has_colour > 0.5 ? v_colour : depth_col;
+                color = vec4(mix(bird_col, v_tint.rgb, v_tint.a), 1.0);
+            }
+        "#;
+
+        // The vertex shader sets gl_PointSize, which glium only enables when asked
+        let point_program = compile_program(facade, "point", point_vertex_shader_src, point_fragment_shader_src, true)?;
+
+        Ok(BirdRenderer {
+            style: RENDER_STYLE,
+            colour_mode: COLOUR_MODE,
+            palette: DEPTH_PALETTE,
+            layers: 1,
+            triangle_buffer,
+            triangle_program,
+            model_buffer,
+            model_program,
+            point_program,
+            context: facade.get_context().clone(),
+        })
+    }
+
+    // Depth testing needs a depth buffer, which offscreen targets don't have.
+    // A colour overrides the colour mode, e.g. to pick out predators, and
+    // anchored birds are always drawn in ANCHORED_COLOUR; the colour mode's
+    // colours are tinted by layer when the flock flies in layers.
+    pub fn draw<S: glium::Surface>(
+        &self,
+        surface: &mut S,
+        birds: &[Bird],
+        camera: &mut Camera,
+        depth_test: bool,
+        colour: Option<[f32; 3]>,
+        scale: f32,
+    ) -> Result<(), BirdflockError> {
+        let (view_matrix, projection_matrix) = camera.matrices();
+        let (depth_far, depth_middle, depth_near) = self.palette.colours();
+        let bird_colour = |bird: &Bird| {
+            let anchored = if bird.anchored { Some(ANCHORED_COLOUR) } else { None };
+            let tint = if colour.or(anchored).is_some() { [0.0; 4] } else { layer_tint(bird, self.layers) };
+            let colour = colour.or(anchored).or_else(|| self.colour_mode.colour_of(bird));
+            (colour.is_some(), colour.unwrap_or([1.0, 1.0, 1.0]), tint)
+        };
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        // Birds in or past the LOD band are also collected as points, and the
+        // full style shrinks away across the band so they don't pop
+        let eye = camera.eye.coords - camera.offset;
+        let mut far_points = Vec::new();
+        let mut drawn = 0;
+        let mut flush = || {
+            drawn += 1;
+            if DRAW_FLUSH_EVERY != 0 && drawn % DRAW_FLUSH_EVERY == 0 {
+                self.context.flush();
+            }
+        };
+        let lod = |bird: &Bird, has_colour: bool, colour: [f32; 3], tint: [f32; 4], far_points: &mut Vec<PointVertex>| {
+            let blend = lod_blend((bird.position.cast::<f32>() - eye).norm());
+            if blend > 0.0 {
+                far_points.push(point_vertex(bird, has_colour, colour, tint));
+            }
+            blend
+        };
+
+        match self.style {
+            RenderStyle::Triangle => {
+                for bird in birds {
+                    let (has_colour, colour, tint) = bird_colour(bird);
+                    let blend = lod(bird, has_colour, colour, tint, &mut far_points);
+                    if blend >= 1.0 {
+                        continue;
+                    }
+                    let uniforms = uniform! {
+                        model: bird_model_matrix(bird, false, scale * (1.0 - blend)),
+                        view: view_matrix,
+                        projection: projection_matrix,
+                        depth: bird.position.z as f32, // Pass z position
+                        has_colour: has_colour,
+                        colour: colour,
+                        tint: tint,
+                        depth_far: depth_far,
+                        depth_middle: depth_middle,
+                        depth_near: depth_near,
+                    };
+                    surface
+                        .draw(&self.triangle_buffer, indices, &self.triangle_program, &uniforms, &Default::default())
+                        .map_err(draw_error("birds"))?;
+                    flush();
+                }
+            }
+            RenderStyle::Model => {
+                let params = glium::DrawParameters {
+                    depth: glium::Depth {
+                        test: if depth_test { glium::DepthTest::IfLess } else { glium::DepthTest::Overwrite },
+                        write: depth_test,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                let eye: [f32; 3] = eye.into();
+                for bird in birds {
+                    let (has_colour, colour, tint) = bird_colour(bird);
+                    let blend = lod(bird, has_colour, colour, tint, &mut far_points);
+                    if blend >= 1.0 {
+                        continue;
+                    }
+                    let uniforms = uniform! {
+                        model: bird_model_matrix(bird, true, scale * (1.0 - blend)),
+                        view: view_matrix,
+                        projection: projection_matrix,
+                        depth: bird.position.z as f32,
+                        has_colour: has_colour,
+                        colour: colour,
+                        tint: tint,
+                        depth_far: depth_far,
+                        depth_middle: depth_middle,
+                        depth_near: depth_near,
+                        eye: eye,
+                        light_direction: LIGHT_DIRECTION,
+                        rim_direction: RIM_DIRECTION,
+                        ambient: AMBIENT_LIGHT,
+                        rim_strength: RIM_STRENGTH,
+                    };
+                    surface.draw(&self.model_buffer, indices, &self.model_program, &uniforms, &params).map_err(draw_error("birds"))?;
+                    flush();
+                }
+            }
+            RenderStyle::Points => {
+                far_points.extend(birds.iter().map(|bird| {
+                    let (has_colour, colour, tint) = bird_colour(bird);
+                    point_vertex(bird, has_colour, colour, tint)
+                }));
+            }
+        }
+
+        // Distant birds from the LOD band, or the whole flock in the point style, in one draw
+        if !far_points.is_empty() {
+            let buffer = glium::VertexBuffer::new(&self.context, &far_points)
+                .map_err(|err| BirdflockError::GlInit(format!("cannot create the point vertex buffer: {}", err)))?;
+            let params = glium::DrawParameters {
+                depth: glium::Depth {
+                    test: if depth_test { glium::DepthTest::IfLess } else { glium::DepthTest::Overwrite },
+                    write: depth_test,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let uniforms = uniform! {
+                view: view_matrix,
+                projection: projection_matrix,
+                point_size: POINT_SIZE * scale,
+                max_point_size: MAX_POINT_SIZE * scale,
+                depth_far: depth_far,
+                depth_middle: depth_middle,
+                depth_near: depth_near,
+            };
+            let points = glium::index::NoIndices(glium::index::PrimitiveType::Points);
+            surface.draw(&buffer, points, &self.point_program, &uniforms, &params).map_err(draw_error("bird points"))?;
+        }
+        Ok(())
+    }
+
+    // Bird triangles laid flat on the ground below each bird, drawn before the birds
+    pub fn draw_shadows<S: glium::Surface>(
+        &self,
+        surface: &mut S,
+        birds: &[Bird],
+        camera: &mut Camera,
+        ground_height: f32,
+    ) -> Result<(), BirdflockError> {
+        let (view_matrix, projection_matrix) = camera.matrices();
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let flatten = Matrix4::from_euler_angles(-std::f32::consts::FRAC_PI_2, 0.0, 0.0);
+        let (depth_far, depth_middle, depth_near) = self.palette.colours();
+        for bird in birds {
+            let position = Vector3::new(bird.position.x as f32, ground_height, bird.position.z as f32);
+            let model: [[f32; 4]; 4] = *(Matrix4::new_translation(&position) * flatten).as_ref();
+            let uniforms = uniform! {
+                model: model,
+                view: view_matrix,
+                projection: projection_matrix,
+                depth: bird.position.z as f32,
+                has_colour: true,
+                colour: SHADOW_COLOUR,
+                tint: [0.0f32; 4],
+                depth_far: depth_far,
+                depth_middle: depth_middle,
+                depth_near: depth_near,
+            };
+            surface
+                .draw(&self.triangle_buffer, indices, &self.triangle_program, &uniforms, &Default::default())
+                .map_err(draw_error("shadows"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TailVertex {
+    position: [f32; 3],
+    colour: [f32; 4],
+}
+
+implement_vertex!(TailVertex, position, colour);
+
+// Line segments from each drawn bird back through its tail history, in its speed
+// colour fading to transparent. A tail stops where the bird wrapped around the space
+// rather than streaking across it.
+pub fn tail_lines(birds: &[Bird], tails: &TailHistory, extent: Real) -> Vec<TailVertex> {
+    let opacity = |age: usize| TAIL_OPACITY * (1.0 - age as f32 / TAIL_LENGTH as f32).powf(TAIL_FADE);
+    let mut lines = Vec::new();
+    for (i, bird) in birds.iter().enumerate() {
+        let [r, g, b] = ColourMode::Speed.colour_of(bird).unwrap();
+        let mut head = bird.position;
+        for (age, snapshot) in tails.snapshots().enumerate() {
+            let Some(&position) = snapshot.get(i) else { break };
+            if (position - head).amax() >= extent {
+                break;
+            }
+            lines.push(TailVertex { position: head.cast::<f32>().into(), colour: [r, g, b, opacity(age)] });
+            lines.push(TailVertex { position: position.cast::<f32>().into(), colour: [r, g, b, opacity(age + 1)] });
+            head = position;
+        }
+    }
+    lines
+}
+
+// Tails carry their colour per vertex, so they get their own program and a buffer rebuilt every draw
+pub struct TailRenderer {
+    program: glium::Program,
+    context: Rc<glium::backend::Context>,
+}
+
+impl TailRenderer {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> Result<Self, BirdflockError> {
+        let tail_vertex_shader_src = r#"
+            #version 140
+
+            in vec3 position;
+            in vec4 colour;
+
+            uniform mat4 view;
+            uniform mat4 projection;
+
+            out vec4 v_colour;
+
+            void main() {
+                v_colour = colour;
+                gl_Position = projection * view * vec4(position, 1.0);
+            }
+        "#;
+
+        let tail_fragment_shader_src = r#"
+            #version 140
+
+            in vec4 v_colour;
+
+            out vec4 color;
+
+            void main() {
+                color = v_colour;
+            }
+        "#;
+
+        Ok(TailRenderer {
+            program: compile_program(facade, "tail", tail_vertex_shader_src, tail_fragment_shader_src, false)?,
+            context: facade.get_context().clone(),
+        })
+    }
+
+    pub fn draw<S: glium::Surface>(
+        &self,
+        surface: &mut S,
+        birds: &[Bird],
+        tails: &TailHistory,
+        extent: Real,
+        camera: &mut Camera,
+    ) -> Result<(), BirdflockError> {
+        let lines = tail_lines(birds, tails, extent);
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let (view_matrix, projection_matrix) = camera.matrices();
+        let uniforms = uniform! {
+            view: view_matrix,
+            projection: projection_matrix,
+        };
+        let params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+        let buffer = glium::VertexBuffer::new(&self.context, &lines)
+            .map_err(|err| BirdflockError::GlInit(format!("cannot create the tail vertex buffer: {}", err)))?;
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::LinesList);
+        surface.draw(&buffer, indices, &self.program, &uniforms, &params).map_err(draw_error("tails"))
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct LineVertex {
+    position: [f32; 3],
+}
+
+implement_vertex!(LineVertex, position);
+
+// Unit sphere as latitude and longitude circles, for drawing as a line list
+pub fn wire_sphere_lines() -> Vec<LineVertex> {
+    use std::f32::consts::{PI, TAU};
+    const SEGMENTS: usize = 32;
+    const RINGS: usize = 6;
+
+    let mut lines = Vec::new();
+    for segment in 0..SEGMENTS {
+        let angles = [segment, segment + 1].map(|s| s as f32 / SEGMENTS as f32 * TAU);
+
+        for ring in 1..RINGS {
+            let latitude = ring as f32 / RINGS as f32 * PI - PI / 2.0;
+            for a in angles {
+                lines.push(LineVertex {
+                    position: [latitude.cos() * a.cos(), latitude.sin(), latitude.cos() * a.sin()],
+                });
+            }
+        }
+
+        for meridian in 0..RINGS {
+            let longitude = meridian as f32 / RINGS as f32 * PI;
+            for a in angles {
+                lines.push(LineVertex {
+                    position: [a.cos() * longitude.cos(), a.sin(), a.cos() * longitude.sin()],
+                });
+            }
+        }
+    }
+    lines
+}
+
+// Translucent overlay lines (wireframes, markers) drawn on top of the flock
+pub struct LineRenderer {
+    program: glium::Program,
+}
+
+impl LineRenderer {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> Result<Self, BirdflockError> {
+        let line_vertex_shader_src = r#"
+            #version 140
+
+            in vec3 position;
+
+            uniform mat4 model;
+            uniform mat4 view;
+            uniform mat4 projection;
+
+            void main() {
+                gl_Position = projection * view * model * vec4(position, 1.0);
+            }
+        "#;
+
+        let line_fragment_shader_src = r#"
+            #version 140
+
+            uniform vec4 line_colour;
+
+            out vec4 color;
+
+            void main() {
+                color = line_colour;
+            }
+        "#;
+
+        Ok(LineRenderer {
+            program: compile_program(facade, "line", line_vertex_shader_src, line_fragment_shader_src, false)?,
+        })
+    }
+
+    // From a whole vertex buffer or a slice of one
+    pub fn draw<'a, S: glium::Surface, V: glium::vertex::MultiVerticesSource<'a>>(
+        &self,
+        surface: &mut S,
+        lines: V,
+        model: Matrix4<Real>, // in simulation space, like the birds
+        camera: &mut Camera,
+        colour: [f32; 4],
+    ) -> Result<(), BirdflockError> {
+        let (view_matrix, projection_matrix) = camera.matrices();
+        let model_matrix: [[f32; 4]; 4] = *model.cast::<f32>().as_ref();
+        let uniforms = uniform! {
+            model: model_matrix,
+            view: view_matrix,
+            projection: projection_matrix,
+            line_colour: colour,
+        };
+        let params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::LinesList);
+        surface.draw(lines, indices, &self.program, &uniforms, &params).map_err(draw_error("lines"))
+    }
+}
+
+// Overlay lines rebuilt every frame, such as the octree or a centroid marker, written
+// into one buffer that is only reallocated, at the next power of two, when they outgrow it
+#[derive(Default)]
+pub struct OverlayLines {
+    buffer: Option<glium::VertexBuffer<LineVertex>>,
+}
+
+impl OverlayLines {
+    pub fn draw<F: glium::backend::Facade, S: glium::Surface>(
+        &mut self,
+        facade: &F,
+        surface: &mut S,
+        renderer: &LineRenderer,
+        lines: &[LineVertex],
+        camera: &mut Camera,
+        colour: [f32; 4],
+    ) -> Result<(), BirdflockError> {
+        if lines.len() > self.buffer.as_ref().map_or(0, |buffer| buffer.len()) {
+            let buffer = glium::VertexBuffer::empty_dynamic(facade, lines.len().next_power_of_two())
+                .map_err(|err| BirdflockError::GlInit(format!("cannot grow an overlay vertex buffer: {}", err)))?;
+            self.buffer = Some(buffer);
+        }
+        let Some(slice) = self.buffer.as_ref().and_then(|buffer| buffer.slice(..lines.len())) else {
+            return Ok(()); // no lines, and never any buffer
+        };
+        slice.write(lines);
+        renderer.draw(surface, slice, Matrix4::identity(), camera, colour)
+    }
+}
+
+// Edges of the cube [-1, 1]^3, for drawing as a line list
+pub fn wire_cube_lines() -> Vec<LineVertex> {
+    let mut lines = Vec::new();
+    for axis in 0..3 {
+        for corner in 0..4 {
+            let mut start = [-1.0; 3];
+            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+            start[a] = if corner & 1 == 0 { -1.0 } else { 1.0 };
+            start[b] = if corner & 2 == 0 { -1.0 } else { 1.0 };
+            let mut end = start;
+            end[axis] = 1.0;
+            lines.push(LineVertex { position: start });
+            lines.push(LineVertex { position: end });
+        }
+    }
+    lines
+}
+
+// Edges of every occupied octree leaf, for drawing as a line list
+pub fn octree_lines(octree: &Octree) -> Vec<LineVertex> {
+    let cube = wire_cube_lines();
+    let mut lines = Vec::new();
+    for leaf in octree.leaves().filter(|leaf| leaf.count() > 0) {
+        let half = leaf.size as f32 / 2.0;
+        let centre = leaf.min.cast::<f32>().add_scalar(half);
+        lines.extend(cube.iter().map(|vertex| LineVertex { position: (centre + Vector3::from(vertex.position) * half).into() }));
+    }
+    lines
+}
+
+// Crosshair `size` across each way at the flock's centroid, and a line from it along the
+// mean velocity to where the centroid will be after CENTROID_VELOCITY_TIME, for drawing
+// as a line list. Both come from a single pass over the birds
+pub fn centroid_marker_lines(birds: &[Bird], size: Real) -> Vec<LineVertex> {
+    if birds.is_empty() {
+        return Vec::new();
+    }
+    let mut position_sum = Vector3::zeros();
+    let mut velocity_sum = Vector3::zeros();
+    for bird in birds {
+        position_sum += bird.position;
+        velocity_sum += bird.velocity;
+    }
+    let count = birds.len() as Real;
+    let (centroid, mean_velocity) = (position_sum / count, velocity_sum / count);
+
+    let mut lines = Vec::new();
+    for axis in 0..3 {
+        let arm = Vector3::ith(axis, size);
+        lines.push(LineVertex { position: (centroid - arm).cast::<f32>().into() });
+        lines.push(LineVertex { position: (centroid + arm).cast::<f32>().into() });
+    }
+    lines.push(LineVertex { position: centroid.cast::<f32>().into() });
+    lines.push(LineVertex { position: (centroid + mean_velocity * CENTROID_VELOCITY_TIME).cast::<f32>().into() });
+    lines
+}
+
+// Square grid spanning -1..1 in x and z at y = 0, for drawing as a line list
+pub fn ground_lines() -> Vec<LineVertex> {
+    let mut lines = Vec::new();
+    for i in 0..=GROUND_DIVISIONS {
+        let t = i as f32 / GROUND_DIVISIONS as f32 * 2.0 - 1.0;
+        lines.push(LineVertex { position: [t, 0.0, -1.0] });
+        lines.push(LineVertex { position: [t, 0.0, 1.0] });
+        lines.push(LineVertex { position: [-1.0, 0.0, t] });
+        lines.push(LineVertex { position: [1.0, 0.0, t] });
+    }
+    lines
+}
+
+// Offscreen buffer the heatmap accumulates into, cleared once on creation
+pub fn new_heatmap<F: glium::backend::Facade>(facade: &F, (width, height): (u32, u32)) -> Result<glium::texture::Texture2d, BirdflockError> {
+    // Float format so slow fades decay fully instead of leaving 8-bit residue
+    let texture = glium::texture::Texture2d::empty_with_format(
+        facade,
+        glium::texture::UncompressedFloatFormat::F16F16F16F16,
+        glium::texture::MipmapsOption::NoMipmap,
+        width,
+        height,
+    ).map_err(|err| BirdflockError::GlInit(format!("cannot create the heatmap texture: {}", err)))?;
+    texture.as_surface().clear_color(0.0, 0.0, 0.0, 1.0);
+    Ok(texture)
+}
@@ -0,0 +1,16 @@
+use nalgebra::Vector3;
+
+use crate::bird::{limit_vec, Bird};
+use crate::params::RoostParams;
+use crate::real::Real;
+
+// Steering to arrive at the roost, slowing to a stop over its radius so the flock settles there
+pub fn roost_force(bird: &Bird, roost: &RoostParams, max_speed: Real, max_force: Real) -> Vector3<Real> {
+    let offset = Vector3::from(roost.position) - bird.position;
+    let distance = offset.norm();
+    if distance == 0.0 {
+        return limit_vec(-bird.velocity, max_force);
+    }
+    let speed = max_speed * (distance / roost.radius.max(Real::EPSILON)).min(1.0);
+    limit_vec(offset / distance * speed - bird.velocity, max_force)
+}
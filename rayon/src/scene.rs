@@ -0,0 +1,697 @@
+use nalgebra::Vector3;
+
+use crate::demo::{Demo, DemoAction, DemoStage};
+use crate::error::BirdflockError;
+use crate::obstacle::Obstacle;
+use crate::params::{Boundary, BoundaryMode, Current, Distribution, Integration, SimulationParams, StallAction, TagRule, ALIGNMENT_WEIGHT, COHESION_WEIGHT, SEPARATION_WEIGHT};
+use crate::real::Real;
+use crate::schedule::{WeightKeyframe, WeightSchedule};
+use crate::simulation::Simulation;
+use crate::waypoint::WaypointPath;
+
+// Settings loaded from a scene file of `key = value` lines, `#` starts a comment:
+//
+//     # step separation alignment cohesion
+//     keyframe = 0    0.5 1.0 4.0
+//     keyframe = 300  6.0 1.0 0.5
+//     keyframe = 600  1.5 2.0 1.5
+//
+// Each `flock = separation alignment cohesion` line adds an independent flock,
+// drawn side by side with the others for comparison.
+//
+// `obstacle = x y z radius` places a fixed sphere, and `obstacle = x y z radius vx vy vz`
+// a moving one that falls under gravity and bounces off the bounds.
+//
+// `anchor = bird` holds a bird where it is and `anchor = bird x y z` moves it there
+// first, e.g. to perch a row of birds on a wire.
+//
+// `waypoint = x y z` lines form a looping path, in order, that the flock is drawn
+// along; it moves on to the next waypoint once its centroid is within `waypoint_threshold`.
+// Give it bouncing or clamped walls, since birds wrapping round keep the centroid central.
+//
+// Any other simulation parameter can be set by its field name, e.g. `perception_radius = 1.2`,
+// `boundary_modes = wrap bounce wrap` or `current_direction = 1 0 0`, and applies to every flock.
+//
+// `tag = tag fraction` gives tag number `tag` to that fraction of the birds, followed by
+// any of `perception x`, `max_speed x` and `max_force x` to scale those for the birds
+// carrying it, e.g. `tag = 0 0.05 perception 2` for a few far-sighted scouts.
+// `arrival_tags` is the bitset every arriving bird carries besides.
+//
+// `world_scale = s` runs the scene in a world `s` times the size, with every length,
+// speed and acceleration scaled to match (see SimulationParams::scaled), obstacles,
+// waypoints and anchors included, so the rest of the scene is written at the base size.
+//
+// `demo_stage = steps name` begins a stage of a looping demo, and the `demo = action`
+// lines after it run as it begins: `weights separation alignment cohesion`,
+// `set parameter value`, `shockwave` (at the centroid) or `shockwave x y z`, `flash` or
+// `flash x y z` likewise, `gust`, `predators count` or `birds count` (added at the
+// arrival point). See scenes/demo.scene.
+//
+// `spawn_positions` and `spawn_velocities` take `uniform extent`, `normal mx my mz stddev`
+// or `shell radius`, e.g. `spawn_velocities = shell 0.5` starts every bird at the same speed.
+#[derive(Default)]
+pub struct Scene {
+    pub parameters: Vec<(&'static str, String)>,
+    pub schedule: Option<WeightSchedule>,
+    pub flocks: Vec<FlockWeights>,
+    pub obstacles: Vec<Obstacle>,
+    pub anchors: Vec<Anchor>,
+    pub waypoints: Vec<Vector3<Real>>,
+    pub demo: Option<Demo>,
+    pub tag_rules: Vec<TagRule>,
+    pub world_scale: Option<Real>, // None keeps the flock's current scale
+}
+
+#[derive(Clone, Copy)]
+pub struct Anchor {
+    pub bird: usize,
+    pub position: Option<Vector3<Real>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct FlockWeights {
+    pub separation: Real,
+    pub alignment: Real,
+    pub cohesion: Real,
+}
+
+impl Scene {
+    pub fn load(path: &str) -> Result<Self, BirdflockError> {
+        let text = std::fs::read_to_string(path).map_err(|error| BirdflockError::io(path, error))?;
+        Scene::parse(&text).map_err(|err| match err {
+            BirdflockError::InvalidParams(message) => BirdflockError::InvalidParams(format!("scene '{}': {}", path, message)),
+            err => BirdflockError::Parse(format!("scene '{}': {}", path, err)),
+        })
+    }
+
+    // Capture the flocks' current parameters, weights, schedule, obstacles and anchored
+    // birds, so tuning done while running can be saved and loaded again. The shared
+    // parameters, schedule and obstacles are taken from the first flock, and everything
+    // is written at the base size along with the world scale.
+    pub fn capture(simulations: &[&Simulation]) -> Self {
+        let first = simulations[0];
+        let base = first.params.scaled(1.0);
+        let unscale = 1.0 / first.params.world_scale;
+        Scene {
+            parameters: PARAMETERS.iter().map(|&name| (name, get_parameter(&base, name).unwrap())).collect(),
+            schedule: first.schedule.clone(),
+            flocks: simulations
+                .iter()
+                .map(|simulation| FlockWeights {
+                    separation: simulation.params.separation_weight,
+                    alignment: simulation.params.alignment_weight,
+                    cohesion: simulation.params.cohesion_weight,
+                })
+                .collect(),
+            obstacles: first.obstacles.iter().map(|obstacle| obstacle.scaled(unscale)).collect(),
+            anchors: first
+                .birds
+                .iter()
+                .enumerate()
+                .filter(|(_, bird)| bird.anchored)
+                .map(|(bird, anchored)| Anchor { bird, position: Some(anchored.position * unscale) })
+                .collect(),
+            waypoints: first
+                .waypoints
+                .as_ref()
+                .map(|path| path.waypoints().iter().map(|p| p * unscale).collect())
+                .unwrap_or_default(),
+            demo: first.demo.as_ref().map(|demo| Demo::new(demo.stages().to_vec())), // from its first stage
+            tag_rules: first.params.tag_rules.clone(),
+            world_scale: Some(first.params.world_scale).filter(|&scale| scale != 1.0),
+        }
+    }
+
+    // The scene in the format `parse` reads, so `parse(to_text())` gives the same scene back
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(scale) = self.world_scale {
+            text += &format!("world_scale = {}\n", scale);
+        }
+        for (name, value) in &self.parameters {
+            text += &format!("{} = {}\n", name, value);
+        }
+        for flock in &self.flocks {
+            text += &format!("flock = {} {} {}\n", flock.separation, flock.alignment, flock.cohesion);
+        }
+        for keyframe in self.schedule.iter().flat_map(WeightSchedule::keyframes) {
+            text += &format!(
+                "keyframe = {} {} {} {}\n",
+                keyframe.step, keyframe.separation, keyframe.alignment, keyframe.cohesion
+            );
+        }
+        for obstacle in &self.obstacles {
+            let p = obstacle.position;
+            text += &format!("obstacle = {} {} {} {}", p.x, p.y, p.z, obstacle.radius);
+            if obstacle.moving {
+                let v = obstacle.velocity;
+                text += &format!(" {} {} {}", v.x, v.y, v.z);
+            }
+            text += "\n";
+        }
+        for p in &self.waypoints {
+            text += &format!("waypoint = {} {} {}\n", p.x, p.y, p.z);
+        }
+        for rule in &self.tag_rules {
+            text += &format!(
+                "tag = {} {} perception {} max_speed {} max_force {}\n",
+                rule.tag, rule.fraction, rule.perception, rule.max_speed, rule.max_force
+            );
+        }
+        for anchor in &self.anchors {
+            text += &format!("anchor = {}", anchor.bird);
+            if let Some(p) = anchor.position {
+                text += &format!(" {} {} {}", p.x, p.y, p.z);
+            }
+            text += "\n";
+        }
+        for stage in self.demo.iter().flat_map(Demo::stages) {
+            text += &format!("demo_stage = {} {}\n", stage.steps, stage.name);
+            for action in &stage.actions {
+                text += &format!("demo = {}\n", demo_action_text(action));
+            }
+        }
+        text
+    }
+
+    // Parse a scene, checking that the parameters it sets are valid together
+    pub fn parse(text: &str) -> Result<Self, BirdflockError> {
+        let scene = parse_lines(text).map_err(BirdflockError::Parse)?;
+        let mut params = SimulationParams::default();
+        scene.apply_params(&mut params);
+        params.validate()?;
+        for stage in scene.demo.iter().flat_map(Demo::stages) {
+            for action in &stage.actions {
+                if let DemoAction::Set(name, value) = action {
+                    let mut changed = params.clone();
+                    set_parameter(&mut changed, name, value).unwrap(); // checked when parsed
+                    if let Err(BirdflockError::InvalidParams(message)) = changed.validate() {
+                        return Err(BirdflockError::InvalidParams(format!("{} in demo stage '{}'", message, stage.name)));
+                    }
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    // Set the parameters shared by every flock, tag rules included, e.g. to spawn the
+    // birds before there's a simulation. They are set at the base size and the result
+    // scaled to the scene's world scale
+    pub fn apply_params(&self, params: &mut SimulationParams) {
+        let world_scale = self.world_scale.unwrap_or(params.world_scale);
+        let mut base = params.scaled(1.0);
+        for (name, value) in &self.parameters {
+            set_parameter(&mut base, name, value).unwrap(); // checked when parsed
+        }
+        base.tag_rules = self.tag_rules.clone();
+        *params = base.scaled(world_scale);
+    }
+
+    // Simulations the scene describes, one when it has no `flock` lines
+    pub fn flock_count(&self) -> usize {
+        self.flocks.len().max(1)
+    }
+
+    // Set flock `index`'s parameters, weights, schedule, obstacles, anchors and waypoints, leaving its other birds where they are
+    pub fn apply(&self, index: usize, simulation: &mut Simulation) {
+        self.apply_params(&mut simulation.params);
+        let weights = self.flocks.get(index).copied().unwrap_or(FlockWeights {
+            separation: SEPARATION_WEIGHT,
+            alignment: ALIGNMENT_WEIGHT,
+            cohesion: COHESION_WEIGHT,
+        });
+        simulation.params.separation_weight = weights.separation;
+        simulation.params.alignment_weight = weights.alignment;
+        simulation.params.cohesion_weight = weights.cohesion;
+        let scale = simulation.params.world_scale;
+        simulation.schedule = self.schedule.clone();
+        simulation.obstacles = self.obstacles.iter().map(|obstacle| obstacle.scaled(scale)).collect();
+        simulation.waypoints = if self.waypoints.is_empty() {
+            None
+        } else {
+            Some(WaypointPath::new(self.waypoints.iter().map(|p| p * scale).collect()))
+        };
+        simulation.demo = self.demo.clone();
+
+        for bird in &mut simulation.birds {
+            bird.anchored = false;
+        }
+        for anchor in &self.anchors {
+            if let Some(bird) = simulation.birds.get_mut(anchor.bird) {
+                bird.anchored = true;
+                bird.velocity = Vector3::zeros();
+                bird.acceleration = Vector3::zeros();
+                if let Some(position) = anchor.position {
+                    bird.position = position * scale;
+                }
+            }
+        }
+    }
+}
+
+// The scene's lines, each value checked on its own
+fn parse_lines(text: &str) -> Result<Scene, String> {
+    let mut parameters = Vec::new();
+    let mut keyframes = Vec::new();
+    let mut flocks = Vec::new();
+    let mut obstacles = Vec::new();
+    let mut anchors = Vec::new();
+    let mut waypoints = Vec::new();
+    let mut stages: Vec<DemoStage> = Vec::new();
+    let mut tag_rules = Vec::new();
+    let mut world_scale = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", number + 1))?;
+
+        match key.trim() {
+            "keyframe" => keyframes.push(parse_keyframe(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "flock" => flocks.push(parse_flock(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "obstacle" => obstacles.push(parse_obstacle(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "anchor" => anchors.push(parse_anchor(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "waypoint" => waypoints.push(parse_waypoint(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "world_scale" => world_scale = Some(parse_world_scale(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "tag" => tag_rules.push(parse_tag_rule(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "demo_stage" => stages.push(parse_demo_stage(value).map_err(|err| format!("line {}: {}", number + 1, err))?),
+            "demo" => {
+                let action = parse_demo_action(value).map_err(|err| format!("line {}: {}", number + 1, err))?;
+                stages
+                    .last_mut()
+                    .ok_or_else(|| format!("line {}: `demo` needs a `demo_stage` before it", number + 1))?
+                    .actions
+                    .push(action);
+            }
+            key if PARAMETERS.contains(&key) => {
+                // Check the value now and keep it in its canonical form
+                let mut params = SimulationParams::default();
+                set_parameter(&mut params, key, value.trim()).map_err(|err| format!("line {}: {}", number + 1, err))?;
+                let name = PARAMETERS.iter().find(|&&name| name == key).unwrap();
+                parameters.push((*name, get_parameter(&params, name).unwrap()));
+            }
+            key => return Err(format!("line {}: unknown key '{}'", number + 1, key)),
+        }
+    }
+
+    Ok(Scene {
+        parameters,
+        schedule: if keyframes.is_empty() { None } else { Some(WeightSchedule::new(keyframes)) },
+        flocks,
+        obstacles,
+        anchors,
+        waypoints,
+        demo: if stages.is_empty() { None } else { Some(Demo::new(stages)) },
+        tag_rules,
+        world_scale,
+    })
+}
+
+fn parse_weight(field: &str) -> Result<Real, String> {
+    field.parse().map_err(|_| format!("invalid weight '{}'", field))
+}
+
+fn parse_keyframe(value: &str) -> Result<WeightKeyframe, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != 4 {
+        return Err("keyframe needs `step separation alignment cohesion`".to_string());
+    }
+    Ok(WeightKeyframe {
+        step: fields[0].parse().map_err(|_| format!("invalid step '{}'", fields[0]))?,
+        separation: parse_weight(fields[1])?,
+        alignment: parse_weight(fields[2])?,
+        cohesion: parse_weight(fields[3])?,
+    })
+}
+
+fn parse_flock(value: &str) -> Result<FlockWeights, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err("flock needs `separation alignment cohesion`".to_string());
+    }
+    Ok(FlockWeights {
+        separation: parse_weight(fields[0])?,
+        alignment: parse_weight(fields[1])?,
+        cohesion: parse_weight(fields[2])?,
+    })
+}
+
+fn parse_obstacle(value: &str) -> Result<Obstacle, String> {
+    let fields: Vec<Real> = value
+        .split_whitespace()
+        .map(|field| field.parse().map_err(|_| format!("invalid number '{}'", field)))
+        .collect::<Result<_, _>>()?;
+    let (position, radius, velocity) = match fields.len() {
+        4 | 7 => (
+            Vector3::new(fields[0], fields[1], fields[2]),
+            fields[3],
+            fields.get(4..7).map(Vector3::from_column_slice),
+        ),
+        _ => return Err("obstacle needs `x y z radius` or `x y z radius vx vy vz`".to_string()),
+    };
+    if radius <= 0.0 {
+        return Err(format!("invalid obstacle radius '{}'", radius));
+    }
+    Ok(Obstacle {
+        position,
+        velocity: velocity.unwrap_or_else(Vector3::zeros),
+        radius,
+        moving: velocity.is_some(),
+    })
+}
+
+fn parse_waypoint(value: &str) -> Result<Vector3<Real>, String> {
+    let coordinates: Vec<Real> = value
+        .split_whitespace()
+        .map(|field| field.parse().map_err(|_| format!("invalid number '{}'", field)))
+        .collect::<Result<_, _>>()?;
+    if coordinates.len() != 3 {
+        return Err("waypoint needs `x y z`".to_string());
+    }
+    Ok(Vector3::from_column_slice(&coordinates))
+}
+
+fn parse_world_scale(value: &str) -> Result<Real, String> {
+    match value.trim().parse::<Real>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        _ => Err(format!("world_scale must be a positive number, not '{}'", value.trim())),
+    }
+}
+
+fn parse_tag_rule(value: &str) -> Result<TagRule, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() < 2 || !fields.len().is_multiple_of(2) {
+        return Err("tag needs `tag fraction` then any of `perception x`, `max_speed x` and `max_force x`".to_string());
+    }
+    let number = |field: &str| field.parse::<Real>().map_err(|_| format!("invalid number '{}'", field));
+    let mut rule = TagRule {
+        tag: fields[0].parse().map_err(|_| format!("invalid tag '{}'", fields[0]))?,
+        fraction: number(fields[1])?,
+        perception: 1.0,
+        max_speed: 1.0,
+        max_force: 1.0,
+    };
+    for pair in fields[2..].chunks(2) {
+        let scale = number(pair[1])?;
+        match pair[0] {
+            "perception" => rule.perception = scale,
+            "max_speed" => rule.max_speed = scale,
+            "max_force" => rule.max_force = scale,
+            other => return Err(format!("unknown tag scale '{}'", other)),
+        }
+    }
+    Ok(rule)
+}
+
+fn parse_demo_stage(value: &str) -> Result<DemoStage, String> {
+    let (steps, name) = value.trim().split_once(char::is_whitespace).ok_or("demo_stage needs `steps name`")?;
+    let steps = steps.parse().ok().filter(|&steps| steps > 0).ok_or_else(|| format!("invalid stage length '{}'", steps))?;
+    Ok(DemoStage { name: name.trim().to_string(), steps, actions: Vec::new() })
+}
+
+fn parse_demo_action(value: &str) -> Result<DemoAction, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let numbers = |fields: &[&str]| -> Result<Vec<Real>, String> {
+        fields.iter().map(|field| field.parse().map_err(|_| format!("invalid number '{}'", field))).collect()
+    };
+    let count = |field: &str| field.parse().map_err(|_| format!("invalid count '{}'", field));
+    match fields[..] {
+        ["weights", ..] => match numbers(&fields[1..])?[..] {
+            [separation, alignment, cohesion] => Ok(DemoAction::Weights(separation, alignment, cohesion)),
+            _ => Err("weights needs `separation alignment cohesion`".to_string()),
+        },
+        ["set", name, ..] => {
+            let value = fields[2..].join(" ");
+            let name = PARAMETERS.iter().find(|&&known| known == name).ok_or_else(|| format!("unknown parameter '{}'", name))?;
+            let mut params = SimulationParams::default();
+            set_parameter(&mut params, name, &value)?;
+            Ok(DemoAction::Set(name, get_parameter(&params, name).unwrap()))
+        }
+        ["shockwave"] => Ok(DemoAction::Shockwave(None)),
+        ["shockwave", ..] => match numbers(&fields[1..])?[..] {
+            [x, y, z] => Ok(DemoAction::Shockwave(Some(Vector3::new(x, y, z)))),
+            _ => Err("shockwave needs no position or `x y z`".to_string()),
+        },
+        ["flash"] => Ok(DemoAction::Flash(None)),
+        ["flash", ..] => match numbers(&fields[1..])?[..] {
+            [x, y, z] => Ok(DemoAction::Flash(Some(Vector3::new(x, y, z)))),
+            _ => Err("flash needs no position or `x y z`".to_string()),
+        },
+        ["gust"] => Ok(DemoAction::Gust),
+        ["predators", n] => Ok(DemoAction::Predators(count(n)?)),
+        ["birds", n] => Ok(DemoAction::Birds(count(n)?)),
+        _ => Err(format!("unknown demo action '{}'", value.trim())),
+    }
+}
+
+fn demo_action_text(action: &DemoAction) -> String {
+    match action {
+        DemoAction::Weights(separation, alignment, cohesion) => format!("weights {} {} {}", separation, alignment, cohesion),
+        DemoAction::Set(name, value) => format!("set {} {}", name, value),
+        DemoAction::Shockwave(None) => "shockwave".to_string(),
+        DemoAction::Shockwave(Some(p)) => format!("shockwave {} {} {}", p.x, p.y, p.z),
+        DemoAction::Flash(None) => "flash".to_string(),
+        DemoAction::Flash(Some(p)) => format!("flash {} {} {}", p.x, p.y, p.z),
+        DemoAction::Gust => "gust".to_string(),
+        DemoAction::Predators(count) => format!("predators {}", count),
+        DemoAction::Birds(count) => format!("birds {}", count),
+    }
+}
+
+fn parse_anchor(value: &str) -> Result<Anchor, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != 1 && fields.len() != 4 {
+        return Err("anchor needs `bird` or `bird x y z`".to_string());
+    }
+    let bird = fields[0].parse().map_err(|_| format!("invalid bird index '{}'", fields[0]))?;
+    let position = if fields.len() == 4 {
+        let coordinates: Vec<Real> = fields[1..]
+            .iter()
+            .map(|field| field.parse().map_err(|_| format!("invalid number '{}'", field)))
+            .collect::<Result<_, _>>()?;
+        Some(Vector3::from_column_slice(&coordinates))
+    } else {
+        None
+    };
+    Ok(Anchor { bird, position })
+}
+
+// Parameter values as written in a scene file
+trait SceneValue: Sized {
+    fn to_scene(&self) -> String;
+    fn from_scene(text: &str) -> Option<Self>;
+}
+
+macro_rules! scene_value {
+    ($($ty:ty),*) => {$(
+        impl SceneValue for $ty {
+            fn to_scene(&self) -> String {
+                self.to_string()
+            }
+
+            fn from_scene(text: &str) -> Option<Self> {
+                text.parse().ok()
+            }
+        }
+    )*};
+}
+
+scene_value!(Real, u32, usize, bool);
+
+impl<T: SceneValue + Copy> SceneValue for [T; 3] {
+    fn to_scene(&self) -> String {
+        format!("{} {} {}", self[0].to_scene(), self[1].to_scene(), self[2].to_scene())
+    }
+
+    fn from_scene(text: &str) -> Option<Self> {
+        let values: Vec<T> = text.split_whitespace().map(T::from_scene).collect::<Option<_>>()?;
+        match values[..] {
+            [x, y, z] => Some([x, y, z]),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! scene_enum {
+    ($ty:ident { $($variant:ident => $name:literal),* }) => {
+        impl SceneValue for $ty {
+            fn to_scene(&self) -> String {
+                match self {
+                    $($ty::$variant => $name,)*
+                }
+                .to_string()
+            }
+
+            fn from_scene(text: &str) -> Option<Self> {
+                match text {
+                    $($name => Some($ty::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+scene_enum!(Boundary { Box => "box", SoftSphere => "soft_sphere", HardSphere => "hard_sphere" });
+scene_enum!(BoundaryMode { Wrap => "wrap", Bounce => "bounce", Clamp => "clamp" });
+scene_enum!(Current { Off => "off", Drift => "drift", Vortex => "vortex", Curl => "curl" });
+scene_enum!(Integration { AddThenClamp => "add_then_clamp", TurnThenSpeed => "turn_then_speed" });
+scene_enum!(StallAction { Off => "off", Log => "log", Reseed => "reseed" });
+
+impl SceneValue for Distribution {
+    fn to_scene(&self) -> String {
+        match self {
+            Distribution::Uniform { extent } => format!("uniform {}", extent),
+            Distribution::Normal { mean, stddev } => format!("normal {} {}", mean.to_scene(), stddev),
+            Distribution::Shell { radius } => format!("shell {}", radius),
+        }
+    }
+
+    fn from_scene(text: &str) -> Option<Self> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let numbers: Vec<Real> = fields.get(1..)?.iter().map(|field| field.parse().ok()).collect::<Option<_>>()?;
+        match (fields[0], &numbers[..]) {
+            ("uniform", &[extent]) => Some(Distribution::Uniform { extent }),
+            ("normal", &[x, y, z, stddev]) => Some(Distribution::Normal { mean: [x, y, z], stddev }),
+            ("shell", &[radius]) => Some(Distribution::Shell { radius }),
+            _ => None,
+        }
+    }
+}
+
+// Scene keys for the simulation parameters and the fields they set. The flocking
+// weights are left out, `flock` and `keyframe` lines set those per flock.
+macro_rules! scene_parameters {
+    ($($name:literal => $($field:ident).+,)*) => {
+        pub(crate) const PARAMETERS: &[&str] = &[$($name),*];
+
+        pub(crate) fn get_parameter(params: &SimulationParams, name: &str) -> Option<String> {
+            match name {
+                $($name => Some(params$(.$field)+.to_scene()),)*
+                _ => None,
+            }
+        }
+
+        pub(crate) fn set_parameter(params: &mut SimulationParams, name: &str, value: &str) -> Result<(), String> {
+            match name {
+                $($name => {
+                    params$(.$field)+ = SceneValue::from_scene(value)
+                        .ok_or_else(|| format!("invalid value '{}' for {}", value, name))?
+                })*
+                _ => return Err(format!("unknown parameter '{}'", name)),
+            }
+            Ok(())
+        }
+    };
+}
+
+scene_parameters! {
+    "extent" => extent,
+    "separation_enabled" => separation_enabled,
+    "alignment_enabled" => alignment_enabled,
+    "cohesion_enabled" => cohesion_enabled,
+    "perception_radius" => perception_radius,
+    "perception_pulse_amplitude" => perception_pulse_amplitude,
+    "perception_pulse_period" => perception_pulse_period,
+    "separation_front" => separation_shape.front,
+    "separation_side" => separation_shape.side,
+    "separation_back" => separation_shape.back,
+    "max_speed" => max_speed,
+    "min_speed" => min_speed,
+    "max_force" => max_force,
+    "max_turn_rate" => max_turn_rate,
+    "drag" => drag,
+    "gravity" => gravity,
+    "lift_coefficient" => lift_coefficient,
+    "dt" => dt,
+    "centre_attraction" => centre_attraction,
+    "waypoint_attraction" => waypoint_attraction,
+    "waypoint_threshold" => waypoint_threshold,
+    "flee_radius" => flee_radius,
+    "flee_weight" => flee_weight,
+    "obstacle_weight" => obstacle_weight,
+    "obstacle_margin" => obstacle_margin,
+    "obstacle_lookahead" => obstacle_lookahead,
+    "obstacle_gravity" => obstacle_gravity,
+    "gust_rate" => gust_rate,
+    "gust_strength" => gust_strength,
+    "gust_lifetime" => gust_lifetime,
+    "shockwave_speed" => shockwave_speed,
+    "shockwave_strength" => shockwave_strength,
+    "shockwave_width" => shockwave_width,
+    "shockwave_lifetime" => shockwave_lifetime,
+    "flash_speed" => flash_speed,
+    "flash_strength" => flash_strength,
+    "flash_width" => flash_width,
+    "flash_lifetime" => flash_lifetime,
+    "startle_radius" => startle_radius,
+    "startle_decay" => startle_decay,
+    "startle_spread" => startle_spread,
+    "startle_contagion" => startle_contagion,
+    "startle_speed_boost" => startle_speed_boost,
+    "startle_separation_boost" => startle_separation_boost,
+    "current" => current,
+    "current_strength" => current_strength,
+    "current_direction" => current_direction,
+    "current_scale" => current_scale,
+    "alignment_noise" => alignment_noise,
+    "cohesion_dead_zone" => cohesion_dead_zone,
+    "cohesion_memory" => cohesion_memory,
+    "cohesion_lookahead" => cohesion_lookahead,
+    "comfort_density" => comfort_density,
+    "comfort_weight" => comfort_weight,
+    "acceleration_smoothing" => acceleration_smoothing,
+    "integration" => integration,
+    "topological_neighbors" => topological_neighbors,
+    "critical_radius" => critical_radius,
+    "critical_suppression" => critical_suppression,
+    "use_grid" => use_grid,
+    "use_octree" => use_octree,
+    "pairwise_forces" => pairwise_forces,
+    "grid_cell_size" => grid_cell_size,
+    "distance_matrix_birds" => distance_matrix_birds,
+    "chunk_size" => chunk_size,
+    "approximation_distance" => approximation_distance,
+    "boundary" => boundary,
+    "boundary_modes" => boundary_modes,
+    "boundary_radius" => boundary_radius,
+    "boundary_weight" => boundary_weight,
+    "boundary_margin" => boundary_margin,
+    "boundary_lookahead" => boundary_lookahead,
+    "stall_action" => stall.action,
+    "stall_order_threshold" => stall.order_threshold,
+    "stall_speed_threshold" => stall.speed_threshold,
+    "stall_steps" => stall.steps,
+    "roost_day" => roost.day,
+    "roost_night" => roost.night,
+    "roost_position" => roost.position,
+    "roost_weight" => roost.weight,
+    "roost_radius" => roost.radius,
+    "roost_speed" => roost.speed,
+    "layers" => layers.count,
+    "layer_coupling" => layers.coupling,
+    "layer_spacing" => layers.spacing,
+    "layer_weight" => layers.weight,
+    "spawn_positions" => spawn_positions,
+    "spawn_velocities" => spawn_velocities,
+    "arrival_every" => arrivals.every,
+    "arrival_count" => arrivals.count,
+    "arrival_max_birds" => arrivals.max_birds,
+    "arrival_position" => arrivals.spawn.position,
+    "arrival_radius" => arrivals.spawn.radius,
+    "arrival_speed" => arrivals.spawn.speed,
+    "arrival_tags" => arrivals.spawn.tags,
+    "straggler_steps" => straggler_steps,
+    "predator_separation_weight" => predator.separation_weight,
+    "predator_alignment_weight" => predator.alignment_weight,
+    "predator_cohesion_weight" => predator.cohesion_weight,
+    "predator_chase_weight" => predator.chase_weight,
+    "predator_perception_radius" => predator.perception_radius,
+    "predator_max_speed" => predator.max_speed,
+    "predator_max_force" => predator.max_force,
+    "predator_catch_radius" => predator.catch_radius,
+}
@@ -0,0 +1,53 @@
+use crate::real::Real;
+
+// Scripted flocking weights, e.g. a tight ball that explodes and then settles
+
+#[derive(Clone, Copy)]
+pub struct WeightKeyframe {
+    pub step: usize,
+    pub separation: Real,
+    pub alignment: Real,
+    pub cohesion: Real,
+}
+
+// Keyframes sorted by step, linearly interpolated in between
+#[derive(Clone)]
+pub struct WeightSchedule {
+    keyframes: Vec<WeightKeyframe>,
+}
+
+impl WeightSchedule {
+    pub fn new(mut keyframes: Vec<WeightKeyframe>) -> Self {
+        keyframes.sort_by_key(|keyframe| keyframe.step);
+        WeightSchedule { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[WeightKeyframe] {
+        &self.keyframes
+    }
+
+    // (separation, alignment, cohesion) at `step`, held constant before the first and after the last keyframe
+    pub fn weights_at(&self, step: usize) -> Option<(Real, Real, Real)> {
+        let next = self.keyframes.iter().position(|keyframe| keyframe.step > step);
+        let (from, to) = match next {
+            Some(0) => (self.keyframes[0], self.keyframes[0]),
+            Some(i) => (self.keyframes[i - 1], self.keyframes[i]),
+            None => {
+                let last = *self.keyframes.last()?;
+                (last, last)
+            }
+        };
+
+        let t = if to.step > from.step {
+            (step.saturating_sub(from.step)) as Real / (to.step - from.step) as Real
+        } else {
+            0.0
+        };
+        let lerp = |a: Real, b: Real| a + (b - a) * t;
+        Some((
+            lerp(from.separation, to.separation),
+            lerp(from.alignment, to.alignment),
+            lerp(from.cohesion, to.cohesion),
+        ))
+    }
+}
@@ -0,0 +1,36 @@
+use nalgebra::Vector3;
+
+use crate::real::Real;
+
+// Expanding spherical shell that pushes birds outwards as it passes, fading over its lifetime
+#[derive(Clone)]
+pub struct Shockwave {
+    pub centre: Vector3<Real>,
+    pub speed: Real,   // growth of the radius per unit time
+    pub strength: Real, // outward acceleration on the shell at the start
+    pub width: Real,   // thickness of the shell
+    pub lifetime: Real,
+    pub age: Real,
+}
+
+impl Shockwave {
+    pub fn radius(&self) -> Real {
+        self.speed * self.age
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.age >= self.lifetime
+    }
+
+    // Acceleration the shell applies at `position`, strongest at its middle
+    pub fn force_at(&self, position: Vector3<Real>) -> Vector3<Real> {
+        let offset = position - self.centre;
+        let distance = offset.norm();
+        let from_shell = (distance - self.radius()).abs();
+        if distance == 0.0 || from_shell > self.width {
+            return Vector3::zeros();
+        }
+        let fade = (1.0 - self.age / self.lifetime).max(0.0);
+        offset / distance * self.strength * fade * (1.0 - from_shell / self.width)
+    }
+}
@@ -0,0 +1,1007 @@
+use nalgebra::Vector3;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+use crate::bird::{assign_influence, assign_tags, limit_turn, limit_vec, random_unit_vector, turn_then_speed, Bird};
+use crate::boundary::{boundary_force, contain};
+use crate::controller::{Controller, ParameterDeltas};
+use crate::current::current_at;
+use crate::demo::{Demo, DemoAction};
+use crate::distances::DistanceMatrix;
+use crate::error::BirdflockError;
+use crate::event::StepEvent;
+use crate::flocking::{check_pairwise_sums, flee_force, measured_neighbor_sums, pairwise_neighbor_sums, shaped_neighbor_sums, steer, update_predators};
+use crate::grid::{brute_force_k_nearest, check_grid_forces, SpatialGrid};
+use crate::flash::Flash;
+use crate::gust::Gust;
+use crate::layers::{assign_layers, layer_force, layer_of};
+use crate::load::ThreadLoad;
+use crate::metrics::{flock_metrics, FlockMetrics};
+use crate::obstacle::{obstacle_force, Obstacle};
+use crate::octree::{check_octree_forces, Octree};
+use crate::params::{Integration, SimulationParams, SpawnConfig, StallAction, APPROXIMATION_CELL_FRACTION, DEBUG_CHECKS, LEADER_FRACTION, LEADER_INFLUENCE, GRID_CHECK_TOLERANCE, POSITIONS_COUNT, POSITIONS_EVERY,
+    POSITIONS_FIRST, SHOW_POSITIONS};
+use crate::real::Real;
+use crate::roost::roost_force;
+use crate::scene::set_parameter;
+use crate::schedule::WeightSchedule;
+use crate::shockwave::Shockwave;
+use crate::startle::update_startle;
+use crate::waypoint::WaypointPath;
+
+// Reported to the step callback after every step
+pub struct StepInfo {
+    pub step_index: usize,
+    pub calc_time: f64,
+    pub order_parameter: Real,
+    pub centroid: Vector3<Real>,
+}
+
+pub type StepCallback = Box<dyn FnMut(&StepInfo) -> ControlFlow<()> + Send>;
+
+// The flock, predators and parameters, advanced one step at a time
+pub struct Simulation {
+    pub params: SimulationParams,
+    pub birds: Vec<Bird>,
+    pub predators: Vec<Bird>,
+    pub obstacles: Vec<Obstacle>,
+    pub gusts: Vec<Gust>,
+    pub shockwaves: Vec<Shockwave>,
+    pub flashes: Vec<Flash>,
+    pub step_index: usize,
+    pub time: f64, // simulated time so far, the sum of dt over the steps
+    pub last_calc_time: f64, // seconds spent in the last update
+    pub schedule: Option<WeightSchedule>, // overrides the flocking weights each step
+    pub demo: Option<Demo>,               // scripted stages, applied after the schedule
+    pub waypoints: Option<WaypointPath>,  // replaces the pull towards the origin
+    pub thread_load: Option<ThreadLoad>,  // per-thread work counts, when diagnosing load balance
+    stalled_steps: usize,
+    roosting: bool,
+    layer_count: usize, // layers the birds were last split between
+    removed: Vec<bool>, // which of the birds the last step removed, by their index before it
+    events: Vec<(usize, StepEvent)>, // with the step each happened in, kept until taken
+    rng: StdRng,        // draws made as the simulation runs, outside the per-bird updates
+    step_callback: Option<StepCallback>,
+    controller: Option<Controller>,
+}
+
+impl Simulation {
+    pub fn new(params: SimulationParams, birds: Vec<Bird>, predators: Vec<Bird>) -> Self {
+        Simulation {
+            params,
+            birds,
+            predators,
+            obstacles: Vec::new(),
+            gusts: Vec::new(),
+            shockwaves: Vec::new(),
+            flashes: Vec::new(),
+            step_index: 0,
+            time: 0.0,
+            last_calc_time: 0.0,
+            schedule: None,
+            demo: None,
+            waypoints: None,
+            thread_load: None,
+            stalled_steps: 0,
+            roosting: false,
+            layer_count: 1,
+            removed: Vec::new(),
+            events: Vec::new(),
+            rng: StdRng::seed_from_u64(0),
+            step_callback: None,
+            controller: None,
+        }
+    }
+
+    // As `new`, refusing parameters that `SimulationParams::validate` rejects
+    pub fn try_new(params: SimulationParams, birds: Vec<Bird>, predators: Vec<Bird>) -> Result<Self, BirdflockError> {
+        params.validate()?;
+        Ok(Simulation::new(params, birds, predators))
+    }
+
+    // Seed the draws the simulation makes for itself as it runs, for gusts, flashes and
+    // arriving and reseeded birds, so a run from a fixed seed repeats exactly. Unseeded, they
+    // start from 0
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Called after each step with timing and metrics; returning Break stops the simulation
+    pub fn set_step_callback<F: FnMut(&StepInfo) -> ControlFlow<()> + Send + 'static>(&mut self, callback: F) {
+        self.step_callback = Some(Box::new(callback));
+    }
+
+    // Called before each step, after any weight schedule, with the flock's metrics; the
+    // deltas it returns are applied unless they would leave the parameters invalid
+    pub fn set_controller<F: FnMut(&FlockMetrics, &SimulationParams) -> ParameterDeltas + Send + 'static>(&mut self, controller: F) {
+        self.controller = Some(Box::new(controller));
+    }
+
+    // Indices of the birds within perception range of bird `index`
+    pub fn neighbors_of(&self, index: usize) -> Vec<usize> {
+        let position = self.birds[index].position;
+        let radius = self.perception_radius_of(index);
+        self.birds
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| {
+                let distance = (position - other.position).norm();
+                distance > 0.0 && distance < radius
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Perception radius of bird `index` now, as scaled by its tags and pulsing over time
+    pub fn perception_radius_of(&self, index: usize) -> Real {
+        self.params.perception_radius_at(self.time) * self.params.tag_scales(self.birds[index].tags).perception
+    }
+
+    // Indices of the `k` birds nearest to bird `index`, closest first
+    pub fn k_nearest(&self, index: usize, k: usize) -> Vec<usize> {
+        SpatialGrid::new(&self.birds, self.params.grid_cell_size.max(self.params.max_perception_radius()), self.params.extent).k_nearest(&self.birds, index, k)
+    }
+
+    // Start a gust in a random direction with the configured strength and lifetime
+    pub fn trigger_gust(&mut self) {
+        let gust = Gust::random(&mut self.rng, self.params.gust_strength, self.params.gust_lifetime);
+        self.gusts.push(gust);
+    }
+
+    // Start a shockwave expanding from `centre` with the configured speed, strength and lifetime
+    pub fn trigger_shockwave(&mut self, centre: Vector3<Real>) {
+        self.shockwaves.push(Shockwave {
+            centre,
+            speed: self.params.shockwave_speed,
+            strength: self.params.shockwave_strength,
+            width: self.params.shockwave_width,
+            lifetime: self.params.shockwave_lifetime,
+            age: 0.0,
+        });
+    }
+
+    // Start a flash spreading from `centre` with the configured speed, strength and
+    // lifetime, turning round a random axis. Flashes already under way carry on, and
+    // a bird on more than one front is steered by each
+    pub fn trigger_flash(&mut self, centre: Vector3<Real>) {
+        let params = &self.params;
+        let flash = Flash::random(&mut self.rng, centre, params.flash_speed, params.flash_strength, params.flash_width, params.flash_lifetime);
+        self.flashes.push(flash);
+    }
+
+    // Add `count` birds bursting out from `spawn`, in their share of the layers and
+    // with leaders as often as at the start. Everything sized by the flock, the grid,
+    // distance matrix and point buffers included, is rebuilt from the birds each step
+    // or frame, so the flock can grow freely
+    pub fn add_birds(&mut self, count: usize, spawn: &SpawnConfig) {
+        let first = self.birds.len();
+        let rng = &mut self.rng;
+        self.birds.extend((0..count).map(|_| Bird { tags: spawn.tags, ..Bird::spawned_from(spawn, rng) }));
+        self.settle_birds_from(first);
+    }
+
+    // Add `count` birds drawn from the spawn distributions, spread through the space
+    // like the starting flock rather than arriving at one point
+    pub fn add_spawned_birds(&mut self, count: usize) {
+        let first = self.birds.len();
+        let (positions, velocities) = (self.params.spawn_positions, self.params.spawn_velocities);
+        let rng = &mut self.rng;
+        self.birds.extend((0..count).map(|_| Bird::sampled(&positions, &velocities, rng)));
+        self.settle_birds_from(first);
+    }
+
+    // Leaders, tags and layers for the birds from `first` on, just added
+    fn settle_birds_from(&mut self, first: usize) {
+        assign_influence(&mut self.birds[first..], LEADER_FRACTION, LEADER_INFLUENCE, &mut self.rng);
+        assign_tags(&mut self.birds[first..], &self.params.tag_rules, &mut self.rng);
+        for (i, bird) in self.birds.iter_mut().enumerate().skip(first) {
+            bird.layer = layer_of(i, self.layer_count);
+        }
+    }
+
+    // Remove the birds matching `predicate`, keeping the rest in order, and return how
+    // many went. As with `add_birds`, everything built from the flock is rebuilt from
+    // the remaining birds on the next step, and an empty flock is fine
+    pub fn remove_birds<F: FnMut(&Bird) -> bool>(&mut self, predicate: F) -> usize {
+        self.removed = self.birds.iter().map(predicate).collect();
+        let count = self.removed.iter().filter(|&&removed| removed).count();
+        if count > 0 {
+            retain_unremoved(&mut self.birds, &self.removed);
+        }
+        count
+    }
+
+    // Remove `count` birds chosen at random, or all of them if there are fewer.
+    // `removed_last_step` then says which went
+    pub fn remove_random_birds(&mut self, count: usize) -> usize {
+        let mut removed = vec![false; self.birds.len()];
+        for i in rand::seq::index::sample(&mut self.rng, removed.len(), count.min(removed.len())) {
+            removed[i] = true;
+        }
+        let mut flags = removed.into_iter();
+        self.remove_birds(|_| flags.next().unwrap())
+    }
+
+    // Copy of the state, without the step callback, controller or load counters, to read
+    // on another thread while this one carries on stepping
+    pub fn snapshot(&self) -> Simulation {
+        Simulation {
+            params: self.params.clone(),
+            birds: self.birds.clone(),
+            predators: self.predators.clone(),
+            obstacles: self.obstacles.clone(),
+            gusts: self.gusts.clone(),
+            shockwaves: self.shockwaves.clone(),
+            flashes: self.flashes.clone(),
+            step_index: self.step_index,
+            time: self.time,
+            last_calc_time: self.last_calc_time,
+            schedule: self.schedule.clone(),
+            demo: self.demo.clone(),
+            waypoints: self.waypoints.clone(),
+            thread_load: None,
+            stalled_steps: self.stalled_steps,
+            roosting: self.roosting,
+            layer_count: self.layer_count,
+            removed: self.removed.clone(),
+            events: self.events.clone(),
+            rng: self.rng.clone(),
+            step_callback: None,
+            controller: None,
+        }
+    }
+
+    // Which birds the last step removed, by their index before it, for anything that
+    // keeps its own per-bird history, e.g. with `retain_unremoved`. Empty if none
+    pub fn removed_last_step(&self) -> &[bool] {
+        &self.removed
+    }
+
+    // The events recorded since they were last taken, oldest first
+    pub fn take_events(&mut self) -> Vec<(usize, StepEvent)> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn record(&mut self, event: StepEvent) {
+        self.events.push((self.step_index, event));
+    }
+
+    pub fn step(&mut self) -> ControlFlow<()> {
+        self.removed.clear();
+        if self.params.gust_rate > 0.0 && self.rng.random::<Real>() < self.params.gust_rate {
+            self.trigger_gust();
+        }
+
+        let arrivals = &self.params.arrivals;
+        if arrivals.every > 0 && self.step_index > 0 && self.step_index.is_multiple_of(arrivals.every) {
+            let count = arrivals.count.min(arrivals.max_birds.saturating_sub(self.birds.len()));
+            if count > 0 {
+                let spawn = arrivals.spawn.clone();
+                self.add_birds(count, &spawn);
+                self.record(StepEvent::Arrived { count, birds: self.birds.len() });
+            }
+        }
+
+        if let Some((separation, alignment, cohesion)) = self.schedule.as_ref().and_then(|s| s.weights_at(self.step_index)) {
+            self.params.separation_weight = separation;
+            self.params.alignment_weight = alignment;
+            self.params.cohesion_weight = cohesion;
+        }
+
+        self.run_demo();
+
+        if let Some(controller) = &mut self.controller {
+            let deltas = controller(&flock_metrics(&self.birds), &self.params);
+            let controlled = deltas.applied_to(&self.params);
+            match controlled.validate() {
+                Ok(()) => self.params = controlled,
+                Err(err) => self.record(StepEvent::ControllerRejected(err.to_string())),
+            }
+        }
+
+        // Announce dusk and dawn as the roost's day/night cycle turns over
+        let night = self.params.roost.is_night(self.time);
+        if night != self.roosting {
+            self.roosting = night;
+            self.record(if night { StepEvent::Dusk } else { StepEvent::Dawn });
+        }
+
+        // Re-split the flock whenever the number of layers changes
+        if self.params.layers.count != self.layer_count {
+            self.layer_count = self.params.layers.count;
+            assign_layers(&mut self.birds, self.layer_count);
+            self.record(StepEvent::Layers(self.layer_count));
+        }
+
+        let birds_snapshot = self.birds.clone();
+        // Searched at this step's radius when it pulses, everything else as set
+        let pulsed;
+        let params = if self.params.perception_pulse_amplitude > 0.0 {
+            pulsed = SimulationParams { perception_radius: self.params.perception_radius_at(self.time), ..self.params.clone() };
+            &pulsed
+        } else {
+            &self.params
+        };
+        let night_speed = if night { params.roost.speed } else { 1.0 };
+        let (separation_weight, alignment_weight, cohesion_weight) = params.rule_weights();
+        let predators = &self.predators;
+        let obstacles = &self.obstacles;
+        let gusts = &self.gusts;
+        let shockwaves = &self.shockwaves;
+        let flashes = &self.flashes;
+        let thread_load = self.thread_load.as_ref();
+
+        let calc_start = Instant::now();
+
+        // One force for the whole flock, so it recentres without distorting its shape,
+        // towards the active waypoint when following a path and otherwise the origin
+        let centring = match &mut self.waypoints {
+            Some(path) => {
+                let centroid = flock_metrics(&self.birds).centroid;
+                path.advance(centroid, params.waypoint_threshold);
+                limit_vec((path.target() - centroid) * params.waypoint_attraction, params.max_force)
+            }
+            None if params.centre_attraction > 0.0 => {
+                let centroid = flock_metrics(&self.birds).centroid;
+                limit_vec(-centroid * params.centre_attraction, params.max_force)
+            }
+            None => Vector3::zeros(),
+        };
+
+        // Small flocks can measure every pair once up front rather than search a grid,
+        // for the radius search only as topological neighbors still need sorting
+        let matrix = if params.topological_neighbors == 0 && birds_snapshot.len() <= params.distance_matrix_birds {
+            Some(DistanceMatrix::new(&birds_snapshot))
+        } else {
+            None
+        };
+
+        // The octree takes over from the grid when enabled, without the approximate sums
+        let octree = if params.use_octree && matrix.is_none() { Some(Octree::new(&birds_snapshot)) } else { None };
+        let grid = if params.use_grid && matrix.is_none() && octree.is_none() {
+            Some(SpatialGrid::new(&birds_snapshot, params.grid_cell_size.max(params.max_perception_radius()), params.extent))
+        } else {
+            None
+        };
+
+        // Brute force over each pair once, when nothing quicker is searching for the radius
+        // and every bird sees equally far, so each of a pair sees the other
+        let pairwise = if params.pairwise_forces
+            && params.topological_neighbors == 0
+            && params.separation_shape.is_isotropic()
+            && params.tag_rules.iter().all(|rule| rule.perception == 1.0)
+            && matrix.is_none()
+            && octree.is_none()
+            && grid.is_none()
+        {
+            Some(pairwise_neighbor_sums(&birds_snapshot, params.perception_radius, Some(&params.layers)))
+        } else {
+            None
+        };
+
+        // Finer cells and their totals for the approximate neighbor sums
+        let summarised = match &grid {
+            Some(_) if params.approximation_distance > 0.0 => {
+                let cells = SpatialGrid::new(&birds_snapshot, params.approximation_distance * APPROXIMATION_CELL_FRACTION, params.extent);
+                let summaries = cells.summaries(&birds_snapshot);
+                Some((cells, summaries))
+            }
+            _ => None,
+        };
+
+        // Each bird reads only the snapshot and shared inputs and writes only itself, with
+        // no accumulation across birds beyond the integer load counts, so the step is
+        // bit-identical for any thread count.
+        // Keep it that way: anything summed over the flock belongs outside this loop, and
+        // random draws here are seeded from the step and bird rather than the thread
+        let step_index = self.step_index;
+        let update = |i: usize, bird: &mut Bird| {
+            bird.age = bird.age.saturating_add(1);
+            if bird.anchored {
+                return;
+            }
+            let scales = params.tag_scales(bird.tags);
+            let radius = params.perception_radius * scales.perception;
+            let k = params.topological_neighbors;
+            let shape = &params.separation_shape;
+            let layers = &params.layers;
+            let sums = if k > 0 {
+                // Topological flocking: the k nearest at any distance
+                let nearest = match (&octree, &grid) {
+                    (Some(octree), _) => octree.k_nearest(&birds_snapshot, i, k),
+                    (None, Some(grid)) => grid.k_nearest(&birds_snapshot, i, k),
+                    (None, None) => brute_force_k_nearest(&birds_snapshot, i, k),
+                };
+                shaped_neighbor_sums(bird, nearest.iter().map(|&j| &birds_snapshot[j]), Real::INFINITY, Some(shape), Some(layers))
+            } else if let Some(matrix) = &matrix {
+                measured_neighbor_sums(bird, birds_snapshot.iter().zip(matrix.row(i).iter().copied()), radius, Some(shape), Some(layers))
+            } else if let Some(pairwise) = &pairwise {
+                pairwise[i].clone()
+            } else if let Some(octree) = &octree {
+                let candidates = octree.candidates(bird.position, radius);
+                shaped_neighbor_sums(bird, candidates.iter().map(|&j| &birds_snapshot[j]), radius, Some(shape), Some(layers))
+            } else {
+                match (&grid, &summarised) {
+                    (_, Some((cells, summaries))) => cells.approximate_neighbor_sums(bird, &birds_snapshot, summaries, params),
+                    (Some(grid), None) => {
+                        shaped_neighbor_sums(bird, grid.candidates(bird.position).map(|j| &birds_snapshot[j]), radius, Some(shape), Some(layers))
+                    }
+                    (None, None) => shaped_neighbor_sums(bird, &birds_snapshot, radius, Some(shape), Some(layers)),
+                }
+            };
+
+            if let Some(thread_load) = thread_load {
+                thread_load.record(sums.count);
+            }
+            bird.alone_steps = if sums.count == 0 { bird.alone_steps + 1 } else { 0 };
+
+            // Cohere towards a moving average of the neighbor centre rather than only the
+            // current one, predicted ahead by the cohesion lookahead
+            if let Some(centre) = sums.predicted_centre(params.cohesion_lookahead) {
+                let memory = params.cohesion_memory;
+                bird.remembered_centre = bird.remembered_centre * memory + centre * (1.0 - memory);
+            }
+
+            // Startled birds fly faster and keep further apart
+            bird.startle = update_startle(bird, &sums, predators, obstacles, params);
+            let max_speed = params.max_speed * scales.max_speed * (1.0 + params.startle_speed_boost * bird.startle) * night_speed;
+            let max_force = params.max_force * scales.max_force;
+            let separation_boost = 1.0 + params.startle_separation_boost * bird.startle;
+
+            let (separation, mut alignment, mut cohesion) =
+                sums.steering(bird, bird.remembered_centre, params.cohesion_dead_zone, max_speed, max_force);
+
+            // Align to a jittered neighbor heading, so a well-aligned flock keeps some
+            // motion within it instead of locking into a single velocity
+            if params.alignment_noise > 0.0 && sums.influence > 0.0 {
+                let heading = (sums.alignment / sums.influence).try_normalize(0.0).unwrap_or_else(Vector3::zeros);
+                let jitter = random_unit_vector(&mut bird_rng(step_index, i, 1)) * params.alignment_noise;
+                alignment = steer(heading + jitter, bird.velocity, max_speed, max_force);
+            }
+
+            // An imminent collision takes priority over keeping up with the flock
+            if sums.nearest < params.critical_radius {
+                alignment *= params.critical_suppression;
+                cohesion *= params.critical_suppression;
+            }
+            // Crowded birds drift outwards and lonely ones inwards, towards the comfort density
+            let comfort = if params.comfort_density > 0.0 {
+                params.comfort_weight * sums.comfort(bird, params.comfort_density, max_force)
+            } else {
+                Vector3::zeros()
+            };
+            let flee = flee_force(bird, predators, params);
+            let avoid = obstacle_force(bird, obstacles, params);
+            let contain_force = boundary_force(bird, params);
+            let gust: Vector3<Real> = gusts.iter().map(|gust| gust.force_at(bird.position)).sum();
+            let shock: Vector3<Real> = shockwaves.iter().map(|shockwave| shockwave.force_at(bird.position)).sum();
+            let flash: Vector3<Real> = flashes.iter().map(|flash| flash.force_at(bird.position, bird.velocity)).sum();
+            let roost = if night {
+                params.roost.weight * roost_force(bird, &params.roost, max_speed, max_force)
+            } else {
+                Vector3::zeros()
+            };
+            let layer = if layers.count > 1 {
+                layers.weight * layer_force(bird, layers, max_speed, max_force)
+            } else {
+                Vector3::zeros()
+            };
+
+            // Combine with weights
+            let acceleration =
+                separation_weight * separation_boost * separation +
+                alignment_weight * alignment +
+                cohesion_weight * cohesion +
+                params.flee_weight * flee +
+                params.obstacle_weight * avoid +
+                params.boundary_weight * contain_force +
+                gust +
+                shock +
+                flash +
+                roost +
+                layer +
+                comfort +
+                centring;
+
+            // Exponential smoothing against the last step's acceleration damps jitter
+            bird.acceleration = if params.acceleration_smoothing < 1.0 {
+                bird.acceleration.lerp(&acceleration, params.acceleration_smoothing)
+            } else {
+                acceleration
+            };
+
+            // Gravity against lift from flying forward, so a bird that slows down sinks
+            let lift = params.lift_coefficient * bird.velocity.norm() - params.gravity;
+
+            // Velocity update, drag and limit speed
+            let previous_velocity = bird.velocity;
+            let total = bird.acceleration + Vector3::y() * lift;
+            bird.velocity = match params.integration {
+                Integration::AddThenClamp => bird.velocity + total * params.dt,
+                Integration::TurnThenSpeed => turn_then_speed(bird.velocity, total, params.dt, max_speed),
+            };
+            bird.velocity *= (1.0 - params.drag * params.dt).max(0.0);
+            if bird.velocity.norm() > max_speed {
+                bird.velocity = bird.velocity.normalize() * max_speed;
+            }
+
+            // Turn through an arc, however slow the bird and sharp the steering
+            if params.max_turn_rate < crate::real::consts::PI {
+                bird.velocity = limit_turn(previous_velocity, bird.velocity, params.max_turn_rate * params.dt);
+            }
+
+            // Keep birds moving, preserving heading where there is one
+            let speed = bird.velocity.norm();
+            if speed < params.min_speed {
+                bird.velocity = if speed > 0.0 {
+                    bird.velocity / speed * params.min_speed
+                } else {
+                    random_unit_vector(&mut bird_rng(step_index, i, 0)) * params.min_speed
+                };
+            }
+
+            // Position update, carried along by any ambient current
+            bird.position += (bird.velocity + current_at(bird.position, params)) * params.dt;
+            contain(bird, params);
+        };
+
+        // Fixed contiguous chunks, one task each, as a thread pool splitting the flock by
+        // hand would, or rayon's own adaptive splitting
+        let chunk_size = params.chunk_size;
+        if chunk_size > 0 {
+            self.birds.par_chunks_mut(chunk_size).enumerate().for_each(|(chunk, birds)| {
+                for (offset, bird) in birds.iter_mut().enumerate() {
+                    update(chunk * chunk_size + offset, bird);
+                }
+            });
+        } else {
+            self.birds.par_iter_mut().enumerate().for_each(|(i, bird)| update(i, bird));
+        }
+
+        if SHOW_POSITIONS && self.step_index.is_multiple_of(POSITIONS_EVERY) {
+            let logged = self.birds.iter().enumerate().skip(POSITIONS_FIRST).take(POSITIONS_COUNT);
+            for (i, bird) in logged {
+                println!(
+                    "Step {} bird {}: pos={:?} vel={:?} acc={:?}",
+                    self.step_index, i, bird.position, bird.velocity, bird.acceleration
+                );
+            }
+        }
+
+        update_predators(&mut self.predators, &self.birds, &params.predator, &params.boundary_modes, params.extent, params.dt);
+        for obstacle in &mut self.obstacles {
+            obstacle.update(params.obstacle_gravity, params.extent, params.dt);
+        }
+        for gust in &mut self.gusts {
+            gust.age += params.dt;
+        }
+        self.gusts.retain(|gust| !gust.is_finished());
+        for shockwave in &mut self.shockwaves {
+            shockwave.age += params.dt;
+        }
+        self.shockwaves.retain(|shockwave| !shockwave.is_finished());
+        for flash in &mut self.flashes {
+            flash.age += params.dt;
+        }
+        self.flashes.retain(|flash| !flash.is_finished());
+
+        self.last_calc_time = calc_start.elapsed().as_secs_f64();
+
+        if DEBUG_CHECKS {
+            check_bounds(&self.birds, self.params.extent, self.step_index);
+            if let Some(grid) = &grid {
+                let k = params.topological_neighbors;
+                if k > 0 {
+                    let mismatched = (0..birds_snapshot.len())
+                        .filter(|&i| grid.k_nearest(&birds_snapshot, i, k) != brute_force_k_nearest(&birds_snapshot, i, k))
+                        .count();
+                    if mismatched > 0 {
+                        println!("Step {}: {} birds have different grid and brute-force nearest neighbors", self.step_index, mismatched);
+                    }
+                }
+
+                let difference = check_grid_forces(&birds_snapshot, grid, params);
+                if difference > GRID_CHECK_TOLERANCE {
+                    println!(
+                        "Step {}: grid forces differ from brute force by {:.3e}",
+                        self.step_index, difference
+                    );
+                }
+            }
+            if let Some(pairwise) = &pairwise {
+                let difference = check_pairwise_sums(&birds_snapshot, pairwise, params.perception_radius, Some(&params.layers));
+                if difference > GRID_CHECK_TOLERANCE {
+                    println!(
+                        "Step {}: pairwise sums differ from per-bird sums by {:.3e}",
+                        self.step_index, difference
+                    );
+                }
+            }
+            if let Some(octree) = &octree {
+                let k = params.topological_neighbors;
+                if k > 0 {
+                    let mismatched = (0..birds_snapshot.len())
+                        .filter(|&i| octree.k_nearest(&birds_snapshot, i, k) != brute_force_k_nearest(&birds_snapshot, i, k))
+                        .count();
+                    if mismatched > 0 {
+                        println!("Step {}: {} birds have different octree and brute-force nearest neighbors", self.step_index, mismatched);
+                    }
+                }
+
+                let difference = check_octree_forces(&birds_snapshot, octree, params);
+                if difference > GRID_CHECK_TOLERANCE {
+                    println!(
+                        "Step {}: octree forces differ from brute force by {:.3e}",
+                        self.step_index, difference
+                    );
+                }
+            }
+        }
+
+        self.cull_birds();
+
+        if self.params.stall.action != StallAction::Off {
+            self.watch_for_stall();
+        }
+
+        let step_index = self.step_index;
+        self.step_index += 1;
+        self.time += self.params.dt as f64;
+
+        match &mut self.step_callback {
+            Some(callback) => {
+                let metrics = flock_metrics(&self.birds);
+                callback(&StepInfo {
+                    step_index,
+                    calc_time: self.last_calc_time,
+                    order_parameter: metrics.order_parameter,
+                    centroid: metrics.centroid,
+                })
+            }
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    // Carry out the actions of a demo stage beginning this step
+    fn run_demo(&mut self) {
+        let stage = match self.demo.as_mut().and_then(Demo::advance) {
+            Some(stage) => stage.clone(),
+            None => return,
+        };
+        self.record(StepEvent::DemoStage {
+            name: stage.name.clone(),
+            steps: stage.steps,
+            order_parameter: flock_metrics(&self.birds).order_parameter,
+            birds: self.birds.len(),
+            predators: self.predators.len(),
+        });
+
+        for action in stage.actions {
+            match action {
+                DemoAction::Weights(separation, alignment, cohesion) => {
+                    self.params.separation_weight = separation;
+                    self.params.alignment_weight = alignment;
+                    self.params.cohesion_weight = cohesion;
+                }
+                DemoAction::Set(name, value) => {
+                    // Set at the base size, like the scene's own parameters
+                    let mut params = self.params.scaled(1.0);
+                    set_parameter(&mut params, name, &value).unwrap(); // checked when parsed
+                    let params = params.scaled(self.params.world_scale);
+                    match params.validate() {
+                        Ok(()) => self.params = params,
+                        Err(err) => self.record(StepEvent::DemoSetSkipped { name, value, reason: err.to_string() }),
+                    }
+                }
+                DemoAction::Shockwave(centre) => {
+                    let centre = centre.map_or_else(|| flock_metrics(&self.birds).centroid, |centre| centre * self.params.world_scale);
+                    self.trigger_shockwave(centre);
+                }
+                DemoAction::Flash(centre) => {
+                    let centre = centre.map_or_else(|| flock_metrics(&self.birds).centroid, |centre| centre * self.params.world_scale);
+                    self.trigger_flash(centre);
+                }
+                DemoAction::Gust => self.trigger_gust(),
+                DemoAction::Predators(count) => {
+                    self.predators.truncate(count);
+                    while self.predators.len() < count {
+                        self.predators.push(Bird::new_scaled(self.params.world_scale, &mut self.rng));
+                    }
+                }
+                DemoAction::Birds(count) => {
+                    let spawn = self.params.arrivals.spawn.clone();
+                    self.add_birds(count, &spawn);
+                }
+            }
+        }
+    }
+
+    // Remove prey caught by a predator and birds that have been alone for too long
+    fn cull_birds(&mut self) {
+        let catch_radius = self.params.predator.catch_radius;
+        let straggler_steps = self.params.straggler_steps;
+        if catch_radius <= 0.0 && straggler_steps == 0 {
+            return;
+        }
+        let predators: Vec<Vector3<Real>> = self.predators.iter().map(|predator| predator.position).collect();
+        let (mut caught, mut stragglers) = (0, 0);
+        let removed = self.remove_birds(|bird| {
+            if bird.anchored {
+                false
+            } else if catch_radius > 0.0 && predators.iter().any(|&predator| (bird.position - predator).norm() < catch_radius) {
+                caught += 1;
+                true
+            } else if straggler_steps > 0 && bird.alone_steps as usize >= straggler_steps {
+                stragglers += 1;
+                true
+            } else {
+                false
+            }
+        });
+        if removed > 0 {
+            self.record(StepEvent::Culled { caught, stragglers, left: self.birds.len() });
+        }
+    }
+
+    // Count steps with a disordered, near-still flock and act once it has stalled for long enough
+    fn watch_for_stall(&mut self) {
+        let stall = &self.params.stall;
+        let metrics = flock_metrics(&self.birds);
+        if metrics.order_parameter >= stall.order_threshold || metrics.mean_speed >= stall.speed_threshold {
+            self.stalled_steps = 0;
+            return;
+        }
+
+        self.stalled_steps += 1;
+        if self.stalled_steps < stall.steps {
+            return;
+        }
+        self.stalled_steps = 0;
+
+        let reseed = stall.action == StallAction::Reseed;
+        self.record(StepEvent::Stalled { steps: stall.steps, order_parameter: metrics.order_parameter, mean_speed: metrics.mean_speed });
+        if reseed {
+            for bird in self.birds.iter_mut().filter(|bird| !bird.anchored) {
+                *bird = Bird {
+                    influence: bird.influence,
+                    layer: bird.layer,
+                    age: bird.age,
+                    tags: bird.tags,
+                    ..Bird::sampled(&self.params.spawn_positions, &self.params.spawn_velocities, &mut self.rng)
+                };
+            }
+            self.record(StepEvent::Reseeded(self.birds.len()));
+        }
+    }
+}
+
+// Random draws for bird `i` in step `step_index`, the same whichever thread updates it.
+// Each use of them in the step takes its own `stream`, 0 or 1, so their draws are independent
+fn bird_rng(step_index: usize, i: usize, stream: u64) -> StdRng {
+    StdRng::seed_from_u64((((step_index as u64) << 32) ^ i as u64) ^ (stream << 63))
+}
+
+// Drop the entries of `items` flagged in `removed`, as `Simulation::remove_birds` does
+// for the birds. Entries beyond the end of `removed` are kept
+pub fn retain_unremoved<T>(items: &mut Vec<T>, removed: &[bool]) {
+    let mut flags = removed.iter();
+    items.retain(|_| !flags.next().copied().unwrap_or(false));
+}
+
+// Debug check: report birds that have escaped the simulation space
+fn check_bounds(birds: &[Bird], extent: Real, step: usize) {
+    let mut outside = 0;
+    let mut worst_index = 0;
+    let mut worst_distance: Real = 0.0;
+
+    for (i, bird) in birds.iter().enumerate() {
+        let mut distance: Real = 0.0;
+        for axis in 0..3 {
+            let p = bird.position[axis];
+            distance = distance.max(-extent - p).max(p - extent);
+        }
+        if distance > 0.0 {
+            outside += 1;
+            if distance > worst_distance {
+                worst_distance = distance;
+                worst_index = i;
+            }
+        }
+    }
+
+    if outside > 0 {
+        println!(
+            "Step {}: {} birds outside [{}, {}] (worst: bird {} at {:.3} outside)",
+            step, outside, -extent, extent, worst_index, worst_distance
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{determinism_run, run_on, same_bits, DETERMINISM_THREADS, GOLDEN_BIRDS, GOLDEN_SEED};
+    use crate::params::{ArrivalParams, Distribution, StallParams};
+
+    #[test]
+    fn results_do_not_depend_on_the_thread_count() -> Result<(), BirdflockError> {
+        let reference = run_on(DETERMINISM_THREADS[0], || determinism_run(1.0, 0))?;
+        for &threads in &DETERMINISM_THREADS[1..] {
+            let birds = run_on(threads, || determinism_run(1.0, 0))?;
+            let differing = reference.iter().zip(&birds).filter(|(a, b)| !same_bits(a, b)).count();
+            assert_eq!(differing, 0, "{} birds on {} threads differ from {} thread", differing, threads, DETERMINISM_THREADS[0]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_chunks_match_rayons_splitting() -> Result<(), BirdflockError> {
+        // Not a divisor of the flock, so the last chunk is short
+        let chunk_size = 37;
+        let threads = DETERMINISM_THREADS[DETERMINISM_THREADS.len() - 1];
+        let reference = run_on(threads, || determinism_run(1.0, 0))?;
+        let chunked = run_on(threads, || determinism_run(1.0, chunk_size))?;
+        let differing = reference.iter().zip(&chunked).filter(|(a, b)| !same_bits(a, b)).count();
+        assert_eq!(differing, 0, "{} birds differ in chunks of {} from rayon's splitting", differing, chunk_size);
+        Ok(())
+    }
+
+    // Final birds of a fixed-seed run that adds and removes birds as it goes: arrivals,
+    // a flock that counts as stalled and is reseeded, and birds added and removed at
+    // random between steps. Flashes are triggered at random like the gusts
+    fn seeded_run(seed: u64, params: SimulationParams) -> Vec<Bird> {
+        let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+        let birds = (0..GOLDEN_BIRDS).map(|_| Bird::new(&mut rng)).collect();
+        let params = SimulationParams {
+            arrivals: ArrivalParams { every: 10, ..params.arrivals.clone() },
+            stall: StallParams { action: StallAction::Reseed, order_threshold: Real::INFINITY, speed_threshold: Real::INFINITY, steps: 30 },
+            ..params
+        };
+        let mut simulation = Simulation::new(params, birds, Vec::new());
+        simulation.set_seed(seed);
+        for step in 0..50 {
+            let _ = simulation.step();
+            if step % 20 == 0 {
+                simulation.add_spawned_birds(30);
+                simulation.remove_random_birds(40);
+            }
+            if simulation.rng.random::<Real>() < 0.1 {
+                simulation.trigger_flash(Vector3::zeros());
+            }
+        }
+        simulation.birds
+    }
+
+    #[test]
+    fn gusts_and_flashes_are_reproducible_from_the_seed() {
+        let params = SimulationParams { gust_rate: 0.1, ..SimulationParams::default() };
+        let reference = seeded_run(GOLDEN_SEED, params.clone());
+        let repeat = seeded_run(GOLDEN_SEED, params);
+        let differing = reference.iter().zip(&repeat).filter(|(a, b)| !same_bits(a, b)).count();
+        assert_eq!(differing, 0, "{} birds differ between gusty runs from the same seed", differing);
+    }
+
+    #[test]
+    fn birds_added_mid_run_are_reproducible_from_the_seed() {
+        let reference = seeded_run(GOLDEN_SEED, SimulationParams::default());
+        let repeat = seeded_run(GOLDEN_SEED, SimulationParams::default());
+        assert_eq!(reference.len(), repeat.len());
+        let differing = reference.iter().zip(&repeat).filter(|(a, b)| !same_bits(a, b)).count();
+        assert_eq!(differing, 0, "{} birds differ between runs from the same seed", differing);
+
+        let reseeded = seeded_run(GOLDEN_SEED + 1, SimulationParams::default());
+        assert!(reference.iter().zip(&reseeded).any(|(a, b)| !same_bits(a, b)), "another seed made the same run");
+    }
+
+    #[test]
+    fn arrivals_and_stalls_are_recorded_until_taken() {
+        let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+        let birds = (0..GOLDEN_BIRDS).map(|_| Bird::new(&mut rng)).collect();
+        let defaults = SimulationParams::default();
+        let params = SimulationParams {
+            arrivals: ArrivalParams { every: 10, count: 5, max_birds: usize::MAX, ..defaults.arrivals.clone() },
+            stall: StallParams { action: StallAction::Reseed, order_threshold: Real::INFINITY, speed_threshold: Real::INFINITY, steps: 15 },
+            ..defaults
+        };
+        let mut simulation = Simulation::new(params, birds, Vec::new());
+        for _ in 0..16 {
+            let _ = simulation.step();
+        }
+
+        let events = simulation.take_events();
+        let flock = GOLDEN_BIRDS + 5;
+        assert_eq!(events.len(), 3, "{:?}", events);
+        assert_eq!(events[0], (10, StepEvent::Arrived { count: 5, birds: flock }));
+        assert!(matches!(events[1], (14, StepEvent::Stalled { steps: 15, .. })), "{:?}", events[1]);
+        assert_eq!(events[2], (14, StepEvent::Reseeded(flock)));
+        assert!(simulation.take_events().is_empty());
+    }
+
+    // Birds set down at rest where the forces on them cancel or there are none, so every
+    // path that normalizes a velocity or a distance meets a zero. Each arrangement is run
+    // under both integrations, a turn limit, alignment noise, pairwise sums and a flash
+    // centred on it, with no gravity or speed floor to get the birds moving
+    fn assert_finite_from_rest(positions: &[[Real; 3]]) {
+        let still = Distribution::Uniform { extent: 0.0 };
+        let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+        let birds: Vec<Bird> = positions.iter().map(|&position| Bird::at_position(Vector3::from(position), &still, &mut rng)).collect();
+
+        let base = SimulationParams { gravity: 0.0, min_speed: 0.0, ..SimulationParams::default() };
+        let variants = [
+            ("the defaults", base.clone()),
+            ("turn then speed", SimulationParams { integration: Integration::TurnThenSpeed, ..base.clone() }),
+            ("a turn limit", SimulationParams { max_turn_rate: 0.5, ..base.clone() }),
+            ("alignment noise", SimulationParams { alignment_noise: 0.2, ..base.clone() }),
+            ("pairwise sums", SimulationParams { use_grid: false, pairwise_forces: true, ..base.clone() }),
+        ];
+        for (name, params) in variants {
+            let mut simulation = Simulation::new(params, birds.clone(), Vec::new());
+            simulation.trigger_flash(birds[0].position);
+            for _ in 0..10 {
+                let _ = simulation.step();
+            }
+            for bird in &simulation.birds {
+                let components = bird.position.iter().chain(bird.velocity.iter());
+                assert!(components.copied().all(Real::is_finite), "with {}: {:?} moving {:?}", name, bird.position, bird.velocity);
+            }
+        }
+    }
+
+    #[test]
+    fn coasting_birds_drift_no_more_than_rounding_allows() {
+        // With every force off each bird coasts in a straight line, and where it ends up,
+        // wrapped into the box, can be worked out directly in f64 however this was built
+        const STEPS: usize = 20000;
+        let params = SimulationParams { separation_weight: 0.0, alignment_weight: 0.0, cohesion_weight: 0.0, ..SimulationParams::default() };
+        let mut rng = StdRng::seed_from_u64(GOLDEN_SEED);
+        let birds: Vec<Bird> = (0..GOLDEN_BIRDS)
+            .map(|_| {
+                // Below max speed, so the speed limit leaves the velocity exactly as it is
+                let mut bird = Bird::new(&mut rng);
+                bird.velocity = limit_vec(bird.velocity, params.max_speed * 0.9);
+                bird
+            })
+            .collect();
+        // The most that rounding could add up to if every step rounded the same way
+        let tolerance = STEPS as f64 * Real::EPSILON as f64 * params.extent as f64;
+        let (extent, travel) = (params.extent as f64, STEPS as f64 * params.dt as f64);
+        let mut simulation = Simulation::new(params, birds.clone(), Vec::new());
+        for _ in 0..STEPS {
+            let _ = simulation.step();
+        }
+
+        for (start, bird) in birds.iter().zip(&simulation.birds) {
+            for axis in 0..3 {
+                let expected = start.position[axis] as f64 + start.velocity[axis] as f64 * travel;
+                // Nearest way round the box, so a bird just across a face from where it should be is close
+                let error = (bird.position[axis] as f64 - expected + extent).rem_euclid(2.0 * extent) - extent;
+                assert!(error.abs() <= tolerance, "a bird drifted {:e} off course, more than {:e}", error.abs(), tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn a_lone_bird_at_rest_stays_finite() {
+        assert_finite_from_rest(&[[0.0; 3]]);
+    }
+
+    #[test]
+    fn two_birds_at_one_point_stay_finite() {
+        assert_finite_from_rest(&[[0.5, 0.5, 0.5]; 2]);
+    }
+
+    #[test]
+    fn a_bird_between_mirrored_neighbors_stays_finite() {
+        assert_finite_from_rest(&[[-0.3, 0.0, 0.0], [0.0; 3], [0.3, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn a_bird_in_a_cube_of_eight_stays_finite() {
+        let mut positions = vec![[0.0; 3]];
+        positions.extend((0..8).map(|corner| [0, 1, 2].map(|axis| if corner >> axis & 1 == 1 { 0.3 } else { -0.3 })));
+        assert_finite_from_rest(&positions);
+    }
+}
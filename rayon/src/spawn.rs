@@ -0,0 +1,51 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::error::BirdflockError;
+use crate::params::SPAWN_EXTENT;
+use crate::real::Real;
+
+// Grayscale image whose pixel intensity is the probability of spawning there
+pub struct SpawnImage {
+    pub width: u32,
+    pub height: u32,
+    pub intensity: Vec<Real>,
+    pub max_intensity: Real,
+}
+
+impl SpawnImage {
+    pub fn load(path: &str) -> Result<Self, BirdflockError> {
+        let image = image::open(path)
+            .map_err(|err| match err {
+                image::ImageError::IoError(error) => BirdflockError::io(path, error),
+                err => BirdflockError::Parse(format!("cannot load spawn image '{}': {}", path, err)),
+            })?
+            .into_luma8();
+        let (width, height) = image.dimensions();
+        let intensity: Vec<Real> = image.pixels().map(|pixel| pixel[0] as Real / 255.0).collect();
+        let max_intensity = intensity.iter().cloned().fold(0.0, Real::max);
+        if max_intensity == 0.0 {
+            return Err(BirdflockError::Parse(format!("spawn image '{}' is completely black", path)));
+        }
+        Ok(SpawnImage { width, height, intensity, max_intensity })
+    }
+
+    // Rejection-sample a pixel by intensity, jitter within it, and extrude along z.
+    // The longer image side spans the spawn area so the aspect ratio is kept, and
+    // the jitter spreads birds evenly whether there are more pixels or more birds.
+    pub fn sample_position<R: Rng>(&self, rng: &mut R) -> Vector3<Real> {
+        let scale = 2.0 * SPAWN_EXTENT / self.width.max(self.height) as Real;
+        loop {
+            let x = rng.random_range(0..self.width);
+            let y = rng.random_range(0..self.height);
+            let intensity = self.intensity[(y * self.width + x) as usize];
+            if rng.random::<Real>() * self.max_intensity < intensity {
+                return Vector3::new(
+                    (x as Real + rng.random::<Real>() - self.width as Real / 2.0) * scale,
+                    (self.height as Real / 2.0 - y as Real - rng.random::<Real>()) * scale,
+                    rng.random_range(-SPAWN_EXTENT..SPAWN_EXTENT)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::bird::Bird;
+use crate::flocking::NeighborSums;
+use crate::obstacle::Obstacle;
+use crate::params::SimulationParams;
+use crate::real::Real;
+
+// Next startle level of a bird, from 0 (calm) to 1 (panicking). A predator or moving
+// obstacle within the startle radius panics it fully, and it catches a fraction of its
+// most startled neighbor's panic, so panic spreads as a wave that weakens with each
+// bird it passes through until it falls below the contagion threshold
+pub fn update_startle(bird: &Bird, sums: &NeighborSums, predators: &[Bird], obstacles: &[Obstacle], params: &SimulationParams) -> Real {
+    let radius = params.startle_radius;
+    if radius <= 0.0 {
+        return 0.0;
+    }
+    let threatened = predators.iter().any(|predator| (predator.position - bird.position).norm() < radius)
+        || obstacles
+            .iter()
+            .any(|obstacle| obstacle.moving && (obstacle.position - bird.position).norm() < obstacle.radius + radius);
+    if threatened {
+        return 1.0;
+    }
+
+    let mut startle = bird.startle * (1.0 - params.startle_decay * params.dt).max(0.0);
+    let caught = sums.startle * params.startle_spread;
+    if caught > params.startle_contagion {
+        startle = startle.max(caught);
+    }
+    startle
+}
@@ -0,0 +1,67 @@
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::error::BirdflockError;
+use crate::simulation::Simulation;
+
+// Bit set in a frame's flags when the velocities follow the positions
+pub const STREAM_VELOCITIES: u32 = 1;
+
+// Bird positions sent to a listening client over TCP after every step, so an external
+// renderer or display can follow the flock from another process or machine. Each
+// frame, all little-endian, is
+//
+//     step: u64, count: u32, flags: u32,
+//     count x (x, y, z) positions as f32,
+//     count x (x, y, z) velocities as f32, if flags has STREAM_VELOCITIES
+//
+// Writes block, so a client that stops reading holds the simulation back rather than
+// missing frames. One that disconnects ends the stream and the simulation carries on
+pub struct PositionStream {
+    address: String,
+    stream: Option<TcpStream>,
+    velocities: bool,
+    frame: Vec<u8>, // reused between frames
+}
+
+impl PositionStream {
+    pub fn connect(address: &str, velocities: bool) -> Result<Self, BirdflockError> {
+        let stream = TcpStream::connect(address).map_err(|err| BirdflockError::io(address, err))?;
+        // Frames go out as soon as they're written rather than waiting to fill a packet
+        stream.set_nodelay(true).map_err(|err| BirdflockError::io(address, err))?;
+        Ok(PositionStream { address: address.to_string(), stream: Some(stream), velocities, frame: Vec::new() })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Send the birds as they are after the simulation's last step
+    pub fn send(&mut self, simulation: &Simulation) {
+        let Some(stream) = &mut self.stream else { return };
+
+        let birds = &simulation.birds;
+        let flags = if self.velocities { STREAM_VELOCITIES } else { 0 };
+        self.frame.clear();
+        self.frame.extend_from_slice(&(simulation.step_index as u64).to_le_bytes());
+        self.frame.extend_from_slice(&(birds.len() as u32).to_le_bytes());
+        self.frame.extend_from_slice(&flags.to_le_bytes());
+        for bird in birds {
+            for value in bird.position.iter() {
+                self.frame.extend_from_slice(&(*value as f32).to_le_bytes());
+            }
+        }
+        if self.velocities {
+            for bird in birds {
+                for value in bird.velocity.iter() {
+                    self.frame.extend_from_slice(&(*value as f32).to_le_bytes());
+                }
+            }
+        }
+
+        if let Err(err) = stream.write_all(&self.frame) {
+            println!("Stream to {} closed ({}), carrying on without it", self.address, err);
+            self.stream = None;
+        }
+    }
+}
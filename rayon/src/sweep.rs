@@ -0,0 +1,241 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::fmt::Write;
+
+use crate::bird::Bird;
+use crate::error::BirdflockError;
+use crate::metrics::flock_metrics;
+use crate::params::SimulationParams;
+use crate::real::Real;
+use crate::scene::{get_parameter, set_parameter, PARAMETERS};
+use crate::simulation::Simulation;
+
+// Defaults for a sweep file's settings
+pub const SWEEP_BIRDS:           usize = 300;
+pub const SWEEP_STEPS:           usize = 500;
+pub const SWEEP_SEED:            u64 = 1;    // every run starts from the same flock, unless its spawn distributions differ
+pub const SWEEP_ORDER_THRESHOLD: Real = 0.8; // order parameter counted as ordered for time_to_order
+
+// Headless runs over every combination of parameter values, from a file of
+// `key = value` lines like a scene's:
+//
+//     # separation, alignment and cohesion weights can be swept as well
+//     alignment_weight = 0, 1, 2, 4
+//     perception_radius = 1.0..3.0 step 0.5
+//     boundary_modes = wrap wrap wrap, bounce bounce bounce
+//     birds = 500
+//     steps = 1000
+//
+// A parameter takes a comma-separated list of values or an inclusive `start..end step size`
+// range, and one with a single value is simply set for every run. `birds`, `steps`, `seed`,
+// `order_threshold` and `output`, the CSV path, configure the sweep itself
+pub struct Sweep {
+    pub axes: Vec<(String, Vec<String>)>, // each parameter with its values, in file order
+    pub birds: usize,
+    pub steps: usize,
+    pub seed: u64,
+    pub order_threshold: Real,
+    pub output: Option<String>,
+}
+
+// One run's parameter values, as in the sweep's axes, and its metrics
+pub struct SweepResult {
+    pub values: Vec<String>,
+    pub order_parameter: Real,        // at the end of the run
+    pub mean_speed: Real,             // at the end of the run
+    pub time_to_order: Option<usize>, // first step the order parameter reached the threshold
+}
+
+// The flocking weights aren't scene parameters, since scenes set them per flock
+const WEIGHTS: [&str; 3] = ["separation_weight", "alignment_weight", "cohesion_weight"];
+
+fn set_sweep_parameter(params: &mut SimulationParams, name: &str, value: &str) -> Result<(), String> {
+    let weight = match name {
+        "separation_weight" => &mut params.separation_weight,
+        "alignment_weight" => &mut params.alignment_weight,
+        "cohesion_weight" => &mut params.cohesion_weight,
+        _ => return set_parameter(params, name, value),
+    };
+    *weight = value.parse().map_err(|_| format!("invalid value '{}' for {}", value, name))?;
+    Ok(())
+}
+
+fn get_sweep_parameter(params: &SimulationParams, name: &str) -> Option<String> {
+    match name {
+        "separation_weight" => Some(params.separation_weight.to_string()),
+        "alignment_weight" => Some(params.alignment_weight.to_string()),
+        "cohesion_weight" => Some(params.cohesion_weight.to_string()),
+        _ => get_parameter(params, name),
+    }
+}
+
+// `start..end step size`, including `end` when the steps land on it
+fn parse_range(value: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("invalid range '{}', expected `start..end step size`", value);
+    let (range, step) = value.split_once("step").ok_or_else(invalid)?;
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let parse = |text: &str| text.trim().parse::<Real>().map_err(|_| invalid());
+    let (start, end, step) = (parse(start)?, parse(end)?, parse(step)?);
+    if step.is_nan() || step <= 0.0 || end < start {
+        return Err(invalid());
+    }
+    let count = ((end - start) / step + 1e-4).floor() as usize + 1;
+    Ok((0..count).map(|i| (start + i as Real * step).to_string()).collect())
+}
+
+impl Sweep {
+    pub fn load(path: &str) -> Result<Self, BirdflockError> {
+        let text = std::fs::read_to_string(path).map_err(|error| BirdflockError::io(path, error))?;
+        Sweep::parse(&text).map_err(|err| BirdflockError::Parse(format!("sweep '{}': {}", path, err)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sweep = Sweep {
+            axes: Vec::new(),
+            birds: SWEEP_BIRDS,
+            steps: SWEEP_STEPS,
+            seed: SWEEP_SEED,
+            order_threshold: SWEEP_ORDER_THRESHOLD,
+            output: None,
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |err: String| format!("line {}: {}", number + 1, err);
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected `key = value`".to_string()))?;
+            let (key, value) = (key.trim(), value.trim());
+            let setting = |value: &str| format!("invalid value '{}' for {}", value, key);
+            match key {
+                "birds" => sweep.birds = value.parse().map_err(|_| error(setting(value)))?,
+                "steps" => sweep.steps = value.parse().map_err(|_| error(setting(value)))?,
+                "seed" => sweep.seed = value.parse().map_err(|_| error(setting(value)))?,
+                "order_threshold" => sweep.order_threshold = value.parse().map_err(|_| error(setting(value)))?,
+                "output" => sweep.output = Some(value.to_string()),
+                key if PARAMETERS.contains(&key) || WEIGHTS.contains(&key) => {
+                    if sweep.axes.iter().any(|(name, _)| name == key) {
+                        return Err(error(format!("'{}' is swept twice", key)));
+                    }
+                    let values = if value.contains("..") {
+                        parse_range(value).map_err(error)?
+                    } else {
+                        value.split(',').map(|value| value.trim().to_string()).collect()
+                    };
+                    // Check each value now and keep it in its canonical form
+                    let mut params = SimulationParams::default();
+                    let values = values
+                        .iter()
+                        .map(|value| {
+                            set_sweep_parameter(&mut params, key, value)?;
+                            Ok(get_sweep_parameter(&params, key).unwrap())
+                        })
+                        .collect::<Result<Vec<String>, String>>()
+                        .map_err(error)?;
+                    sweep.axes.push((key.to_string(), values));
+                }
+                key => return Err(error(format!("unknown key '{}'", key))),
+            }
+        }
+        Ok(sweep)
+    }
+
+    pub fn run_count(&self) -> usize {
+        self.axes.iter().map(|(_, values)| values.len()).product()
+    }
+
+    // Parameter values of run `index`, the last axis varying fastest
+    fn run_values(&self, index: usize) -> Vec<String> {
+        let mut stride = self.run_count();
+        self.axes
+            .iter()
+            .map(|(_, values)| {
+                stride /= values.len();
+                values[index / stride % values.len()].clone()
+            })
+            .collect()
+    }
+
+    fn run_params(&self, values: &[String]) -> SimulationParams {
+        let mut params = SimulationParams::default();
+        for ((name, _), value) in self.axes.iter().zip(values) {
+            set_sweep_parameter(&mut params, name, value).unwrap(); // checked when parsed
+        }
+        params
+    }
+
+    // Every run, in parallel as they're independent. Each is deterministic for any
+    // thread count, its random gusts and arrivals included as they're drawn from the
+    // sweep's seed, so the results don't depend on how the runs are scheduled
+    pub fn run(&self) -> Result<Vec<SweepResult>, BirdflockError> {
+        let runs: Vec<Vec<String>> = (0..self.run_count()).map(|index| self.run_values(index)).collect();
+        for values in &runs {
+            if let Err(BirdflockError::InvalidParams(message)) = self.run_params(values).validate() {
+                let run: Vec<String> = self.axes.iter().zip(values).map(|((name, _), value)| format!("{} = {}", name, value)).collect();
+                return Err(BirdflockError::InvalidParams(format!("{} in the run with {}", message, run.join(", "))));
+            }
+        }
+
+        Ok(runs
+            .into_par_iter()
+            .map(|values| {
+                let params = self.run_params(&values);
+                let mut rng = StdRng::seed_from_u64(self.seed);
+                let birds: Vec<Bird> = (0..self.birds).map(|_| Bird::sampled(&params.spawn_positions, &params.spawn_velocities, &mut rng)).collect();
+                let mut simulation = Simulation::new(params, birds, Vec::new());
+                simulation.set_seed(self.seed);
+                let mut time_to_order = None;
+                for step in 0..self.steps {
+                    if simulation.step().is_break() {
+                        break;
+                    }
+                    if time_to_order.is_none() && flock_metrics(&simulation.birds).order_parameter >= self.order_threshold {
+                        time_to_order = Some(step + 1);
+                    }
+                }
+                let metrics = flock_metrics(&simulation.birds);
+                SweepResult {
+                    values,
+                    order_parameter: metrics.order_parameter,
+                    mean_speed: metrics.mean_speed,
+                    time_to_order,
+                }
+            })
+            .collect())
+    }
+
+    // One row per run: the swept values then the metrics, time_to_order left empty if never reached
+    pub fn to_csv(&self, results: &[SweepResult]) -> String {
+        let mut csv = String::new();
+        for (name, _) in &self.axes {
+            write!(csv, "{},", name).unwrap();
+        }
+        writeln!(csv, "order_parameter,mean_speed,time_to_order").unwrap();
+        for result in results {
+            for value in &result.values {
+                write!(csv, "{},", value).unwrap();
+            }
+            let time_to_order = result.time_to_order.map(|step| step.to_string()).unwrap_or_default();
+            writeln!(csv, "{},{},{}", result.order_parameter, result.mean_speed, time_to_order).unwrap();
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{run_on, DETERMINISM_THREADS};
+
+    #[test]
+    fn sweeps_with_random_events_repeat_exactly() -> Result<(), BirdflockError> {
+        let sweep = Sweep::parse("gust_rate = 0, 0.2\narrival_every = 0, 15\nbirds = 100\nsteps = 60").unwrap();
+        let csv = |threads| run_on(threads, || sweep.run().map(|results| sweep.to_csv(&results)));
+        let reference = csv(DETERMINISM_THREADS[0])??;
+        for &threads in &DETERMINISM_THREADS {
+            assert_eq!(csv(threads)??, reference, "the sweep on {} threads gave different results", threads);
+        }
+        Ok(())
+    }
+}
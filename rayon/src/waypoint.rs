@@ -0,0 +1,36 @@
+use nalgebra::Vector3;
+
+use crate::real::Real;
+
+// Looping path of points the flock is drawn along, e.g. to trace a figure-eight.
+// The flock heads for one waypoint at a time, moving on once its centroid is close
+#[derive(Clone)]
+pub struct WaypointPath {
+    waypoints: Vec<Vector3<Real>>,
+    active: usize,
+}
+
+impl WaypointPath {
+    pub fn new(waypoints: Vec<Vector3<Real>>) -> Self {
+        WaypointPath { waypoints, active: 0 }
+    }
+
+    pub fn waypoints(&self) -> &[Vector3<Real>] {
+        &self.waypoints
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    pub fn target(&self) -> Vector3<Real> {
+        self.waypoints[self.active]
+    }
+
+    // Move on to the next waypoint, wrapping round to the first, once `centroid` is within `threshold`
+    pub fn advance(&mut self, centroid: Vector3<Real>, threshold: Real) {
+        if (self.target() - centroid).norm() < threshold {
+            self.active = (self.active + 1) % self.waypoints.len();
+        }
+    }
+}